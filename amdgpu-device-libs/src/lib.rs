@@ -97,7 +97,7 @@
 //! ```
 #![deny(missing_docs)]
 #![allow(internal_features)]
-#![feature(core_intrinsics, gpu_intrinsics, stdarch_amdgpu)]
+#![feature(core_intrinsics, gpu_intrinsics, link_llvm_intrinsics, stdarch_amdgpu)]
 #![no_std]
 
 /// Re-exported for use in print macros
@@ -190,6 +190,7 @@ mod intrinsics {
 /// Contains `print!`, `println!`, intrinsics to get workitem and workgroup id among others.
 pub mod prelude {
     pub use crate::dispatch_ptr;
+    pub use crate::dynamic_shared_memory;
     pub use crate::intrinsics::*;
     #[cfg(feature = "print")]
     pub use print;
@@ -223,6 +224,12 @@ unsafe extern "C" {
     safe fn __amdgpu_util_implicitarg_ptr() -> *const ffi::c_void;
 }
 
+unsafe extern "C" {
+    /// The size of statically allocated shared memory (LDS) in the kernel.
+    #[link_name = "llvm.amdgcn.groupstaticsize"]
+    safe fn groupstaticsize() -> u32;
+}
+
 /// Handle to an HSA signal.
 #[cfg(feature = "device_libs")]
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
@@ -378,6 +385,42 @@ pub fn dispatch_ptr() -> &'static HsaKernelDispatchPacket {
     }
 }
 
+/// Get the dynamically sized shared memory of this workgroup.
+///
+/// Shared memory (LDS) is visible to all threads of a workgroup.
+/// The size of the dynamic part is set when launching the kernel, it is read from the
+/// `group_segment_size` of the [dispatch packet](dispatch_ptr), minus the statically allocated
+/// shared memory of the kernel.
+///
+/// The memory is uninitialized at the start of the kernel.
+/// It is shared between all threads of a workgroup, so accesses from different threads need to be
+/// synchronized, e.g. with `s_barrier`.
+///
+/// # Example
+///
+/// ```rust
+/// # #![no_std]
+/// # fn main() {
+/// use amdgpu_device_libs::prelude::*;
+///
+/// let scratch = dynamic_shared_memory::<u32>();
+/// let id = workitem_id_x() as usize;
+/// if id < scratch.len() {
+///     unsafe { (scratch as *mut u32).add(id).write(id as u32) };
+/// }
+/// s_barrier();
+/// # }
+/// ```
+#[inline]
+pub fn dynamic_shared_memory<T>() -> *mut [T] {
+    let ptr = core::intrinsics::gpu::gpu_launch_sized_workgroup_mem::<T>();
+    // Dynamic shared memory starts after the static part, aligned for T
+    let start = (groupstaticsize() as usize).next_multiple_of(core::mem::align_of::<T>());
+    let bytes = (dispatch_ptr().group_segment_size as usize).saturating_sub(start);
+    let len = bytes.checked_div(core::mem::size_of::<T>()).unwrap_or(0);
+    core::ptr::slice_from_raw_parts_mut(ptr, len)
+}
+
 /// Call a function on the host.
 ///
 /// This allows calling functions on the CPU from the GPU.
@@ -1,7 +1,6 @@
 //! Similar to the vector_add example, but copies a larger vector and has more of a focus on performance.
 //! Mostly to show off/test performance features.
 #![cfg_attr(feature = "gpu", no_std, feature(abi_gpu_kernel))]
-#![cfg_attr(not(feature = "gpu"), feature(clone_from_ref))]
 
 use gpu_kernel::kernel;
 
@@ -11,10 +10,10 @@ gpu_kernel::kernel_lib!();
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[kernel]
 pub fn kernel(a: &[u32], b: &[u32], c: *mut u32) {
-    use gpu_kernel::intrinsics::{workgroup_id_x, workitem_id_x};
-
-    // Compute own, global id
-    let id = workitem_id_x() as usize + 32 * workgroup_id_x() as usize;
+    // Compute own, global id and skip threads beyond the end of the vectors
+    let Some(id) = gpu_kernel::intrinsics::element_index(a.len()) else {
+        return;
+    };
 
     // Add multiple input numbers and store into output
     let mut sum: u32 = 0;
@@ -32,34 +31,37 @@ pub fn kernel(a: &[u32], b: &[u32], c: *mut u32) {
 
 #[cfg(not(feature = "gpu"))]
 fn main() {
-    use gpu_kernel::{GpuAlloc, GpuBox, LaunchConfig};
+    use gpu_kernel::{DeviceBuffer, LaunchConfig};
 
     // Create two vectors a and b to add together
     let mut a = Vec::new();
     let mut b = Vec::new();
-    // Use a multiple of 32, so that the kernel does not need to handle boundaries
     for i in 0..3_200_000 {
         a.push(i);
         b.push(i);
     }
 
-    // Copy vectors to GPU
-    let a = GpuBox::clone_from_ref_in(a.as_slice(), GpuAlloc);
-    let b = GpuBox::clone_from_ref_in(b.as_slice(), GpuAlloc);
+    // Copy vectors to GPU memory
+    let a = DeviceBuffer::from_slice(&a);
+    let b = DeviceBuffer::from_slice(&b);
 
     // Create vector c to hold the results.
     // Create it on the CPU as that seems to be faster than on the GPU
     let mut c_gpu = Box::new_uninit_slice(a.len());
     // Creating it on the GPU would look like this:
-    // let mut c_gpu = GpuBox::new_uninit_slice_in(a.len(), GpuAlloc);
+    // let mut c_gpu = gpu_kernel::GpuBox::new_uninit_slice_in(a.len(), gpu_kernel::GpuAlloc);
+
+    // Launch one thread per element, in workgroups of 32 threads
+    let launch_config = LaunchConfig::for_elements(a.len(), 32);
+    kernel.launch(&launch_config, &a, &b, c_gpu.as_mut_ptr() as *mut _);
 
-    kernel.launch(
-        LaunchConfig::new()
-            .threads_per_workgroup([32, 1, 1])
-            .workgroups([(a.len() / 32) as u32, 1, 1]),
-        &a,
-        &b,
-        c_gpu.as_mut_ptr() as *mut _,
+    // Measure how long the kernel takes on the GPU
+    let times = kernel
+        .bench(&launch_config, 10, &a, &b, c_gpu.as_mut_ptr() as *mut _)
+        .expect("Failed to benchmark kernel");
+    println!(
+        "Kernel time: min {:?}, median {:?}, max {:?}",
+        times.min, times.median, times.max
     );
 
     let c_gpu = unsafe { c_gpu.assume_init() };
@@ -47,7 +47,18 @@
           ty: path:
           let
             # Do not use craneLib.cleanCargoSource, otherwise it does not find util32.bc
-            src = if ty != cpuTy then ./. else craneLib.cleanCargoSource ./.;
+            src =
+              if ty != cpuTy then
+                ./.
+              else
+                # Keep binaries that tests read with include_bytes!
+                lib.cleanSourceWith {
+                  src = ./.;
+                  filter =
+                    path: type:
+                    (craneLib.filterCargoSources path type) || (lib.hasInfix "/tests/data" path);
+                  name = "source";
+                };
             cargoLock = ./${path}/Cargo.lock;
           in
           {
@@ -90,6 +101,7 @@
         cpu_pkgs = [
           "amdgpu-device-libs-build"
           "gpu-kernel"
+          "gpu-kernel-bundle"
           "gpu-kernel-proc-macros"
           "examples-raw/default-cpu"
           "examples-raw/hostcall-cpu"
@@ -137,6 +149,25 @@
           )
         ) package_args;
 
+        # Test the CPU backend and the mock runtime, which run without a GPU
+        test_features = {
+          cpu = "--no-default-features --features cpu";
+          mock = "--features mock";
+          mock-without-hip = "--no-default-features --features mock";
+        };
+        packages_test = lib.mapAttrs' (
+          name: features:
+          lib.nameValuePair "gpu-kernel-test-${name}" (
+            craneLib.cargoTest (
+              package_args."gpu-kernel"
+              // {
+                cargoArtifacts = craneLib.buildDepsOnly package_args."gpu-kernel";
+                cargoTestExtraArgs = features;
+              }
+            )
+          )
+        ) test_features;
+
         # Run all examples (except panic)
         runExamples =
           let
@@ -190,7 +221,8 @@
         }
         // packages
         // packages_rustfmt
-        // packages_clippy;
+        // packages_clippy
+        // packages_test;
       }
     );
 }
@@ -0,0 +1,3 @@
+# {{crate}} [![docs.rs](https://docs.rs/gpu-kernel-bundle/badge.svg)](https://docs.rs/gpu-kernel-bundle)
+
+{{readme}}
@@ -0,0 +1,74 @@
+//! The parts of the clang offload bundle format that both `gpu-kernel` and its proc macros need.
+//!
+//! See `gpu_kernel::OffloadBundle` for reading and creating bundles.
+#![deny(missing_docs)]
+
+/// The magic bytes at the start of an uncompressed offload bundle.
+pub const MAGIC: &[u8] = b"__CLANG_OFFLOAD_BUNDLE__";
+/// Code objects are aligned like in bundles created by clang.
+const ALIGNMENT: usize = 4096;
+
+/// Write an uncompressed clang offload bundle.
+///
+/// `entries` contains the id and content of each entry, e.g.
+/// `hipv4-amdgcn-amd-amdhsa--gfx90a` and a code object.
+pub fn write_offload_bundle(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let header_size = MAGIC.len() + 8 + entries.iter().map(|(id, _)| 24 + id.len()).sum::<usize>();
+
+    let mut bundle = Vec::new();
+    bundle.extend_from_slice(MAGIC);
+    bundle.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    let mut offsets = Vec::new();
+    let mut offset = header_size;
+    for (id, data) in entries {
+        // Empty entries, like the host entry, are not aligned
+        if !data.is_empty() {
+            offset = offset.next_multiple_of(ALIGNMENT);
+        }
+        offsets.push(offset);
+        bundle.extend_from_slice(&(offset as u64).to_le_bytes());
+        bundle.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        bundle.extend_from_slice(&(id.len() as u64).to_le_bytes());
+        bundle.extend_from_slice(id.as_bytes());
+        offset += data.len();
+    }
+    for (offset, (_, data)) in offsets.into_iter().zip(entries) {
+        bundle.resize(offset, 0);
+        bundle.extend_from_slice(data);
+    }
+    bundle
+}
+
+/// The 64-bit FNV-1a hash.
+///
+/// Identifies the content of compressed bundles and checks that a precompiled module is the one
+/// the executable was built against.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn write() {
+        // A bundle with an empty entry between two code objects
+        let expected = include_bytes!("../tests/data/bundle.hipfb");
+        let bundle = write_offload_bundle(&[
+            ("hipv4-amdgcn-amd-amdhsa--gfx90a", b"gfx90a code object"),
+            ("hipv4-amdgcn-amd-amdhsa--gfx1030", b""),
+            ("hipv4-amdgcn-amd-amdhsa--gfx1100", b"gfx1100 code object"),
+        ]);
+        assert_eq!(bundle, expected);
+    }
+}
@@ -8,10 +8,10 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::{env, fs};
 
-use quote::{format_ident, quote};
+use quote::{format_ident, quote, quote_spanned};
 use syn::{
-    FnArg, GenericArgument, GenericParam, Generics, Ident, ItemFn, Lifetime, Pat, PathArguments,
-    Safety, Type, parse_macro_input,
+    FnArg, GenericArgument, GenericParam, Generics, Ident, ItemFn, ItemStatic, Lifetime, Pat,
+    PathArguments, Safety, StaticMutability, Type, parse_macro_input,
 };
 use toml::Table;
 use toml::map::Entry;
@@ -36,6 +36,29 @@ fn to_explicit_lifetimes(
     Some(lifetime)
 }
 
+/// Format a type as it is usually written, e.g. `&[u32]` instead of `& [u32]`.
+fn type_to_string(ty: &Type) -> String {
+    let mut s = quote!(#ty).to_string();
+    for (from, to) in [
+        ("& ", "&"),
+        ("* ", "*"),
+        (" <", "<"),
+        ("< ", "<"),
+        (" >", ">"),
+        (" ,", ","),
+        (" ::", "::"),
+        (":: ", "::"),
+        ("[ ", "["),
+        (" ]", "]"),
+        ("( ", "("),
+        (" )", ")"),
+        (" ;", ";"),
+    ] {
+        s = s.replace(from, to);
+    }
+    s
+}
+
 /// Replace all anonymous lifetimes '_ with named ones.
 /// Used as impl trait does not allow anonymous lifetimes.
 fn type_with_explicit_lifetimes(
@@ -79,6 +102,8 @@ fn type_with_explicit_lifetimes(
 ///
 /// On the CPU side a `.launch` function is generated, taking a `&LaunchConfig`
 /// argument as the first argument, followed by all function arguments.
+/// `.launch` panics if launching the kernel fails.
+/// A `.try_launch` function with the same arguments returns a `Result<(), GpuError>` instead.
 ///
 /// Mutable references are generally forbidden as arguments as the same
 /// arguments are passed to many, parallel executions of the function.
@@ -112,6 +137,44 @@ fn type_with_explicit_lifetimes(
 /// - If `T` is safe, the same goes for `&Box<[T>]>` → `&[T]`, `&Arc<T>` → `T`, `&GpuBox<T>` and `&GpuBox<[T]>` (see also the documentation for `GpuBox`)
 /// - `ThreadIndexedSlice` can be used to pass a mutable reference to a list where each thread gets access to an element at its thread index (`&mut Vec<T>` → `ThreadIndexedSlice<T>` where `T` is safe)
 ///
+/// # Benchmarking
+///
+/// `.bench` takes the launch config, a number of iterations and the kernel arguments.
+/// It launches the kernel repeatedly and returns the minimum, median and maximum time the kernel
+/// took on the GPU, measured with GPU events.
+///
+/// # Devices
+///
+/// Kernels are loaded lazily on each device they are launched on.
+/// The device is chosen by the `LaunchConfig`: its `device`, the device of its `stream` or the
+/// current device of the thread, in that order.
+/// Use `.get(device)` to get the loaded `Kernel` for a device.
+///
+/// # Asynchronous Launches
+///
+/// `.launch` blocks until the kernel finished.
+/// `.launch_async` takes the same arguments but returns a `LaunchHandle` right after queuing the
+/// kernel, so the CPU can do other work or queue more kernels in the meantime.
+/// The handle borrows all arguments until it is waited on or dropped.
+/// It implements `Future`, so it can be `.await`ed in async code without blocking a thread.
+/// As leaking the handle would end the borrow while the kernel still runs, `.launch_async` is `unsafe`.
+///
+/// # CPU Backend
+///
+/// With the `cpu` feature of `gpu-kernel`, kernels are compiled for the CPU instead of the GPU.
+/// Launches run every workgroup on a group of CPU threads, one per GPU thread, so barriers work
+/// like on a GPU.
+/// The intrinsics in `gpu_kernel::intrinsics` are emulated and `println!` prints to stdout.
+/// If the kernel panics, the launch returns `GpuError::KernelPanicked`.
+/// `.launch_async` runs the kernel before returning.
+///
+/// # Registry
+///
+/// Every kernel is registered with its name, argument types and source location.
+/// `gpu_kernel::kernels()` lists the kernels of all crates in the executable.
+/// The generated kernel statics implement `KernelFn`, so kernels with different arguments can be
+/// stored as `&dyn KernelFn` and launched with packed arguments.
+///
 /// [ROCm unified memory docs]: https://rocm.docs.amd.com/projects/HIP/en/latest/how-to/hip_runtime_api/memory_management/unified_memory.html
 #[proc_macro_attribute]
 pub fn kernel(
@@ -155,8 +218,14 @@ pub fn kernel(
     // Save them in extra variables as we are unable to re-query alignment after the value is moved.
     let mut input_alignment_names = Vec::new();
     let mut input_size_names = Vec::new();
+    // Names and types of the arguments as written in the source for the kernel descriptor
+    let mut arg_descriptors = Vec::new();
 
     let mut extra_lifetimes = Vec::new();
+    // Argument types for `launch_async`, these need to outlive the returned handle
+    let mut async_input_tys = Vec::new();
+    let mut async_bounds = Vec::new();
+    let mut async_lifetimes = Vec::new();
 
     for (i, arg) in inputs.iter().enumerate() {
         let mut name = format_ident!("_gpu_kernel_arg{i}");
@@ -177,6 +246,17 @@ pub fn kernel(
                 if let Pat::Ident(ident) = &*arg.pat {
                     name = ident.ident.clone();
                 }
+                let arg_name = match &*arg.pat {
+                    Pat::Ident(_) => name.to_string(),
+                    pat => quote!(#pat).to_string(),
+                };
+                let arg_ty = type_to_string(&arg.ty);
+                arg_descriptors.push(quote! {
+                    ::gpu_kernel::KernelArgDescriptor {
+                        name: #arg_name,
+                        ty: #arg_ty,
+                    }
+                });
                 if let Type::Reference(r) = &*arg.ty {
                     assert!(
                         r.mutability.is_none(),
@@ -187,12 +267,17 @@ pub fn kernel(
                         "#[kernel] `{orig_ident}` arg `{name}` cannot be of `impl Trait` type"
                     );
                 }
+                let async_ty =
+                    type_with_explicit_lifetimes(&arg.ty, &name, 0, &mut async_lifetimes);
                 if is_unsafe {
                     let ty = &arg.ty;
                     input_tys.push(quote! { #ty });
+                    async_input_tys.push(quote! { #async_ty });
+                    async_bounds.push(quote! { #async_ty: 'gpu_kernel_launch });
                 } else {
                     let ty = type_with_explicit_lifetimes(&arg.ty, &name, 0, &mut extra_lifetimes);
                     input_tys.push(quote! { impl ::gpu_kernel::SafeKernelArg<Output = #ty> });
+                    async_input_tys.push(quote! { impl ::gpu_kernel::SafeKernelArg<Output = #async_ty> + 'gpu_kernel_launch });
                 }
             }
         }
@@ -217,6 +302,15 @@ pub fn kernel(
         quote! { <#(#extra_lifetimes),*> }
     };
 
+    let async_generics = {
+        let params = &generics.params;
+        quote! { <'gpu_kernel_launch, #(#async_lifetimes,)* #params> }
+    };
+    let async_where_clause = {
+        let predicates = where_clause.as_ref().map(|w| &w.predicates);
+        quote! { where #(#async_bounds,)* #predicates }
+    };
+
     let require_safe = if is_unsafe {
         quote!()
     } else {
@@ -235,6 +329,25 @@ pub fn kernel(
         quote! { #[allow(improper_ctypes_definitions, improper_gpu_kernel_arg)] }
     };
 
+    // Describe where each argument is packed, to check it against the compiled kernel
+    let arg_count = input_names.len();
+    let layout = quote! {
+        #[allow(unused_mut, unused_variables, clippy::size_of_ref)]
+        let _gpu_kernel_layout: [::gpu_kernel::KernelArgLayout; #arg_count] = {
+            let mut _gpu_kernel_offset: usize = 0;
+            [#({
+                _gpu_kernel_offset = _gpu_kernel_offset.next_multiple_of(std::mem::align_of_val(&#input_names));
+                let _gpu_kernel_arg = ::gpu_kernel::KernelArgLayout {
+                    name: std::stringify!(#input_names),
+                    offset: _gpu_kernel_offset,
+                    size: std::mem::size_of_val(&#input_names),
+                };
+                _gpu_kernel_offset += _gpu_kernel_arg.size;
+                _gpu_kernel_arg
+            }),*]
+        };
+    };
+
     // Assemble arguments on the CPU
     let args;
     let drop;
@@ -297,6 +410,106 @@ pub fn kernel(
         };
     }
 
+    let host_kernel;
+    let bench_body;
+    let launch_body;
+    let launch_async_body;
+    let cpu_allow;
+    if cfg!(feature = "cpu") {
+        // Compile the kernel for the CPU and run it on CPU threads, every thread gets a copy of the
+        // arguments like on the GPU
+        host_kernel = quote! {
+            #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+            #(#attrs)*
+            #safety fn #kernel_ident #generics(#inputs) #where_clause #output {
+                #[allow(unused_imports)]
+                use ::gpu_kernel::prelude::*;
+                #code
+            }
+        };
+        let call = if is_unsafe {
+            quote! { unsafe { #kernel_ident(#(#input_names),*); } }
+        } else {
+            quote! { #kernel_ident(#(#input_names),*); }
+        };
+        bench_body = quote! {
+            #require_safe
+            unsafe {
+                ::gpu_kernel::cpu::bench(gpu_kernel_launch_config, self.name(), gpu_kernel_iterations, (#(#input_names,)*), |(#(#input_names,)*)| { #call })
+            }
+        };
+        launch_body = quote! {
+            // SAFETY: The handle is not leaked, we wait for it right away
+            unsafe {
+                self.launch_async(gpu_kernel_launch_config, #(#input_names),*)
+            }?.wait()
+        };
+        launch_async_body = quote! {
+            #require_safe
+            unsafe {
+                ::gpu_kernel::cpu::launch_async(gpu_kernel_launch_config, self.name(), (#(#input_names,)*), |(#(#input_names,)*)| { #call })
+            }
+        };
+        cpu_allow = quote! { #[allow(unused_mut)] };
+    } else {
+        host_kernel = quote! {};
+        bench_body = quote! {
+            #require_safe
+            #layout
+            #args
+            // Launch kernel
+            let _gpu_kernel_result = unsafe {
+                self.bench_impl(gpu_kernel_launch_config, gpu_kernel_iterations, ::gpu_kernel::ArgLayout::Declared(&_gpu_kernel_layout), _gpu_kernel_args)
+            };
+            #drop
+            _gpu_kernel_result
+        };
+        launch_body = quote! {
+            #require_safe
+            #layout
+            #args
+            // Launch kernel and wait for it
+            let _gpu_kernel_result = unsafe {
+                self.launch_impl(gpu_kernel_launch_config, ::gpu_kernel::ArgLayout::Declared(&_gpu_kernel_layout), _gpu_kernel_args)
+            };
+            #drop
+            _gpu_kernel_result
+        };
+        launch_async_body = quote! {
+            #require_safe
+            #layout
+            #args
+            // Launch kernel
+            let _gpu_kernel_result = unsafe {
+                self.launch_async_impl(gpu_kernel_launch_config, ::gpu_kernel::ArgLayout::Declared(&_gpu_kernel_layout), _gpu_kernel_args)
+            };
+            #drop
+            _gpu_kernel_result
+        };
+        cpu_allow = quote! {};
+    }
+
+    let try_launch_call = if is_unsafe {
+        quote! { unsafe { self.try_launch(gpu_kernel_launch_config, #(#input_names),*) } }
+    } else {
+        quote! { self.try_launch(gpu_kernel_launch_config, #(#input_names),*) }
+    };
+    let async_safety_doc = if is_unsafe {
+        quote! {
+            /// The arguments must be valid for the kernel and the returned handle must not be
+            /// leaked, e.g. with `std::mem::forget`.
+        }
+    } else {
+        quote! {
+            /// The returned handle must not be leaked, e.g. with `std::mem::forget`.
+        }
+    };
+
+    // Register the kernel, pointing to the function name in the source
+    let descriptor_ident = format_ident!("_gpu_kernel_descriptor_{}", orig_ident);
+    let line = quote_spanned! {orig_ident.span()=> std::line!() };
+    let column = quote_spanned! {orig_ident.span()=> std::column!() };
+
     let output = quote! {
         // GPU code
 
@@ -314,36 +527,87 @@ pub fn kernel(
 
         // CPU code
 
+        #host_kernel
+
         #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
         #[allow(non_camel_case_types)]
-        #vis struct #kernel_struct_ident(::gpu_kernel::Kernel);
+        #vis struct #kernel_struct_ident(::gpu_kernel::LazyKernel);
 
         #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
         #[allow(non_upper_case_globals)]
         #(#attrs)*
-        #vis static #orig_ident: std::sync::LazyLock<#kernel_struct_ident> = std::sync::LazyLock::new(|| {
-            #kernel_struct_ident(crate::KERNEL_LIB_CALLED_IN_CRATE.get_kernel(std::stringify!(#kernel_ident)))
-        });
+        #vis static #orig_ident: #kernel_struct_ident = #kernel_struct_ident(
+            ::gpu_kernel::LazyKernel::new(&crate::KERNEL_LIB_CALLED_IN_CRATE, std::stringify!(#kernel_ident))
+        );
 
         #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
         impl std::ops::Deref for #kernel_struct_ident {
-            type Target = ::gpu_kernel::Kernel;
+            type Target = ::gpu_kernel::LazyKernel;
 
             fn deref(&self) -> &Self::Target {
                 &self.0
             }
         }
 
+        #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+        #[allow(non_upper_case_globals)]
+        #[::gpu_kernel::linkme::distributed_slice(::gpu_kernel::KERNELS)]
+        #[linkme(crate = ::gpu_kernel::linkme)]
+        static #descriptor_ident: ::gpu_kernel::KernelDescriptor = ::gpu_kernel::KernelDescriptor {
+            name: std::stringify!(#orig_ident),
+            module_path: std::module_path!(),
+            args: &[#(#arg_descriptors),*],
+            is_unsafe: #is_unsafe,
+            file: std::file!(),
+            line: #line,
+            column: #column,
+            kernel: &#orig_ident,
+        };
+
+        #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+        impl ::gpu_kernel::KernelFn for #kernel_struct_ident {
+            fn descriptor(&self) -> &'static ::gpu_kernel::KernelDescriptor {
+                &#descriptor_ident
+            }
+
+            fn lazy_kernel(&self) -> &::gpu_kernel::LazyKernel {
+                &self.0
+            }
+        }
+
         #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
         impl #kernel_struct_ident {
-            #vis #safety fn launch #cpu_generics(&self, gpu_kernel_launch_config: &::gpu_kernel::LaunchConfig, #(mut #input_names: #input_tys),*) #where_clause {
-                #require_safe
-                #args
-                // Launch kernel
-                unsafe {
-                    self.launch_impl(gpu_kernel_launch_config, _gpu_kernel_args);
+            #vis #safety fn launch #cpu_generics(&self, gpu_kernel_launch_config: &::gpu_kernel::LaunchConfig, #(#input_names: #input_tys),*) #where_clause {
+                let _gpu_kernel_result = #try_launch_call;
+                if let Err(e) = _gpu_kernel_result {
+                    panic!("{e}");
                 }
-                #drop
+            }
+
+            #cpu_allow
+            #vis #safety fn try_launch #cpu_generics(&self, gpu_kernel_launch_config: &::gpu_kernel::LaunchConfig, #(mut #input_names: #input_tys),*) -> std::result::Result<(), ::gpu_kernel::GpuError> #where_clause {
+                #launch_body
+            }
+
+            /// Launch the kernel `iterations` times and measure how long it runs on the GPU.
+            ///
+            /// The kernel is launched once more before measuring to warm up.
+            #cpu_allow
+            #vis #safety fn bench #cpu_generics(&self, gpu_kernel_launch_config: &::gpu_kernel::LaunchConfig, gpu_kernel_iterations: u32, #(mut #input_names: #input_tys),*) -> std::result::Result<::gpu_kernel::BenchResult, ::gpu_kernel::GpuError> #where_clause {
+                #bench_body
+            }
+
+            /// Launch the kernel without waiting for it to finish.
+            ///
+            /// The returned handle borrows all arguments until the kernel finished.
+            ///
+            /// # Safety
+            ///
+            /// Dropping the handle waits for the kernel to finish.
+            #async_safety_doc
+            #cpu_allow
+            #vis unsafe fn launch_async #async_generics(&self, gpu_kernel_launch_config: &::gpu_kernel::LaunchConfig, #(mut #input_names: #async_input_tys),*) -> std::result::Result<::gpu_kernel::LaunchHandle<'gpu_kernel_launch>, ::gpu_kernel::GpuError> #async_where_clause {
+                #launch_async_body
             }
         }
     };
@@ -351,6 +615,177 @@ pub fn kernel(
     proc_macro::TokenStream::from(output)
 }
 
+/// Declare a static as a GPU global variable that can be accessed from the CPU.
+///
+/// The static is compiled for the GPU and can be used in kernels like any other static.
+/// On the CPU side, the static is a handle to the variable on the GPU, with these functions:
+///
+/// - `.read()` copies the current value from the GPU, it is `unsafe` for `static mut`
+/// - `.write(&value)` copies a new value to the GPU, it is always `unsafe` as the static needs
+///   to be mutable on the GPU (a `static mut` or interior mutability like atomics)
+/// - `.as_ptr()` gets the address of the variable on the GPU, e.g. to pass it to kernels
+///
+/// The type needs to be `Copy` and have the same layout on the CPU and the GPU.
+///
+/// # Devices
+///
+/// Every device has its own copy of the variable.
+/// All functions access the variable on the current device of the thread.
+///
+/// With the `cpu` feature, kernels run on the CPU and share the variable with the CPU side.
+/// The handle has the same `read`, `write` and `as_ptr` functions, which access the variable
+/// directly.
+/// Kernels use the handle too, it dereferences to the value of immutable statics, a `static mut`
+/// is accessed through `as_ptr`.
+///
+/// # Example
+///
+/// ```ignore
+/// #[gpu_kernel::global]
+/// static mut COUNTER: u32 = 0;
+///
+/// #[kernel]
+/// fn count() {
+///     unsafe { core::intrinsics::atomic_xadd::<_, { AtomicOrdering::Relaxed }>(&raw mut COUNTER, 1) };
+/// }
+///
+/// // On the CPU
+/// unsafe { COUNTER.write(&0)? };
+/// count.launch(&LaunchConfig::for_elements(100, 10));
+/// assert_eq!(unsafe { COUNTER.read()? }, 100);
+/// ```
+#[proc_macro_attribute]
+pub fn global(
+    _attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let item = parse_macro_input!(input as ItemStatic);
+    let attrs = item.attrs;
+    let vis = item.vis;
+    let mutability = item.mutability;
+    let is_mut = matches!(mutability, StaticMutability::Mut(_));
+    let orig_ident = item.ident;
+    let ty = item.ty;
+    let expr = item.expr;
+    let global_name = format!("{orig_ident}_gpu_global");
+    let global_struct_ident = format_ident!("GpuGlobal_{}", orig_ident);
+
+    let read = if is_mut {
+        quote! {
+            /// Copy the current value of the variable from the GPU.
+            ///
+            /// # Safety
+            ///
+            /// No kernel may write the variable while it is read.
+            #vis unsafe fn read(&self) -> std::result::Result<#ty, ::gpu_kernel::GpuError> {
+                unsafe { self.0.read_impl() }
+            }
+        }
+    } else {
+        quote! {
+            /// Copy the current value of the variable from the GPU.
+            #vis fn read(&self) -> std::result::Result<#ty, ::gpu_kernel::GpuError> {
+                // SAFETY: Immutable statics are only changed by `write`, which is unsafe
+                unsafe { self.0.read_impl() }
+            }
+        }
+    };
+    let write = quote! {
+        /// Copy a new value for the variable to the GPU.
+        ///
+        /// # Safety
+        ///
+        /// The variable must be mutable on the GPU, i.e. a `static mut` or a type with interior
+        /// mutability, and no kernel may access the variable while it is written.
+        #vis unsafe fn write(&self, value: &#ty) -> std::result::Result<(), ::gpu_kernel::GpuError> {
+            unsafe { self.0.write_impl(value) }
+        }
+    };
+
+    if cfg!(feature = "cpu") {
+        // Kernels run on the CPU and share the variable with the CPU side, so the handle holds
+        // the value itself
+        let access = if is_mut {
+            quote! {
+                // SAFETY: Like for a `static mut`, all accesses are unsafe
+                unsafe impl Sync for #global_struct_ident {}
+            }
+        } else {
+            quote! {
+                impl std::ops::Deref for #global_struct_ident {
+                    type Target = #ty;
+
+                    fn deref(&self) -> &Self::Target {
+                        // SAFETY: Immutable statics are only changed by `write`, which is unsafe
+                        unsafe { self.0.get() }
+                    }
+                }
+            }
+        };
+        let output = quote! {
+            #[allow(non_camel_case_types)]
+            #vis struct #global_struct_ident(::gpu_kernel::cpu::Global<#ty>);
+
+            #(#attrs)*
+            #vis static #orig_ident: #global_struct_ident =
+                #global_struct_ident(::gpu_kernel::cpu::Global::new(#expr));
+
+            #access
+
+            impl #global_struct_ident {
+                #read
+
+                #write
+
+                /// Get the address of the variable, kernels running on the CPU access a
+                /// `static mut` through it.
+                #vis fn as_ptr(&self) -> std::result::Result<*mut #ty, ::gpu_kernel::GpuError> {
+                    self.0.as_ptr()
+                }
+            }
+        };
+        return proc_macro::TokenStream::from(output);
+    }
+
+    let output = quote! {
+        // GPU code
+
+        #[cfg(any(target_arch = "amdgpu", target_arch = "nvptx64"))]
+        #(#attrs)*
+        #[unsafe(export_name = #global_name)]
+        #vis static #mutability #orig_ident: #ty = #expr;
+
+        // CPU code
+
+        #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+        #[allow(non_camel_case_types)]
+        #vis struct #global_struct_ident(::gpu_kernel::LazyGlobal<#ty>);
+
+        #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+        #(#attrs)*
+        #vis static #orig_ident: #global_struct_ident = #global_struct_ident(
+            ::gpu_kernel::LazyGlobal::new(&crate::KERNEL_LIB_CALLED_IN_CRATE, #global_name)
+        );
+
+        #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+        impl std::ops::Deref for #global_struct_ident {
+            type Target = ::gpu_kernel::LazyGlobal<#ty>;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+        impl #global_struct_ident {
+            #read
+
+            #write
+        }
+    };
+    proc_macro::TokenStream::from(output)
+}
+
 /// The `kernel_lib!()` macro, compiling the crate in debug mode.
 ///
 /// See `kernel_lib!()` for documentation.
@@ -533,19 +968,74 @@ fn create_cargo_toml(
     }
 }
 
+/// Declare a module that is loaded at runtime from a precompiled file.
+///
+/// The file is the output of a normal build, copied to `precompiled_dir`.
+/// It is not embedded, only its checksum, to detect if a different file is shipped.
+fn precompiled_module(precompiled_dir: &Path, crate_name: &str) -> proc_macro::TokenStream {
+    #[cfg(not(feature = "nvidia"))]
+    let file_names = [format!("{crate_name}.hipfb"), format!("{crate_name}.elf")];
+    #[cfg(feature = "nvidia")]
+    let file_names = [format!("{crate_name}.ptx")];
+    let file_name = file_names
+        .iter()
+        .find(|name| precompiled_dir.join(name).exists())
+        .unwrap_or_else(|| {
+            panic!(
+                "Did not find {} in $GPU_KERNEL_PRECOMPILED ({})",
+                file_names.join(" or "),
+                precompiled_dir.display()
+            )
+        });
+    let path = precompiled_dir.join(file_name);
+    let data = fs::read(&path).unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()));
+    let checksum = gpu_kernel_bundle::fnv1a(&data);
+
+    let path = path.display().to_string();
+    let output = quote! {
+        // Dummy include to re-run the macro if the file changed, it is not used at runtime.
+        // Use proc_macro tracked path and env once it is stable.
+        const _: &[u8] = std::include_bytes!(#path);
+        const _: std::option::Option<&str> = std::option_env!("GPU_KERNEL_PRECOMPILED");
+
+        // If someone forgets to call kernel_lib!() and defines a kernel, they will see
+        // an error that this is not found.
+        // Use the name to hint the user what is missing.
+        #[doc(hidden)]
+        static KERNEL_LIB_CALLED_IN_CRATE: ::gpu_kernel::LazyModule = ::gpu_kernel::LazyModule::from_file(#file_name, #checksum);
+    };
+    proc_macro::TokenStream::from(output)
+}
+
 fn kernel_lib_impl(_: proc_macro::TokenStream, debug: bool) -> proc_macro::TokenStream {
+    // Kernels are compiled for the CPU together with the rest of the crate.
+    // Without a GPU backend, e.g. with only the `mock` runtime, there is no GPU code to compile.
+    if cfg!(feature = "cpu") || !cfg!(any(feature = "amd", feature = "nvidia")) {
+        let output = quote! {
+            #[doc(hidden)]
+            static KERNEL_LIB_CALLED_IN_CRATE: ::gpu_kernel::LazyModule = ::gpu_kernel::LazyModule::new(&[]);
+        };
+        return proc_macro::TokenStream::from(output);
+    }
+
     #[cfg(feature = "amd")]
     let target = "amdgcn-amd-amdhsa";
-    #[cfg(not(feature = "amd"))]
+    #[cfg(feature = "nvidia")]
+    let target = "nvptx64-nvidia-cuda";
+    #[cfg(not(any(feature = "amd", feature = "nvidia")))]
     let target = "";
 
     let target_env = target.replace('-', "_").to_uppercase();
     let target_rustflags = format!("CARGO_TARGET_{target_env}_RUSTFLAGS");
     let target_cargoflags = format!("CARGO_TARGET_{target_env}_FLAGS");
+    let target_cpus_var = format!("CARGO_TARGET_{target_env}_CPUS");
 
     // Compile gpu crate here
     let crate_name = env::var("CARGO_CRATE_NAME").expect("$CARGO_CRATE_NAME must be set");
+    #[cfg(not(feature = "nvidia"))]
     let kernel_file = format!("{crate_name}.elf");
+    #[cfg(feature = "nvidia")]
+    let kernel_file = format!("{crate_name}.ptx");
     let manifest_dir =
         PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR must be set"))
             .canonicalize()
@@ -558,10 +1048,14 @@ fn kernel_lib_impl(_: proc_macro::TokenStream, debug: bool) -> proc_macro::Token
         .map(PathBuf::from)
         .unwrap_or_else(|_| manifest_dir.join("target"))
         .join("gpu-kernel");
-    let kernel_path = target_dir
-        .join(target)
-        .join(if debug { "debug" } else { "release" })
-        .join(&kernel_file);
+    let profile = if debug { "debug" } else { "release" };
+
+    // Load a precompiled module at runtime instead of compiling the GPU crate
+    if let Ok(precompiled_dir) = env::var("GPU_KERNEL_PRECOMPILED")
+        && !precompiled_dir.is_empty()
+    {
+        return precompiled_module(&manifest_dir.join(precompiled_dir), &crate_name);
+    }
 
     let env_rustflags = env::var(&target_rustflags).unwrap_or_default();
     // Custom setting
@@ -571,15 +1065,29 @@ fn kernel_lib_impl(_: proc_macro::TokenStream, debug: bool) -> proc_macro::Token
     let all_rustflags = get_rustflags(&env_rustflags, &manifest_dir, target);
 
     // Find important things in flags
-    let target_cpu = {
-        let i = all_rustflags.rfind("target-cpu").unwrap_or_else(|| panic!("Did not find target-cpu, make sure to set `-Ctarget-cpu=...` in ${target_rustflags}"));
+    let target_cpu = all_rustflags.rfind("target-cpu").map(|i| {
         let start = i + "target-cpu".len() + 1;
         let end = all_rustflags[start..]
             .find(' ')
             .map(|i| start + i)
             .unwrap_or(all_rustflags.len());
         &all_rustflags[start..end]
-    };
+    });
+    // Compile for all listed CPUs or the one from the flags
+    let target_cpus = env::var(&target_cpus_var).unwrap_or_default();
+    let mut target_cpus = target_cpus
+        .split([',', ' '])
+        .filter(|cpu| !cpu.is_empty())
+        .collect::<Vec<_>>();
+    if target_cpus.is_empty() {
+        target_cpus.push(target_cpu.unwrap_or_else(|| panic!("Did not find target-cpu, make sure to set `-Ctarget-cpu=...` in ${target_rustflags} or list target CPUs in ${target_cpus_var}")));
+    }
+    // PTX is compiled by the driver when loading and runs on GPUs newer than the target as well
+    if cfg!(feature = "nvidia") && target_cpus.len() > 1 {
+        panic!(
+            "Compiling for multiple CPUs is not supported for NVIDIA GPUs, set only the oldest one in ${target_cpus_var}"
+        );
+    }
     // Enabled and not disabled or enabling comes later than disabling
     #[cfg(feature = "amd")]
     let is_wave64_enabled = all_rustflags
@@ -593,16 +1101,6 @@ fn kernel_lib_impl(_: proc_macro::TokenStream, debug: bool) -> proc_macro::Token
         })
         .unwrap_or_default();
 
-    #[cfg(feature = "amd")]
-    let link_args =
-        amdgpu_device_libs_build::get_link_args(is_wave64_enabled, &target_cpu).link_args;
-    #[cfg(not(feature = "amd"))]
-    let link_args = [/* mark as used */ target_cpu];
-    let new_rustflags = link_args
-        .iter()
-        .map(|v| format!("-Clink-arg={v}"))
-        .collect::<Vec<_>>();
-
     // Copy Cargo.toml, insert lib.path = main.rs if lib does not exist, set lib.crate-type = cdylib
     let cargo_toml = fs::read_to_string(&manifest_path)
         .unwrap_or_else(|e| panic!("Failed to read {}: {e}", manifest_path.display()));
@@ -622,56 +1120,116 @@ fn kernel_lib_impl(_: proc_macro::TokenStream, debug: bool) -> proc_macro::Token
         println!("Warning: Failed to copy Cargo.lock to GPU directory ({e}), ignoring");
     }
 
-    let mut cargo = Command::new("cargo");
-    cargo.args([
-        "build",
-        "--target",
-        target,
-        "--lib",
-        "-Zbuild-std=core,alloc",
-        "-m",
-        &gpu_toml.display().to_string(),
-        "--target-dir",
-        &target_dir.display().to_string(),
-    ]);
-    if has_gpu_feature {
-        cargo.arg("--features=gpu");
-    }
-    if !debug {
-        // Compile with panic=immediate-abort,
-        // because GPU code is often quite performance sensitive and just the
-        // existence of panic messages can slow things down considerably.
-        // E.g. the vector_add_fast example gets a speed-up of 6%.
+    let mut code_objects = Vec::new();
+    for cpu in &target_cpus {
+        // Use a separate target dir per CPU, so builds for other CPUs stay cached
+        let cpu_target_dir = if target_cpus.len() == 1 {
+            target_dir.clone()
+        } else {
+            target_dir.join(cpu)
+        };
+
+        #[cfg(feature = "amd")]
+        let link_args = amdgpu_device_libs_build::get_link_args(is_wave64_enabled, cpu).link_args;
+        #[cfg(not(feature = "amd"))]
+        let link_args: [&str; 0] = [];
+        // Device libs are linked as bitcode on amdgpu
+        let lto = if cfg!(feature = "amd") {
+            " -Clinker-plugin-lto"
+        } else {
+            ""
+        };
+        let new_rustflags = link_args
+            .iter()
+            .map(|v| format!("-Clink-arg={v}"))
+            .collect::<Vec<_>>();
+
+        let mut cargo = Command::new("cargo");
         cargo.args([
-            "--release",
-            "-Zpanic-immediate-abort",
-            "--config=profile.release.panic=\"immediate-abort\"",
+            "build",
+            "--target",
+            target,
+            "--lib",
+            "-Zbuild-std=core,alloc",
+            "-m",
+            &gpu_toml.display().to_string(),
+            "--target-dir",
+            &cpu_target_dir.display().to_string(),
         ]);
-    } else {
-        // Compile always with optimizations.
-        // Compiling without optimizations can lead to crashes or compilation failures.
-        cargo.arg("--config=profile.dev.opt-level=2");
-    }
-    if !cargoflags.is_empty() {
-        for f in cargoflags.split(' ') {
-            cargo.arg(f);
+        if has_gpu_feature {
+            cargo.arg("--features=gpu");
+        }
+        if !debug {
+            // Compile with panic=immediate-abort,
+            // because GPU code is often quite performance sensitive and just the
+            // existence of panic messages can slow things down considerably.
+            // E.g. the vector_add_fast example gets a speed-up of 6%.
+            cargo.args([
+                "--release",
+                "-Zpanic-immediate-abort",
+                "--config=profile.release.panic=\"immediate-abort\"",
+            ]);
+        } else {
+            // Compile always with optimizations.
+            // Compiling without optimizations can lead to crashes or compilation failures.
+            cargo.arg("--config=profile.dev.opt-level=2");
+        }
+        if !cargoflags.is_empty() {
+            for f in cargoflags.split(' ') {
+                cargo.arg(f);
+            }
         }
-    }
 
-    cargo.env(
-        &target_rustflags,
-        format!(
-            "{env_rustflags} {} -Clinker-plugin-lto",
-            new_rustflags.join(" ")
-        ),
-    );
-    let res = cargo
-        .status()
-        .expect("Failed to run cargo to compile for GPU");
-    if !res.success() {
-        panic!("Cargo did not exit successfully, failed to compile for GPU");
+        cargo.env(
+            &target_rustflags,
+            format!(
+                "{env_rustflags} -Ctarget-cpu={cpu} {}{lto}",
+                new_rustflags.join(" ")
+            ),
+        );
+        let res = cargo
+            .status()
+            .expect("Failed to run cargo to compile for GPU");
+        if !res.success() {
+            panic!("Cargo did not exit successfully, failed to compile for GPU ({cpu})");
+        }
+        code_objects.push((
+            *cpu,
+            cpu_target_dir.join(target).join(profile).join(&kernel_file),
+        ));
     }
 
+    // Bundle code objects if there are multiple
+    let kernel_path = if let [(_, path)] = code_objects.as_slice() {
+        path.clone()
+    } else {
+        let code_objects = code_objects
+            .iter()
+            .map(|(cpu, path)| {
+                let data = fs::read(path)
+                    .unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()));
+                (format!("hipv4-{target}--{cpu}"), data)
+            })
+            .collect::<Vec<_>>();
+        // `gpu_kernel::Module` loads the code object that matches the device
+        let entries = code_objects
+            .iter()
+            .map(|(id, data)| (id.as_str(), data.as_slice()))
+            .collect::<Vec<_>>();
+        let bundle_path = target_dir
+            .join(target)
+            .join(profile)
+            .join(format!("{crate_name}.hipfb"));
+        fs::create_dir_all(bundle_path.parent().unwrap())
+            .expect("Failed to create gpu-kernel target dir");
+        fs::write(
+            &bundle_path,
+            gpu_kernel_bundle::write_offload_bundle(&entries),
+        )
+        .expect("Failed to write offload bundle");
+        bundle_path
+    };
+
     let kernel_path = kernel_path.display().to_string();
     let manifest_path = manifest_path.display().to_string();
     let lock_path = lock_path.display().to_string();
@@ -684,6 +1242,8 @@ fn kernel_lib_impl(_: proc_macro::TokenStream, debug: bool) -> proc_macro::Token
         const _: std::option::Option<&str> = std::option_env!("CARGO_TARGET_DIR");
         const _: std::option::Option<&str> = std::option_env!(#target_rustflags);
         const _: std::option::Option<&str> = std::option_env!(#target_cargoflags);
+        const _: std::option::Option<&str> = std::option_env!(#target_cpus_var);
+        const _: std::option::Option<&str> = std::option_env!("GPU_KERNEL_PRECOMPILED");
 
         #[doc(hidden)]
         static GPU_KERNEL_MODULE_DATA: &[u8] = std::include_bytes!(#kernel_path);
@@ -691,7 +1251,7 @@ fn kernel_lib_impl(_: proc_macro::TokenStream, debug: bool) -> proc_macro::Token
         // an error that this is not found.
         // Use the name to hint the user what is missing.
         #[doc(hidden)]
-        static KERNEL_LIB_CALLED_IN_CRATE: std::sync::LazyLock<::gpu_kernel::Module> = std::sync::LazyLock::new(|| ::gpu_kernel::Module::new(GPU_KERNEL_MODULE_DATA));
+        static KERNEL_LIB_CALLED_IN_CRATE: ::gpu_kernel::LazyModule = ::gpu_kernel::LazyModule::new(GPU_KERNEL_MODULE_DATA);
     };
     proc_macro::TokenStream::from(output)
 }
@@ -0,0 +1,119 @@
+#[cfg(feature = "nvidia")]
+use crate::cuda;
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use crate::error::check;
+#[cfg(feature = "hip")]
+use crate::runtime::runtime;
+use crate::{GpuError, Kernel};
+
+/// Resource usage of a compiled kernel, see [`Kernel::attributes`].
+///
+/// # Example
+///
+/// ```no_run
+/// use gpu_kernel::kernel;
+///
+/// gpu_kernel::kernel_lib!();
+///
+/// #[kernel]
+/// fn kernel() {}
+///
+/// # fn main() -> Result<(), gpu_kernel::GpuError> {
+/// let attributes = kernel.get_current()?.attributes()?;
+/// // Scratch memory usually means that registers are spilled
+/// assert_eq!(attributes.private_segment_size, 0, "Kernel spills registers");
+/// # Ok(())
+/// # }
+/// ```
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct KernelAttributes {
+    /// The number of vector registers (VGPRs) used by each thread.
+    pub vgpr_count: u32,
+    /// The size of private (scratch) memory per thread in bytes.
+    ///
+    /// This includes stack and spilled registers.
+    pub private_segment_size: u32,
+    /// The size of statically allocated shared memory (LDS) per workgroup in bytes.
+    pub static_shared_memory: u32,
+    /// The size of constant memory used by the kernel in bytes.
+    pub constant_memory: u32,
+    /// The maximum number of threads in a workgroup that the kernel was compiled for.
+    pub max_threads_per_workgroup: u32,
+    /// The number of scalar registers (SGPRs) used by each wavefront.
+    ///
+    /// Read from the code object metadata, `None` if it is not available, e.g. on NVIDIA GPUs.
+    pub sgpr_count: Option<u32>,
+    /// The size of the kernel arguments in bytes.
+    ///
+    /// Read from the code object metadata, `None` if it is not available.
+    pub kernarg_segment_size: Option<u32>,
+}
+
+impl Kernel {
+    /// Query the resource usage of the compiled kernel.
+    pub fn attributes(&self) -> Result<KernelAttributes, GpuError> {
+        #[cfg(feature = "hip")]
+        {
+            use crate::hip::hipFunction_attribute::*;
+
+            let attribute = |attribute| func_attribute(self.func, attribute, &self.name);
+            Ok(KernelAttributes {
+                vgpr_count: attribute(HIP_FUNC_ATTRIBUTE_NUM_REGS)?,
+                private_segment_size: attribute(HIP_FUNC_ATTRIBUTE_LOCAL_SIZE_BYTES)?,
+                static_shared_memory: attribute(HIP_FUNC_ATTRIBUTE_SHARED_SIZE_BYTES)?,
+                constant_memory: attribute(HIP_FUNC_ATTRIBUTE_CONST_SIZE_BYTES)?,
+                max_threads_per_workgroup: attribute(HIP_FUNC_ATTRIBUTE_MAX_THREADS_PER_BLOCK)?,
+                sgpr_count: self.metadata.as_ref().map(|m| m.sgpr_count),
+                kernarg_segment_size: self.metadata.as_ref().map(|m| m.kernarg_segment_size),
+            })
+        }
+        #[cfg(feature = "nvidia")]
+        {
+            use cuda::*;
+
+            let attribute = |attribute| func_attribute(self.func, attribute, &self.name);
+            Ok(KernelAttributes {
+                vgpr_count: attribute(CU_FUNC_ATTRIBUTE_NUM_REGS)?,
+                private_segment_size: attribute(CU_FUNC_ATTRIBUTE_LOCAL_SIZE_BYTES)?,
+                static_shared_memory: attribute(CU_FUNC_ATTRIBUTE_SHARED_SIZE_BYTES)?,
+                constant_memory: attribute(CU_FUNC_ATTRIBUTE_CONST_SIZE_BYTES)?,
+                max_threads_per_workgroup: attribute(CU_FUNC_ATTRIBUTE_MAX_THREADS_PER_BLOCK)?,
+                sgpr_count: None,
+                kernarg_segment_size: None,
+            })
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Err(GpuError::NoRuntime)
+    }
+}
+
+/// Query a single attribute of a kernel function.
+#[cfg(feature = "hip")]
+pub(crate) fn func_attribute(
+    func: crate::hip::hipFunction_t,
+    attribute: crate::hip::hipFunction_attribute,
+    name: &str,
+) -> Result<u32, GpuError> {
+    let mut value = 0;
+    unsafe {
+        let result = runtime().func_get_attribute(&mut value, attribute, func);
+        check(result, "hipFuncGetAttribute", Some(name))?;
+    }
+    Ok(value as u32)
+}
+
+/// Query a single attribute of a kernel function.
+#[cfg(feature = "nvidia")]
+pub(crate) fn func_attribute(
+    func: cuda::CUfunction,
+    attribute: std::ffi::c_int,
+    name: &str,
+) -> Result<u32, GpuError> {
+    let mut value = 0;
+    unsafe {
+        let result = cuda::cuFuncGetAttribute(&mut value, attribute, func);
+        check(result, "cuFuncGetAttribute", Some(name))?;
+    }
+    Ok(value as u32)
+}
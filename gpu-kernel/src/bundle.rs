@@ -0,0 +1,474 @@
+use std::io::Read;
+
+use gpu_kernel_bundle::{MAGIC, fnv1a};
+
+use crate::{DeviceProperties, GpuError};
+
+/// The magic bytes at the start of a compressed offload bundle.
+const COMPRESSED_MAGIC: &[u8] = b"CCOB";
+/// The triple of AMD GPU code objects.
+const TRIPLE: &str = "amdgcn-amd-amdhsa-";
+
+/// Compression methods in compressed bundles.
+const METHOD_ZLIB: u16 = 0;
+const METHOD_ZSTD: u16 = 1;
+/// Preallocate at most this multiple of the compressed size when decompressing.
+const MAX_PREALLOCATED_RATIO: usize = 16;
+
+/// A clang offload bundle, containing code objects for multiple GPUs.
+///
+/// Bundles are created by `hipcc --genco` (usually as `.hipfb` or `.co` files) and by
+/// `kernel_lib!()` when compiling for multiple GPUs.
+/// Both uncompressed (`__CLANG_OFFLOAD_BUNDLE__`) and compressed (`CCOB`) bundles can be read.
+///
+/// [`Module::new`](crate::Module::new) accepts bundles directly and loads the code object for the
+/// device.
+///
+/// # Example
+///
+/// ```no_run
+/// use gpu_kernel::OffloadBundle;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let data = std::fs::read("kernels.hipfb")?;
+/// let bundle = OffloadBundle::parse(&data)?;
+/// for entry in bundle.entries() {
+///     println!("{}: {} bytes", entry.id, entry.data.len());
+/// }
+/// let code_object = bundle.code_object("amdgcn-amd-amdhsa--gfx90a");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OffloadBundle {
+    entries: Vec<OffloadBundleEntry>,
+}
+
+/// An entry in an [`OffloadBundle`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OffloadBundleEntry {
+    /// The id of the entry, e.g. `hipv4-amdgcn-amd-amdhsa--gfx90a:xnack-` or
+    /// `host-x86_64-unknown-linux-gnu-`.
+    ///
+    /// The id consists of the offload kind, the target triple and the target id.
+    pub id: String,
+    /// The content of the entry, usually a code object.
+    pub data: Vec<u8>,
+}
+
+impl OffloadBundle {
+    /// Create an empty bundle.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check if the data is a compressed or uncompressed offload bundle instead of a single code
+    /// object.
+    pub fn is_bundle(data: &[u8]) -> bool {
+        data.starts_with(MAGIC) || data.starts_with(COMPRESSED_MAGIC)
+    }
+
+    /// Parse a compressed or uncompressed offload bundle.
+    pub fn parse(data: &[u8]) -> Result<Self, GpuError> {
+        if data.starts_with(COMPRESSED_MAGIC) {
+            Self::parse(&decompress(data)?)
+        } else if data.starts_with(MAGIC) {
+            parse_uncompressed(data)
+        } else {
+            Err(invalid("has no magic bytes"))
+        }
+    }
+
+    /// All entries in the bundle.
+    pub fn entries(&self) -> &[OffloadBundleEntry] {
+        &self.entries
+    }
+
+    /// Add an entry to the bundle.
+    pub fn push(&mut self, id: impl Into<String>, data: Vec<u8>) {
+        self.entries.push(OffloadBundleEntry {
+            id: id.into(),
+            data,
+        });
+    }
+
+    /// Add an AMD GPU code object for the given target, e.g. `gfx90a` or `gfx90a:xnack-`.
+    pub fn push_code_object(&mut self, target: &str, data: Vec<u8>) {
+        self.push(format!("hipv4-{TRIPLE}-{target}"), data);
+    }
+
+    /// Get the content of the entry with the given id.
+    pub fn entry(&self, id: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|e| e.id == id)
+            .map(|e| e.data.as_slice())
+    }
+
+    /// Get the code object for a target id like `amdgcn-amd-amdhsa--gfx90a:xnack-`.
+    ///
+    /// The target id needs to match exactly, independent of the offload kind (`hip` or `hipv4`).
+    /// Use [`Self::code_object_for`] to find a code object that runs on a device.
+    pub fn code_object(&self, target_id: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|e| e.id.split_once('-').is_some_and(|(_, id)| id == target_id))
+            .map(|e| e.data.as_slice())
+    }
+
+    /// Get the code object that runs on a device.
+    ///
+    /// A code object compiled without target features like `xnack` runs on devices with any
+    /// setting of the feature.
+    pub fn code_object_for(&self, props: &DeviceProperties) -> Result<&[u8], GpuError> {
+        let mut available = Vec::new();
+        for entry in &self.entries {
+            let Some(target) = entry.amdgpu_target() else {
+                // Host or other entry
+                continue;
+            };
+            if matches_device(target, props) {
+                return Ok(&entry.data);
+            }
+            available.push(target.to_string());
+        }
+        Err(GpuError::NoCodeObjectForDevice {
+            arch: props.arch.clone(),
+            available,
+            embedded: false,
+        })
+    }
+
+    /// Write the bundle in the uncompressed format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let entries = self
+            .entries
+            .iter()
+            .map(|e| (e.id.as_str(), e.data.as_slice()))
+            .collect::<Vec<_>>();
+        gpu_kernel_bundle::write_offload_bundle(&entries)
+    }
+
+    /// Write the bundle in the compressed `CCOB` format, using zstd.
+    ///
+    /// The header contains a hash of the uncompressed bundle that is only used to identify the
+    /// content.
+    /// It is not the same hash that clang writes.
+    pub fn to_compressed_bytes(&self) -> Vec<u8> {
+        let data = self.to_bytes();
+        let compressed = ruzstd::encoding::compress_to_vec(
+            &data[..],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+
+        let mut bundle = Vec::new();
+        bundle.extend_from_slice(COMPRESSED_MAGIC);
+        // Version 2 has 32-bit sizes, version 3 has 64-bit sizes
+        let total_size = COMPRESSED_MAGIC.len() + 4 + 8 + 8 + compressed.len();
+        if let (Ok(total_size), Ok(size)) = (u32::try_from(total_size), u32::try_from(data.len())) {
+            bundle.extend_from_slice(&2u16.to_le_bytes());
+            bundle.extend_from_slice(&METHOD_ZSTD.to_le_bytes());
+            bundle.extend_from_slice(&total_size.to_le_bytes());
+            bundle.extend_from_slice(&size.to_le_bytes());
+        } else {
+            bundle.extend_from_slice(&3u16.to_le_bytes());
+            bundle.extend_from_slice(&METHOD_ZSTD.to_le_bytes());
+            bundle.extend_from_slice(&(total_size as u64 + 8).to_le_bytes());
+            bundle.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        }
+        bundle.extend_from_slice(&fnv1a(&data).to_le_bytes());
+        bundle.extend_from_slice(&compressed);
+        bundle
+    }
+}
+
+impl OffloadBundleEntry {
+    /// The AMD GPU target of this entry, e.g. `gfx90a:xnack-` for
+    /// `hipv4-amdgcn-amd-amdhsa--gfx90a:xnack-`.
+    ///
+    /// `None` if the entry is not an AMD GPU code object.
+    pub fn amdgpu_target(&self) -> Option<&str> {
+        let (_kind, rest) = self.id.split_once('-')?;
+        rest.strip_prefix(TRIPLE)?.strip_prefix('-')
+    }
+}
+
+/// Read the entries of an uncompressed bundle.
+fn parse_uncompressed(data: &[u8]) -> Result<OffloadBundle, GpuError> {
+    let count = read_u64(data, MAGIC.len())?;
+    let mut offset = MAGIC.len() + 8;
+    let mut bundle = OffloadBundle::new();
+    for _ in 0..count {
+        let start = read_u64(data, offset)?;
+        let size = read_u64(data, offset + 8)?;
+        let id_size = read_u64(data, offset + 16)?;
+        offset += 24;
+        let id = offset
+            .checked_add(id_size)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| invalid("is truncated"))?;
+        let id = str::from_utf8(id).map_err(|_| invalid("has an entry id that is not UTF-8"))?;
+        offset += id_size;
+        let content = start
+            .checked_add(size)
+            .and_then(|end| data.get(start..end))
+            .ok_or_else(|| invalid(&format!("entry `{id}` is out of bounds")))?;
+        bundle.push(id, content.to_vec());
+    }
+    Ok(bundle)
+}
+
+/// Decompress a `CCOB` bundle into an uncompressed bundle.
+fn decompress(data: &[u8]) -> Result<Vec<u8>, GpuError> {
+    let read_u16 = |offset: usize| {
+        data.get(offset..offset + 2)
+            .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+            .ok_or_else(|| invalid("is truncated"))
+    };
+    let read_u32 = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+            .ok_or_else(|| invalid("is truncated"))
+    };
+
+    let version = read_u16(4)?;
+    let method = read_u16(6)?;
+    // Version 1 has no total size, version 2 has 32-bit sizes and version 3 64-bit sizes
+    let (total_size, size, header_size) = match version {
+        1 => (data.len(), read_u32(8)?, 20),
+        2 => (read_u32(8)?, read_u32(12)?, 24),
+        3 => (read_u64(data, 8)?, read_u64(data, 16)?, 32),
+        _ => return Err(invalid(&format!("has unsupported version {version}"))),
+    };
+    let compressed = data
+        .get(header_size..total_size)
+        .ok_or_else(|| invalid("is truncated"))?;
+    let decompress_error =
+        |e: &dyn std::fmt::Display| invalid(&format!("cannot be decompressed: {e}"));
+
+    // The size in the header is not trusted for allocations, decompress at most one byte more
+    // to detect a mismatch
+    let limit = size.saturating_add(1);
+    let decompressed = match method {
+        METHOD_ZLIB => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(compressed, limit)
+            .map_err(|e| decompress_error(&e))?,
+        METHOD_ZSTD => {
+            let capacity = size.min(compressed.len().saturating_mul(MAX_PREALLOCATED_RATIO));
+            let mut decompressed = Vec::with_capacity(capacity);
+            ruzstd::decoding::StreamingDecoder::new(compressed)
+                .map_err(|e| decompress_error(&e))?
+                .take(limit as u64)
+                .read_to_end(&mut decompressed)
+                .map_err(|e| decompress_error(&e))?;
+            decompressed
+        }
+        _ => {
+            return Err(invalid(&format!(
+                "has unsupported compression method {method}"
+            )));
+        }
+    };
+    if decompressed.len() != size {
+        return Err(invalid(&format!(
+            "decompresses to {} bytes instead of {size} bytes",
+            decompressed.len()
+        )));
+    }
+    Ok(decompressed)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<usize, GpuError> {
+    data.get(offset..offset + 8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()) as usize)
+        .ok_or_else(|| invalid("is truncated"))
+}
+
+fn invalid(reason: &str) -> GpuError {
+    GpuError::InvalidCodeObject(format!("offload bundle {reason}"))
+}
+
+/// Check if a target like `gfx90a:xnack-` runs on the device.
+///
+/// Features that are not mentioned in the target work with any setting on the device.
+fn matches_device(target: &str, props: &DeviceProperties) -> bool {
+    let mut parts = target.split(':');
+    if parts.next() != Some(props.gfx.as_str()) {
+        return false;
+    }
+    parts.all(|feature| {
+        let (name, enabled) = if let Some(name) = feature.strip_suffix('+') {
+            (name, true)
+        } else if let Some(name) = feature.strip_suffix('-') {
+            (name, false)
+        } else {
+            return true;
+        };
+        let device_setting = match name {
+            "xnack" => props.xnack,
+            "sramecc" => props.sramecc,
+            _ => None,
+        };
+        device_setting.is_none_or(|s| s == enabled)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bundle with an empty entry between two code objects.
+    const BUNDLE: &[u8] = include_bytes!("../tests/data/bundle.hipfb");
+
+    fn bundle() -> OffloadBundle {
+        let mut bundle = OffloadBundle::new();
+        bundle.push_code_object("gfx90a", b"gfx90a code object".to_vec());
+        bundle.push_code_object("gfx1030", Vec::new());
+        bundle.push_code_object("gfx1100", b"gfx1100 code object".to_vec());
+        bundle
+    }
+
+    fn props(gfx: &str, xnack: Option<bool>, sramecc: Option<bool>) -> DeviceProperties {
+        DeviceProperties {
+            name: String::new(),
+            arch: gfx.to_string(),
+            gfx: gfx.to_string(),
+            xnack,
+            sramecc,
+            wave_size: 64,
+            compute_units: 1,
+            max_threads_per_compute_unit: 1024,
+            max_threads_per_workgroup: 1024,
+            max_workgroup_size: [1024; 3],
+            max_workgroups: [u32::MAX; 3],
+            shared_memory_per_workgroup: 65536,
+            total_memory: 0,
+            l2_cache_size: 0,
+            managed_memory: false,
+            concurrent_managed_access: false,
+            pageable_memory_access: false,
+            integrated: false,
+        }
+    }
+
+    #[test]
+    fn to_bytes() {
+        assert_eq!(bundle().to_bytes(), BUNDLE);
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let mut bundle = bundle();
+        bundle.push("host-x86_64-unknown-linux-gnu-", Vec::new());
+        let parsed = OffloadBundle::parse(&bundle.to_bytes()).unwrap();
+        assert_eq!(parsed, bundle);
+        assert_eq!(
+            parsed.code_object("amdgcn-amd-amdhsa--gfx1100"),
+            Some(&b"gfx1100 code object"[..])
+        );
+        assert_eq!(
+            OffloadBundle::parse(&OffloadBundle::new().to_bytes()).unwrap(),
+            OffloadBundle::new()
+        );
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let bundle = bundle();
+        let compressed = bundle.to_compressed_bytes();
+        assert!(compressed.starts_with(COMPRESSED_MAGIC));
+        assert!(OffloadBundle::is_bundle(&compressed));
+        assert_eq!(OffloadBundle::parse(&compressed).unwrap(), bundle);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(OffloadBundle::parse(b"not a bundle").is_err());
+        for len in 0..BUNDLE.len() {
+            assert!(
+                OffloadBundle::parse(&BUNDLE[..len]).is_err(),
+                "prefix of {len} bytes"
+            );
+        }
+
+        // The version 2 header stores the decompressed size at offset 12
+        let compressed = bundle().to_compressed_bytes();
+        for size in [
+            0,
+            BUNDLE.len() as u32 - 1,
+            BUNDLE.len() as u32 + 1,
+            u32::MAX,
+        ] {
+            let mut data = compressed.clone();
+            data[12..16].copy_from_slice(&size.to_le_bytes());
+            assert!(OffloadBundle::parse(&data).is_err(), "size {size}");
+        }
+        for len in 0..compressed.len() {
+            assert!(OffloadBundle::parse(&compressed[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn code_object_for() {
+        let mut bundle = OffloadBundle::new();
+        bundle.push("host-x86_64-unknown-linux-gnu-", Vec::new());
+        bundle.push_code_object("gfx90a:xnack+", b"xnack+".to_vec());
+        bundle.push_code_object("gfx90a:sramecc+:xnack-", b"sramecc+ xnack-".to_vec());
+        bundle.push_code_object("gfx90a:sramecc-", b"sramecc-".to_vec());
+        bundle.push_code_object("gfx1100", b"gfx1100".to_vec());
+
+        let code_object = |xnack, sramecc| bundle.code_object_for(&props("gfx90a", xnack, sramecc));
+        assert_eq!(code_object(Some(true), Some(true)).unwrap(), b"xnack+");
+        assert_eq!(
+            code_object(Some(false), Some(true)).unwrap(),
+            b"sramecc+ xnack-"
+        );
+        assert_eq!(code_object(Some(false), Some(false)).unwrap(), b"sramecc-");
+        // Devices that do not report a feature run code objects compiled for any setting
+        assert_eq!(code_object(None, None).unwrap(), b"xnack+");
+        assert_eq!(code_object(Some(false), None).unwrap(), b"sramecc+ xnack-");
+
+        // Code objects without target features run everywhere
+        let props_1100 = props("gfx1100", Some(true), Some(false));
+        assert_eq!(bundle.code_object_for(&props_1100).unwrap(), b"gfx1100");
+
+        match bundle.code_object_for(&props("gfx942", None, None)) {
+            Err(GpuError::NoCodeObjectForDevice {
+                arch,
+                available,
+                embedded: false,
+            }) => {
+                assert_eq!(arch, "gfx942");
+                assert_eq!(
+                    available,
+                    [
+                        "gfx90a:xnack+",
+                        "gfx90a:sramecc+:xnack-",
+                        "gfx90a:sramecc-",
+                        "gfx1100"
+                    ]
+                );
+            }
+            result => panic!("unexpected result {result:?}"),
+        }
+    }
+
+    #[test]
+    fn no_code_object_message() {
+        let error = |available: &[&str], embedded| GpuError::NoCodeObjectForDevice {
+            arch: "gfx942:sramecc+:xnack-".to_string(),
+            available: available.iter().map(|s| s.to_string()).collect(),
+            embedded,
+        };
+        assert_eq!(
+            error(&["gfx90a", "gfx1100"], false).to_string(),
+            "No code object for gfx942:sramecc+:xnack- in the module, it contains code for \
+             gfx90a, gfx1100"
+        );
+        // Only modules built by `kernel_lib!()` can be fixed by compiling for more targets
+        assert_eq!(
+            error(&[], true).to_string(),
+            "No code object for gfx942:sramecc+:xnack- in the module, it contains no AMD GPU code \
+             objects. Add gfx942 to CARGO_TARGET_AMDGCN_AMD_AMDHSA_CPUS"
+        );
+    }
+}
@@ -0,0 +1,614 @@
+use std::any::Any;
+use std::cell::{Cell, UnsafeCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
+
+use crate::{BenchResult, GpuError, LaunchConfig, LaunchHandle};
+
+/// The maximum number of threads in a workgroup, the same as on AMD GPUs.
+const MAX_THREADS_PER_WORKGROUP: usize = 1024;
+/// The maximum number of threads that run a launch, fewer workgroups run in parallel if needed.
+const MAX_THREADS: usize = 4096;
+
+/// Describes the launch of the running kernel, like the HSA dispatch packet on AMD GPUs.
+///
+/// Get it with [`dispatch_packet`] inside a kernel.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DispatchPacket {
+    /// X dimension of a workgroup, in threads.
+    pub workgroup_size_x: u16,
+    /// Y dimension of a workgroup, in threads.
+    pub workgroup_size_y: u16,
+    /// Z dimension of a workgroup, in threads.
+    pub workgroup_size_z: u16,
+    /// X dimension of the whole launch, in threads.
+    pub grid_size_x: u32,
+    /// Y dimension of the whole launch, in threads.
+    pub grid_size_y: u32,
+    /// Z dimension of the whole launch, in threads.
+    pub grid_size_z: u32,
+    /// Size of dynamic shared memory per workgroup in bytes.
+    pub group_segment_size: u32,
+}
+
+/// The state of a thread that runs a kernel.
+#[derive(Clone, Copy)]
+struct Context {
+    workitem_id: [u32; 3],
+    workgroup_id: [u32; 3],
+    worker: *const Worker,
+}
+
+thread_local! {
+    /// Set while the thread runs a kernel.
+    static CONTEXT: Cell<Option<Context>> = const { Cell::new(None) };
+}
+
+/// Get the state of the kernel running on this thread.
+fn context() -> Context {
+    CONTEXT
+        .get()
+        .expect("GPU intrinsics can only be used inside a kernel")
+}
+
+/// A group of threads that runs one workgroup after another.
+///
+/// The threads of a worker live as long as the launch, so they can wait for each other in
+/// barriers.
+struct Worker {
+    barrier: Barrier,
+    /// The index of the workgroup that is run next, written by the first thread.
+    workgroup: AtomicUsize,
+    dispatch: DispatchPacket,
+    /// Dynamic shared memory of the current workgroup.
+    shared_memory: Box<[UnsafeCell<u128>]>,
+}
+
+// SAFETY: Kernels synchronize accesses to shared memory, like on a GPU.
+unsafe impl Sync for Worker {}
+
+/// A barrier that wakes up all waiting threads when a thread panics.
+struct Barrier {
+    threads: usize,
+    state: Mutex<BarrierState>,
+    condvar: Condvar,
+}
+
+struct BarrierState {
+    arrived: usize,
+    generation: u64,
+    poisoned: bool,
+}
+
+/// A thread of the workgroup panicked, so the barrier can never complete.
+struct Poisoned;
+
+impl Barrier {
+    fn new(threads: usize) -> Self {
+        Self {
+            threads,
+            state: Mutex::new(BarrierState {
+                arrived: 0,
+                generation: 0,
+                poisoned: false,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn wait(&self) -> Result<(), Poisoned> {
+        let mut state = self.state.lock().unwrap();
+        if state.poisoned {
+            return Err(Poisoned);
+        }
+        let generation = state.generation;
+        state.arrived += 1;
+        if state.arrived == self.threads {
+            state.arrived = 0;
+            state.generation += 1;
+            self.condvar.notify_all();
+            return Ok(());
+        }
+        let state = self
+            .condvar
+            .wait_while(state, |s| s.generation == generation && !s.poisoned)
+            .unwrap();
+        if state.generation == generation {
+            Err(Poisoned)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn poison(&self) {
+        self.state.lock().unwrap().poisoned = true;
+        self.condvar.notify_all();
+    }
+}
+
+/// The state shared by all threads of a launch.
+struct Launch<T> {
+    /// The arguments that every thread gets a copy of.
+    args: *const T,
+    kernel: fn(T),
+    workgroups: [u32; 3],
+    threads_per_workgroup: [u32; 3],
+    /// The index of the next workgroup that is not started yet.
+    next_workgroup: AtomicUsize,
+    total_workgroups: usize,
+    /// The message of the first panic in the kernel.
+    panic: Mutex<Option<String>>,
+}
+
+// SAFETY: Every thread gets its own copy of the arguments, like on a GPU, the caller guarantees
+// that this is fine.
+unsafe impl<T> Sync for Launch<T> {}
+
+/// The index of a thread or workgroup in the x, y and z dimension.
+fn index_3d(index: usize, size: [u32; 3]) -> [u32; 3] {
+    let [x, y, _] = size.map(|s| s as usize);
+    [index % x, (index / x) % y, index / (x * y)].map(|i| i as u32)
+}
+
+/// Run the kernel on the CPU with the given arguments and wait for it to finish.
+///
+/// Every workgroup runs on a group of threads, one thread per GPU thread, and as many workgroups
+/// run in parallel as the CPU has threads, as long as there are at most [`MAX_THREADS`] threads.
+///
+/// # Safety
+///
+/// Every thread gets a bitwise copy of `args`, like on the GPU.
+/// This must be fine for the argument types, e.g. a `#[kernel]` that is not `unsafe`.
+#[doc(hidden)]
+pub unsafe fn launch<T>(
+    launch_config: &LaunchConfig,
+    kernel_name: &str,
+    args: T,
+    kernel: fn(T),
+) -> Result<(), GpuError> {
+    unsafe { run(launch_config, kernel_name, &args, kernel) }
+}
+
+/// Run the kernel on the CPU and return a handle that is already finished.
+///
+/// # Safety
+///
+/// See [`launch`].
+#[doc(hidden)]
+pub unsafe fn launch_async<'a, T>(
+    launch_config: &LaunchConfig,
+    kernel_name: &str,
+    args: T,
+    kernel: fn(T),
+) -> Result<LaunchHandle<'a>, GpuError> {
+    unsafe { launch(launch_config, kernel_name, args, kernel) }?;
+    Ok(LaunchHandle::finished(kernel_name.to_string()))
+}
+
+/// Run the kernel on the CPU multiple times and measure the time it takes.
+///
+/// # Safety
+///
+/// See [`launch`].
+#[doc(hidden)]
+pub unsafe fn bench<T>(
+    launch_config: &LaunchConfig,
+    kernel_name: &str,
+    iterations: u32,
+    args: T,
+    kernel: fn(T),
+) -> Result<BenchResult, GpuError> {
+    if iterations == 0 {
+        return Err(GpuError::InvalidLaunchConfig(
+            "must run at least one iteration".into(),
+        ));
+    }
+    // Warm up
+    unsafe { run(launch_config, kernel_name, &args, kernel) }?;
+    let mut times = Vec::with_capacity(iterations as usize);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        unsafe { run(launch_config, kernel_name, &args, kernel) }?;
+        times.push(start.elapsed());
+    }
+    Ok(BenchResult::from_times(times))
+}
+
+/// Run all workgroups of a kernel.
+///
+/// # Safety
+///
+/// See [`launch`].
+unsafe fn run<T>(
+    launch_config: &LaunchConfig,
+    kernel_name: &str,
+    args: &T,
+    kernel: fn(T),
+) -> Result<(), GpuError> {
+    launch_config.validate()?;
+    let workgroups = launch_config.workgroups.unwrap();
+    let threads_per_workgroup = launch_config.threads_per_workgroup.unwrap();
+    let threads = threads_per_workgroup.iter().map(|t| *t as usize).product();
+    if threads > MAX_THREADS_PER_WORKGROUP {
+        return Err(GpuError::InvalidLaunchConfig(format!(
+            "threads_per_workgroup is {threads} in total, but the CPU backend supports at most \
+             {MAX_THREADS_PER_WORKGROUP}"
+        )));
+    }
+
+    let dispatch = DispatchPacket {
+        workgroup_size_x: threads_per_workgroup[0] as u16,
+        workgroup_size_y: threads_per_workgroup[1] as u16,
+        workgroup_size_z: threads_per_workgroup[2] as u16,
+        grid_size_x: workgroups[0] * threads_per_workgroup[0],
+        grid_size_y: workgroups[1] * threads_per_workgroup[1],
+        grid_size_z: workgroups[2] * threads_per_workgroup[2],
+        group_segment_size: launch_config.dynamic_shared_memory,
+    };
+    let total_workgroups = workgroups.iter().map(|w| *w as usize).product::<usize>();
+    let worker_count = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(MAX_THREADS / threads)
+        .min(total_workgroups)
+        .max(1);
+    let shared_memory_len = (launch_config.dynamic_shared_memory as usize).div_ceil(16);
+    let workers = (0..worker_count)
+        .map(|_| Worker {
+            barrier: Barrier::new(threads),
+            workgroup: AtomicUsize::new(0),
+            dispatch: dispatch.clone(),
+            shared_memory: (0..shared_memory_len).map(|_| UnsafeCell::new(0)).collect(),
+        })
+        .collect::<Vec<_>>();
+    let launch = Launch {
+        args,
+        kernel,
+        workgroups,
+        threads_per_workgroup,
+        next_workgroup: AtomicUsize::new(0),
+        total_workgroups,
+        panic: Mutex::new(None),
+    };
+
+    let spawn_error = std::thread::scope(|scope| {
+        for worker in &workers {
+            for thread in 0..threads {
+                let launch = &launch;
+                let spawned = std::thread::Builder::new()
+                    .name(format!("{kernel_name}-{thread}"))
+                    .spawn_scoped(scope, move || run_thread(launch, worker, thread));
+                if let Err(e) = spawned {
+                    // Stop all workers and wake up the threads that wait for the missing ones
+                    launch
+                        .next_workgroup
+                        .store(launch.total_workgroups, Ordering::Relaxed);
+                    worker.barrier.poison();
+                    return Some(e);
+                }
+            }
+        }
+        None
+    });
+    if let Some(e) = spawn_error {
+        return Err(GpuError::InvalidLaunchConfig(format!(
+            "cannot spawn {threads} threads to run a workgroup of kernel `{kernel_name}` on the \
+             CPU: {e}"
+        )));
+    }
+
+    match launch.panic.into_inner().unwrap() {
+        Some(message) => Err(GpuError::KernelPanicked {
+            kernel: kernel_name.to_string(),
+            message,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Run one thread of every workgroup that the worker takes.
+fn run_thread<T>(launch: &Launch<T>, worker: &Worker, thread: usize) {
+    let workitem_id = index_3d(thread, launch.threads_per_workgroup);
+    loop {
+        // The first thread picks the next workgroup, all threads of the previous workgroup finished
+        if thread == 0 {
+            let next = launch.next_workgroup.fetch_add(1, Ordering::Relaxed);
+            worker.workgroup.store(next, Ordering::Relaxed);
+        }
+        if worker.barrier.wait().is_err() {
+            return;
+        }
+        let workgroup = worker.workgroup.load(Ordering::Relaxed);
+        // Wait until all threads read the index before it is overwritten
+        if worker.barrier.wait().is_err() || workgroup >= launch.total_workgroups {
+            return;
+        }
+
+        CONTEXT.set(Some(Context {
+            workitem_id,
+            workgroup_id: index_3d(workgroup, launch.workgroups),
+            worker,
+        }));
+        // SAFETY: The caller of `launch` guarantees that copying the arguments is fine
+        let args = unsafe { std::ptr::read(launch.args) };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| (launch.kernel)(args)));
+        CONTEXT.set(None);
+
+        if let Err(payload) = result {
+            // Threads that were woken up from a poisoned barrier are no new panic
+            if !payload.is::<Poisoned>() {
+                launch
+                    .panic
+                    .lock()
+                    .unwrap()
+                    .get_or_insert_with(|| panic_message(payload));
+            }
+            // Stop all workers
+            launch
+                .next_workgroup
+                .store(launch.total_workgroups, Ordering::Relaxed);
+            worker.barrier.poison();
+            return;
+        }
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Ok(message) = payload.downcast::<String>() {
+        *message
+    } else {
+        "unknown panic payload".into()
+    }
+}
+
+/// The index of the thread in its workgroup in the x dimension.
+pub fn workitem_id_x() -> u32 {
+    context().workitem_id[0]
+}
+
+/// The index of the thread in its workgroup in the y dimension.
+pub fn workitem_id_y() -> u32 {
+    context().workitem_id[1]
+}
+
+/// The index of the thread in its workgroup in the z dimension.
+pub fn workitem_id_z() -> u32 {
+    context().workitem_id[2]
+}
+
+/// The index of the workgroup in the x dimension.
+pub fn workgroup_id_x() -> u32 {
+    context().workgroup_id[0]
+}
+
+/// The index of the workgroup in the y dimension.
+pub fn workgroup_id_y() -> u32 {
+    context().workgroup_id[1]
+}
+
+/// The index of the workgroup in the z dimension.
+pub fn workgroup_id_z() -> u32 {
+    context().workgroup_id[2]
+}
+
+/// Wait until all threads of the workgroup reached the barrier.
+pub fn s_barrier() {
+    // SAFETY: The worker lives until all its threads finished
+    let worker = unsafe { &*context().worker };
+    if worker.barrier.wait().is_err() {
+        // Another thread panicked, stop this one without printing another panic
+        panic::resume_unwind(Box::new(Poisoned));
+    }
+}
+
+/// Get a copy of the packet that describes the launch of the running kernel.
+///
+/// The packet lives only as long as the launch, so there is no `dispatch_ptr` like on AMD GPUs,
+/// a reference could outlive it.
+pub fn dispatch_packet() -> DispatchPacket {
+    // SAFETY: The worker lives until all its threads finished
+    unsafe { (*context().worker).dispatch.clone() }
+}
+
+/// Get the dynamically sized shared memory of this workgroup.
+///
+/// The size is set when launching the kernel, see [`LaunchConfig::dynamic_shared_memory`].
+/// The memory keeps the contents of the previous workgroup that ran on the same threads, so it
+/// needs to be initialized like on a GPU.
+pub fn dynamic_shared_memory<T>() -> *mut [T] {
+    let worker = context().worker;
+    // SAFETY: The worker lives until all its threads finished
+    let (ptr, bytes) = unsafe {
+        (
+            (*worker).shared_memory.as_ptr() as *mut u8,
+            (*worker).dispatch.group_segment_size as usize,
+        )
+    };
+    let start = ptr.align_offset(align_of::<T>()).min(bytes);
+    let len = (bytes - start).checked_div(size_of::<T>()).unwrap_or(0);
+    std::ptr::slice_from_raw_parts_mut(ptr.wrapping_add(start).cast(), len)
+}
+
+/// A `#[global]` variable when kernels run on the CPU.
+///
+/// Kernels and the CPU access the same variable, so `read` and `write` copy from and to it
+/// directly instead of going through the GPU runtime.
+#[doc(hidden)]
+#[repr(transparent)]
+pub struct Global<T>(UnsafeCell<T>);
+
+// SAFETY: Like a `static`, the value is shared if it is `Sync`, all writes are unsafe
+unsafe impl<T: Sync> Sync for Global<T> {}
+
+impl<T> Global<T> {
+    pub const fn new(value: T) -> Self {
+        Self(UnsafeCell::new(value))
+    }
+
+    /// Get the address of the variable, it is valid as long as the program runs.
+    pub fn as_ptr(&self) -> Result<*mut T, GpuError> {
+        Ok(self.0.get())
+    }
+
+    /// Get a reference to the variable.
+    ///
+    /// # Safety
+    ///
+    /// The variable must not be written while the reference is used.
+    pub unsafe fn get(&self) -> &T {
+        unsafe { &*self.0.get() }
+    }
+}
+
+impl<T: Copy> Global<T> {
+    /// Copy the value of the variable.
+    ///
+    /// # Safety
+    ///
+    /// No kernel may write the variable while it is read.
+    pub unsafe fn read_impl(&self) -> Result<T, GpuError> {
+        Ok(unsafe { self.0.get().read() })
+    }
+
+    /// Overwrite the value of the variable.
+    ///
+    /// # Safety
+    ///
+    /// No kernel may access the variable while it is written.
+    pub unsafe fn write_impl(&self, value: &T) -> Result<(), GpuError> {
+        unsafe { self.0.get().write(*value) };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+
+    use super::*;
+
+    fn launch_config(workgroups: u32, threads: u32) -> LaunchConfig {
+        LaunchConfig::new()
+            .workgroups([workgroups, 1, 1])
+            .threads_per_workgroup([threads, 1, 1])
+            .clone()
+    }
+
+    /// Every thread writes its id to shared memory and reads the id of the next thread.
+    fn rotate(out: &[AtomicU32]) {
+        let threads = dispatch_packet().workgroup_size_x as usize;
+        let thread = workitem_id_x() as usize;
+        let shared = dynamic_shared_memory::<u32>();
+        assert_eq!(shared.len(), threads);
+        let shared = shared as *mut u32;
+        let id = workgroup_id_x() as usize * threads + thread;
+        unsafe { shared.add(thread).write(id as u32) };
+        s_barrier();
+        let next = unsafe { shared.add((thread + 1) % threads).read() };
+        // Keep the next workgroup on this worker from overwriting shared memory too early
+        s_barrier();
+        out[id].store(next, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn barrier_and_shared_memory() {
+        let out = (0..8 * 64)
+            .map(|_| AtomicU32::new(u32::MAX))
+            .collect::<Vec<_>>();
+        let config = launch_config(8, 64).dynamic_shared_memory(64 * 4).clone();
+        unsafe { launch(&config, "rotate", &out[..], rotate) }.unwrap();
+        for (id, value) in out.iter().enumerate() {
+            let expected = id / 64 * 64 + (id + 1) % 64;
+            assert_eq!(value.load(Ordering::Relaxed), expected as u32);
+        }
+    }
+
+    /// Count the threads that run the kernel and the threads that run it at the same time.
+    fn count(counters: &[AtomicUsize; 3]) {
+        let [total, running, max_running] = counters;
+        total.fetch_add(1, Ordering::Relaxed);
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        max_running.fetch_max(now, Ordering::SeqCst);
+        s_barrier();
+        running.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn more_threads_than_max_threads() {
+        let counters = [const { AtomicUsize::new(0) }; 3];
+        let workgroups = (MAX_THREADS / MAX_THREADS_PER_WORKGROUP * 3) as u32;
+        let config = launch_config(workgroups, MAX_THREADS_PER_WORKGROUP as u32);
+        unsafe { launch(&config, "count", &counters, count) }.unwrap();
+        let [total, _, max_running] = counters.map(AtomicUsize::into_inner);
+        assert_eq!(total, workgroups as usize * MAX_THREADS_PER_WORKGROUP);
+        assert!(
+            max_running <= MAX_THREADS,
+            "{max_running} threads ran at the same time"
+        );
+    }
+
+    #[test]
+    fn too_many_threads_per_workgroup() {
+        let counters = [const { AtomicUsize::new(0) }; 3];
+        let config = launch_config(1, MAX_THREADS_PER_WORKGROUP as u32 + 1);
+        let result = unsafe { launch(&config, "count", &counters, count) };
+        assert!(matches!(result, Err(GpuError::InvalidLaunchConfig(_))));
+        assert_eq!(counters[0].load(Ordering::Relaxed), 0);
+    }
+
+    /// One thread panics before the barrier that all other threads wait in.
+    fn panic_before_barrier(finished: &AtomicU32) {
+        if workgroup_id_x() == 1 && workitem_id_x() == 3 {
+            panic!("thread 3 failed");
+        }
+        s_barrier();
+        finished.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn panic_poisons_barrier() {
+        let finished = AtomicU32::new(0);
+        let config = launch_config(4, 64);
+        let result = unsafe { launch(&config, "panic", &finished, panic_before_barrier) };
+        match result {
+            Err(GpuError::KernelPanicked { kernel, message }) => {
+                assert_eq!(kernel, "panic");
+                assert_eq!(message, "thread 3 failed");
+            }
+            result => panic!("expected a panic, got {result:?}"),
+        }
+        // The other threads of the panicking workgroup never pass the barrier
+        assert!(finished.load(Ordering::Relaxed) <= 3 * 64);
+    }
+
+    #[crate::global]
+    static TABLE: [u32; 4] = [1, 2, 3, 4];
+
+    #[crate::global]
+    static mut SUM: u32 = 0;
+
+    /// Add the table entry of every thread to the sum.
+    fn sum_table(_: ()) {
+        let value = TABLE[workitem_id_x() as usize];
+        let sum = unsafe { AtomicU32::from_ptr(SUM.as_ptr().unwrap()) };
+        sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn globals() {
+        unsafe { SUM.write(&100) }.unwrap();
+        unsafe { launch(&launch_config(2, 4), "sum_table", (), sum_table) }.unwrap();
+        assert_eq!(unsafe { SUM.read() }.unwrap(), 120);
+        assert_eq!(TABLE.read().unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn bench_zero_iterations() {
+        let counters = [const { AtomicUsize::new(0) }; 3];
+        let result = unsafe { bench(&launch_config(1, 1), "count", 0, &counters, count) };
+        assert!(matches!(result, Err(GpuError::InvalidLaunchConfig(_))));
+    }
+}
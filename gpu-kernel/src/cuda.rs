@@ -0,0 +1,245 @@
+use std::ffi::{c_char, c_int, c_uint, c_void};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::GpuError;
+use crate::error::check;
+
+/// A result code of the CUDA driver API.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct CUresult(pub(crate) c_int);
+
+impl CUresult {
+    pub(crate) const SUCCESS: Self = Self(0);
+    pub(crate) const NO_DEVICE: Self = Self(100);
+    pub(crate) const NOT_READY: Self = Self(600);
+    pub(crate) const NOT_SUPPORTED: Self = Self(801);
+}
+
+pub(crate) type CUdevice = c_int;
+pub(crate) type CUdeviceptr = u64;
+pub(crate) type CUcontext = *mut c_void;
+pub(crate) type CUmodule = *mut c_void;
+pub(crate) type CUfunction = *mut c_void;
+pub(crate) type CUstream = *mut c_void;
+pub(crate) type CUevent = *mut c_void;
+pub(crate) type CUhostFn = Option<unsafe extern "C" fn(data: *mut c_void)>;
+pub(crate) type CUoccupancyB2DSize = Option<unsafe extern "C" fn(block_size: c_int) -> usize>;
+
+// CUdevice_attribute
+pub(crate) const CU_DEVICE_ATTRIBUTE_MAX_THREADS_PER_BLOCK: c_int = 1;
+pub(crate) const CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_X: c_int = 2;
+pub(crate) const CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_Y: c_int = 3;
+pub(crate) const CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_Z: c_int = 4;
+pub(crate) const CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_X: c_int = 5;
+pub(crate) const CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_Y: c_int = 6;
+pub(crate) const CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_Z: c_int = 7;
+pub(crate) const CU_DEVICE_ATTRIBUTE_MAX_SHARED_MEMORY_PER_BLOCK: c_int = 8;
+pub(crate) const CU_DEVICE_ATTRIBUTE_WARP_SIZE: c_int = 10;
+pub(crate) const CU_DEVICE_ATTRIBUTE_MULTIPROCESSOR_COUNT: c_int = 16;
+pub(crate) const CU_DEVICE_ATTRIBUTE_INTEGRATED: c_int = 18;
+pub(crate) const CU_DEVICE_ATTRIBUTE_L2_CACHE_SIZE: c_int = 38;
+pub(crate) const CU_DEVICE_ATTRIBUTE_MAX_THREADS_PER_MULTIPROCESSOR: c_int = 39;
+pub(crate) const CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR: c_int = 75;
+pub(crate) const CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR: c_int = 76;
+pub(crate) const CU_DEVICE_ATTRIBUTE_MANAGED_MEMORY: c_int = 83;
+pub(crate) const CU_DEVICE_ATTRIBUTE_PAGEABLE_MEMORY_ACCESS: c_int = 88;
+pub(crate) const CU_DEVICE_ATTRIBUTE_CONCURRENT_MANAGED_ACCESS: c_int = 89;
+
+// CUfunction_attribute
+pub(crate) const CU_FUNC_ATTRIBUTE_MAX_THREADS_PER_BLOCK: c_int = 0;
+pub(crate) const CU_FUNC_ATTRIBUTE_SHARED_SIZE_BYTES: c_int = 1;
+pub(crate) const CU_FUNC_ATTRIBUTE_CONST_SIZE_BYTES: c_int = 2;
+pub(crate) const CU_FUNC_ATTRIBUTE_LOCAL_SIZE_BYTES: c_int = 3;
+pub(crate) const CU_FUNC_ATTRIBUTE_NUM_REGS: c_int = 4;
+
+pub(crate) const CU_MEM_ATTACH_GLOBAL: c_uint = 0x1;
+pub(crate) const CU_MEMHOSTALLOC_PORTABLE: c_uint = 0x1;
+pub(crate) const CU_MEMHOSTALLOC_DEVICEMAP: c_uint = 0x2;
+pub(crate) const CU_EVENT_DISABLE_TIMING: c_uint = 0x2;
+pub(crate) const CU_LAUNCH_PARAM_END: *mut c_void = std::ptr::null_mut();
+#[allow(clippy::manual_dangling_ptr)]
+pub(crate) const CU_LAUNCH_PARAM_BUFFER_POINTER: *mut c_void = 0x1 as *mut c_void;
+#[allow(clippy::manual_dangling_ptr)]
+pub(crate) const CU_LAUNCH_PARAM_BUFFER_SIZE: *mut c_void = 0x2 as *mut c_void;
+
+#[link(name = "cuda")]
+unsafe extern "C" {
+    pub(crate) fn cuInit(flags: c_uint) -> CUresult;
+    pub(crate) fn cuGetErrorName(error: CUresult, name: *mut *const c_char) -> CUresult;
+    pub(crate) fn cuGetErrorString(error: CUresult, string: *mut *const c_char) -> CUresult;
+    pub(crate) fn cuDeviceGetCount(count: *mut c_int) -> CUresult;
+    pub(crate) fn cuDeviceGet(device: *mut CUdevice, ordinal: c_int) -> CUresult;
+    pub(crate) fn cuDeviceGetName(name: *mut c_char, len: c_int, device: CUdevice) -> CUresult;
+    pub(crate) fn cuDeviceGetAttribute(
+        value: *mut c_int,
+        attribute: c_int,
+        device: CUdevice,
+    ) -> CUresult;
+    #[link_name = "cuDeviceTotalMem_v2"]
+    pub(crate) fn cuDeviceTotalMem(bytes: *mut usize, device: CUdevice) -> CUresult;
+    pub(crate) fn cuDevicePrimaryCtxRetain(context: *mut CUcontext, device: CUdevice) -> CUresult;
+    pub(crate) fn cuCtxSetCurrent(context: CUcontext) -> CUresult;
+    pub(crate) fn cuCtxGetCurrent(context: *mut CUcontext) -> CUresult;
+    pub(crate) fn cuCtxGetDevice(device: *mut CUdevice) -> CUresult;
+    pub(crate) fn cuMemAllocManaged(ptr: *mut CUdeviceptr, size: usize, flags: c_uint) -> CUresult;
+    #[link_name = "cuMemAlloc_v2"]
+    pub(crate) fn cuMemAlloc(ptr: *mut CUdeviceptr, size: usize) -> CUresult;
+    #[link_name = "cuMemFree_v2"]
+    pub(crate) fn cuMemFree(ptr: CUdeviceptr) -> CUresult;
+    pub(crate) fn cuMemHostAlloc(ptr: *mut *mut c_void, size: usize, flags: c_uint) -> CUresult;
+    pub(crate) fn cuMemFreeHost(ptr: *mut c_void) -> CUresult;
+    #[link_name = "cuMemcpyDtoH_v2"]
+    pub(crate) fn cuMemcpyDtoH(dst: *mut c_void, src: CUdeviceptr, size: usize) -> CUresult;
+    #[link_name = "cuMemcpyHtoD_v2"]
+    pub(crate) fn cuMemcpyHtoD(dst: CUdeviceptr, src: *const c_void, size: usize) -> CUresult;
+    #[link_name = "cuMemcpyDtoD_v2"]
+    pub(crate) fn cuMemcpyDtoD(dst: CUdeviceptr, src: CUdeviceptr, size: usize) -> CUresult;
+    #[link_name = "cuMemcpyDtoHAsync_v2"]
+    pub(crate) fn cuMemcpyDtoHAsync(
+        dst: *mut c_void,
+        src: CUdeviceptr,
+        size: usize,
+        stream: CUstream,
+    ) -> CUresult;
+    pub(crate) fn cuModuleLoadData(module: *mut CUmodule, image: *const c_void) -> CUresult;
+    pub(crate) fn cuModuleUnload(module: CUmodule) -> CUresult;
+    pub(crate) fn cuModuleGetFunction(
+        function: *mut CUfunction,
+        module: CUmodule,
+        name: *const c_char,
+    ) -> CUresult;
+    #[link_name = "cuModuleGetGlobal_v2"]
+    pub(crate) fn cuModuleGetGlobal(
+        ptr: *mut CUdeviceptr,
+        size: *mut usize,
+        module: CUmodule,
+        name: *const c_char,
+    ) -> CUresult;
+    pub(crate) fn cuLaunchKernel(
+        function: CUfunction,
+        grid_x: c_uint,
+        grid_y: c_uint,
+        grid_z: c_uint,
+        block_x: c_uint,
+        block_y: c_uint,
+        block_z: c_uint,
+        shared_memory: c_uint,
+        stream: CUstream,
+        params: *mut *mut c_void,
+        extra: *mut *mut c_void,
+    ) -> CUresult;
+    pub(crate) fn cuStreamCreate(stream: *mut CUstream, flags: c_uint) -> CUresult;
+    #[link_name = "cuStreamDestroy_v2"]
+    pub(crate) fn cuStreamDestroy(stream: CUstream) -> CUresult;
+    pub(crate) fn cuStreamSynchronize(stream: CUstream) -> CUresult;
+    pub(crate) fn cuEventCreate(event: *mut CUevent, flags: c_uint) -> CUresult;
+    pub(crate) fn cuEventRecord(event: CUevent, stream: CUstream) -> CUresult;
+    #[link_name = "cuEventDestroy_v2"]
+    pub(crate) fn cuEventDestroy(event: CUevent) -> CUresult;
+    pub(crate) fn cuEventSynchronize(event: CUevent) -> CUresult;
+    pub(crate) fn cuEventQuery(event: CUevent) -> CUresult;
+    pub(crate) fn cuEventElapsedTime(ms: *mut f32, start: CUevent, end: CUevent) -> CUresult;
+    pub(crate) fn cuLaunchHostFunc(
+        stream: CUstream,
+        function: CUhostFn,
+        data: *mut c_void,
+    ) -> CUresult;
+    pub(crate) fn cuFuncGetAttribute(
+        value: *mut c_int,
+        attribute: c_int,
+        function: CUfunction,
+    ) -> CUresult;
+    pub(crate) fn cuOccupancyMaxPotentialBlockSize(
+        grid_size: *mut c_int,
+        block_size: *mut c_int,
+        function: CUfunction,
+        dynamic_shared_memory_fn: CUoccupancyB2DSize,
+        dynamic_shared_memory: usize,
+        block_size_limit: c_int,
+    ) -> CUresult;
+    pub(crate) fn cuOccupancyMaxActiveBlocksPerMultiprocessor(
+        blocks: *mut c_int,
+        function: CUfunction,
+        block_size: c_int,
+        dynamic_shared_memory: usize,
+    ) -> CUresult;
+}
+
+/// Get the name of a result code, e.g. `CUDA_ERROR_OUT_OF_MEMORY`.
+pub(crate) fn error_name(error: CUresult) -> Option<&'static str> {
+    static_string(|s| unsafe { cuGetErrorName(error, s) })
+}
+
+/// Get the description of a result code.
+pub(crate) fn error_string(error: CUresult) -> Option<&'static str> {
+    static_string(|s| unsafe { cuGetErrorString(error, s) })
+}
+
+/// Call a driver function that returns a static string, `None` if it fails.
+fn static_string(f: impl FnOnce(*mut *const c_char) -> CUresult) -> Option<&'static str> {
+    let mut string = std::ptr::null();
+    if f(&mut string) != CUresult::SUCCESS || string.is_null() {
+        return None;
+    }
+    // SAFETY: The driver returns nul-terminated strings that live as long as the process
+    unsafe { std::ffi::CStr::from_ptr(string) }.to_str().ok()
+}
+
+/// Initialize the CUDA driver.
+///
+/// The result is cached, so this can be called before every use of the driver.
+pub(crate) fn init_driver() -> Result<(), GpuError> {
+    static RESULT: OnceLock<CUresult> = OnceLock::new();
+    let result = *RESULT.get_or_init(|| unsafe { cuInit(0) });
+    check(result, "cuInit", None)
+}
+
+/// Initialize the CUDA driver and make sure this thread has a current context.
+///
+/// Threads without a context use the primary context of device 0, like the CUDA runtime.
+pub(crate) fn init() -> Result<(), GpuError> {
+    init_driver()?;
+    let mut context = std::ptr::null_mut();
+    unsafe {
+        check(cuCtxGetCurrent(&mut context), "cuCtxGetCurrent", None)?;
+    }
+    if context.is_null() {
+        set_device(0)?;
+    }
+    Ok(())
+}
+
+/// The maximum number of devices that [`set_device`] supports.
+pub(crate) const MAX_DEVICES: usize = 64;
+
+/// Make the primary context of a device current on this thread.
+///
+/// The primary context is retained once and kept alive until the process exits.
+/// This does not allocate, so it can be used in the global allocator.
+/// The retained contexts are cached in a fixed-size array, devices with an index of
+/// [`MAX_DEVICES`] or higher return `CUDA_ERROR_NOT_SUPPORTED`.
+pub(crate) fn set_device(index: c_int) -> Result<(), GpuError> {
+    /// Retained primary contexts, indexed by device.
+    static CONTEXTS: [AtomicPtr<c_void>; MAX_DEVICES] =
+        [const { AtomicPtr::new(std::ptr::null_mut()) }; MAX_DEVICES];
+
+    init_driver()?;
+    let Some(cached) = usize::try_from(index).ok().and_then(|i| CONTEXTS.get(i)) else {
+        // Retaining without caching would retain the context again on every call
+        return check(CUresult::NOT_SUPPORTED, "cuDevicePrimaryCtxRetain", None);
+    };
+    let mut context = cached.load(Ordering::Acquire);
+    if context.is_null() {
+        unsafe {
+            let mut device = 0;
+            check(cuDeviceGet(&mut device, index), "cuDeviceGet", None)?;
+            let result = cuDevicePrimaryCtxRetain(&mut context, device);
+            check(result, "cuDevicePrimaryCtxRetain", None)?;
+        }
+        // Retaining twice in a race returns the same context, it stays alive either way
+        cached.store(context, Ordering::Release);
+    }
+    unsafe { check(cuCtxSetCurrent(context), "cuCtxSetCurrent", None) }
+}
@@ -0,0 +1,331 @@
+use crate::GpuError;
+#[cfg(feature = "nvidia")]
+use crate::cuda;
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use crate::error::check;
+#[cfg(feature = "hip")]
+use crate::runtime::runtime;
+
+/// A GPU in the system.
+///
+/// Each host thread has a current device, which is used for launches that do not specify a
+/// device or stream.
+/// Kernels are loaded separately on every device they are launched on.
+///
+/// # Example
+///
+/// ```no_run
+/// # use gpu_kernel::{Device, LaunchConfig};
+/// for device in Device::all().unwrap() {
+///     let props = device.properties().unwrap();
+///     println!("Device {}: {} ({})", device.index(), props.name, props.arch);
+///     let launch_config = LaunchConfig::new()
+///         .workgroups([1, 1, 1])
+///         .threads_per_workgroup([32, 1, 1])
+///         .device(device)
+///         .clone();
+///     // Launch kernels with `launch_config`…
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Device {
+    index: u32,
+}
+
+/// Information about a [`Device`], see [`Device::properties`].
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DeviceProperties {
+    /// The marketing name of the GPU, e.g. `AMD Radeon RX 7900 XTX`.
+    pub name: String,
+    /// The architecture name including target features, e.g. `gfx1100` or `gfx90a:sramecc+:xnack-`.
+    ///
+    /// For NVIDIA GPUs, this is the compute capability, e.g. `sm_80`.
+    pub arch: String,
+    /// The architecture name without target features, e.g. `gfx90a` or `sm_80`.
+    ///
+    /// Use this as `target-cpu` to compile kernels for the device.
+    pub gfx: String,
+    /// If XNACK (retrying memory accesses on page faults) is enabled.
+    ///
+    /// `None` if the device does not report the feature.
+    pub xnack: Option<bool>,
+    /// If SRAM ECC (error correction for on-chip memory) is enabled.
+    ///
+    /// `None` if the device does not report the feature.
+    pub sramecc: Option<bool>,
+    /// The number of threads in a wavefront, usually 32 or 64.
+    pub wave_size: u32,
+    /// The number of compute units.
+    pub compute_units: u32,
+    /// The maximum number of threads that can be resident on a compute unit.
+    pub max_threads_per_compute_unit: u32,
+    /// The maximum total number of threads in a workgroup.
+    pub max_threads_per_workgroup: u32,
+    /// The maximum number of threads in a workgroup for each dimension.
+    pub max_workgroup_size: [u32; 3],
+    /// The maximum number of workgroups in a launch for each dimension.
+    pub max_workgroups: [u32; 3],
+    /// The amount of shared memory (LDS) available to a workgroup in bytes.
+    pub shared_memory_per_workgroup: usize,
+    /// The amount of global memory (VRAM) in bytes.
+    pub total_memory: usize,
+    /// The size of the L2 cache in bytes.
+    pub l2_cache_size: usize,
+    /// If the device supports managed memory, see [`ManagedMemAlloc`](crate::ManagedMemAlloc).
+    pub managed_memory: bool,
+    /// If the CPU and the device can access managed memory concurrently.
+    pub concurrent_managed_access: bool,
+    /// If the device can access pageable CPU memory that was not allocated through the runtime.
+    pub pageable_memory_access: bool,
+    /// If the device is integrated, sharing memory with the CPU.
+    pub integrated: bool,
+}
+
+impl Device {
+    /// The number of available GPUs.
+    ///
+    /// Returns `0` if there is no GPU.
+    pub fn count() -> Result<u32, GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut count = 0;
+            let result = runtime().get_device_count(&mut count);
+            if result == crate::hip::hipError_t::hipErrorNoDevice {
+                return Ok(0);
+            }
+            check(result, "hipGetDeviceCount", None)?;
+            Ok(count as u32)
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            match cuda::init_driver() {
+                Err(GpuError::Cuda { error, .. })
+                    if error.code() == cuda::CUresult::NO_DEVICE.0 =>
+                {
+                    return Ok(0);
+                }
+                result => result?,
+            }
+            let mut count = 0;
+            check(cuda::cuDeviceGetCount(&mut count), "cuDeviceGetCount", None)?;
+            Ok(count as u32)
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Ok(0)
+    }
+
+    /// All available GPUs.
+    pub fn all() -> Result<Vec<Self>, GpuError> {
+        Ok((0..Self::count()?).map(|index| Self { index }).collect())
+    }
+
+    /// Get the device with the given index.
+    ///
+    /// Returns an error if there is no such device.
+    pub fn get(index: u32) -> Result<Self, GpuError> {
+        if index >= Self::count()? {
+            return Err(GpuError::DeviceNotFound(index));
+        }
+        Ok(Self { index })
+    }
+
+    /// The current device of this thread.
+    pub fn current() -> Result<Self, GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut index = 0;
+            let result = runtime().get_device(&mut index);
+            check(result, "hipGetDevice", None)?;
+            Ok(Self {
+                index: index as u32,
+            })
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            cuda::init()?;
+            let mut index = 0;
+            check(cuda::cuCtxGetDevice(&mut index), "cuCtxGetDevice", None)?;
+            Ok(Self {
+                index: index as u32,
+            })
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Err(GpuError::NoRuntime)
+    }
+
+    /// Make this the current device of this thread.
+    ///
+    /// Launches without a device or stream in their `LaunchConfig` use the current device.
+    pub fn set_current(&self) -> Result<(), GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let result = runtime().set_device(self.index as i32);
+            check(result, "hipSetDevice", None)?;
+        }
+        #[cfg(feature = "nvidia")]
+        cuda::set_device(self.index as i32)?;
+        Ok(())
+    }
+
+    /// The index of this device, as used by the GPU runtime.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Query information about this device.
+    pub fn properties(&self) -> Result<DeviceProperties, GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut props: crate::hip::hipDeviceProp_t = std::mem::zeroed();
+            let result = runtime().get_device_properties(&mut props, self.index as i32);
+            check(result, "hipGetDeviceProperties", None)?;
+            Ok(DeviceProperties::from_raw(&props))
+        }
+        #[cfg(feature = "nvidia")]
+        {
+            cuda::init_driver()?;
+            DeviceProperties::from_cuda(self.index as i32)
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Err(GpuError::NoRuntime)
+    }
+
+    /// Run `f` with this as the current device and restore the previous device afterwards.
+    pub(crate) fn with_current<R>(
+        &self,
+        f: impl FnOnce() -> Result<R, GpuError>,
+    ) -> Result<R, GpuError> {
+        let previous = Self::current()?;
+        if previous == *self {
+            return f();
+        }
+        self.set_current()?;
+        let result = f();
+        previous.set_current()?;
+        result
+    }
+}
+
+impl DeviceProperties {
+    #[cfg(feature = "hip")]
+    fn from_raw(props: &crate::hip::hipDeviceProp_t) -> Self {
+        let arch = c_str(&props.gcnArchName);
+        let (gfx, xnack, sramecc) = parse_arch(&arch);
+        let dims = |d: [i32; 3]| d.map(|d| d as u32);
+        Self {
+            name: c_str(&props.name),
+            gfx,
+            xnack,
+            sramecc,
+            arch,
+            wave_size: props.warpSize as u32,
+            compute_units: props.multiProcessorCount as u32,
+            max_threads_per_compute_unit: props.maxThreadsPerMultiProcessor as u32,
+            max_threads_per_workgroup: props.maxThreadsPerBlock as u32,
+            max_workgroup_size: dims(props.maxThreadsDim),
+            max_workgroups: dims(props.maxGridSize),
+            shared_memory_per_workgroup: props.sharedMemPerBlock,
+            total_memory: props.totalGlobalMem,
+            l2_cache_size: props.l2CacheSize as usize,
+            managed_memory: props.managedMemory != 0,
+            concurrent_managed_access: props.concurrentManagedAccess != 0,
+            pageable_memory_access: props.pageableMemoryAccess != 0,
+            integrated: props.integrated != 0,
+        }
+    }
+}
+
+#[cfg(feature = "nvidia")]
+impl DeviceProperties {
+    fn from_cuda(device: cuda::CUdevice) -> Result<Self, GpuError> {
+        use cuda::*;
+
+        let attribute = |attribute| {
+            let mut value = 0;
+            let result = unsafe { cuDeviceGetAttribute(&mut value, attribute, device) };
+            check(result, "cuDeviceGetAttribute", None).map(|()| value)
+        };
+        let mut name = [0; 256];
+        let mut total_memory = 0;
+        unsafe {
+            let result = cuDeviceGetName(name.as_mut_ptr(), name.len() as i32, device);
+            check(result, "cuDeviceGetName", None)?;
+            let result = cuDeviceTotalMem(&mut total_memory, device);
+            check(result, "cuDeviceTotalMem_v2", None)?;
+        }
+        // NVIDIA GPUs are identified by their compute capability, e.g. sm_80
+        let arch = format!(
+            "sm_{}{}",
+            attribute(CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR)?,
+            attribute(CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR)?
+        );
+        Ok(Self {
+            name: c_str(&name),
+            gfx: arch.clone(),
+            xnack: None,
+            sramecc: None,
+            arch,
+            wave_size: attribute(CU_DEVICE_ATTRIBUTE_WARP_SIZE)? as u32,
+            compute_units: attribute(CU_DEVICE_ATTRIBUTE_MULTIPROCESSOR_COUNT)? as u32,
+            max_threads_per_compute_unit: attribute(
+                CU_DEVICE_ATTRIBUTE_MAX_THREADS_PER_MULTIPROCESSOR,
+            )? as u32,
+            max_threads_per_workgroup: attribute(CU_DEVICE_ATTRIBUTE_MAX_THREADS_PER_BLOCK)? as u32,
+            max_workgroup_size: [
+                attribute(CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_X)? as u32,
+                attribute(CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_Y)? as u32,
+                attribute(CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_Z)? as u32,
+            ],
+            max_workgroups: [
+                attribute(CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_X)? as u32,
+                attribute(CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_Y)? as u32,
+                attribute(CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_Z)? as u32,
+            ],
+            shared_memory_per_workgroup: attribute(CU_DEVICE_ATTRIBUTE_MAX_SHARED_MEMORY_PER_BLOCK)?
+                as usize,
+            total_memory,
+            l2_cache_size: attribute(CU_DEVICE_ATTRIBUTE_L2_CACHE_SIZE)? as usize,
+            managed_memory: attribute(CU_DEVICE_ATTRIBUTE_MANAGED_MEMORY)? != 0,
+            concurrent_managed_access: attribute(CU_DEVICE_ATTRIBUTE_CONCURRENT_MANAGED_ACCESS)?
+                != 0,
+            pageable_memory_access: attribute(CU_DEVICE_ATTRIBUTE_PAGEABLE_MEMORY_ACCESS)? != 0,
+            integrated: attribute(CU_DEVICE_ATTRIBUTE_INTEGRATED)? != 0,
+        })
+    }
+}
+
+/// Split an architecture name like `gfx90a:sramecc+:xnack-` into the gfx version and the xnack
+/// and sramecc features.
+#[cfg_attr(not(feature = "hip"), allow(dead_code))]
+fn parse_arch(arch: &str) -> (String, Option<bool>, Option<bool>) {
+    let mut parts = arch.split(':');
+    let gfx = parts.next().unwrap_or_default().to_string();
+    let (mut xnack, mut sramecc) = (None, None);
+    for feature in parts {
+        let (name, enabled) = if let Some(name) = feature.strip_suffix('+') {
+            (name, true)
+        } else if let Some(name) = feature.strip_suffix('-') {
+            (name, false)
+        } else {
+            continue;
+        };
+        match name {
+            "xnack" => xnack = Some(enabled),
+            "sramecc" => sramecc = Some(enabled),
+            _ => {}
+        }
+    }
+    (gfx, xnack, sramecc)
+}
+
+/// Convert a nul-terminated string from a runtime struct.
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+fn c_str(s: &[std::ffi::c_char]) -> String {
+    let bytes = s
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
@@ -0,0 +1,579 @@
+use std::marker::PhantomData;
+use std::ops::{Bound, Range, RangeBounds};
+use std::ptr::NonNull;
+
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use crate::Event;
+#[cfg(feature = "nvidia")]
+use crate::cuda;
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use crate::error::check;
+#[cfg(feature = "hip")]
+use crate::runtime::runtime;
+use crate::{Device, GpuError, LaunchHandle, Stream};
+
+/// A typed buffer in GPU memory that is copied to and from the CPU explicitly.
+///
+/// Unlike a [`GpuBox`](crate::GpuBox), the memory is never accessed by the CPU directly, which
+/// works on every system and keeps kernels fast.
+/// Data is moved with [`Self::copy_from_host`] and [`Self::copy_to_host`], parts of the buffer are
+/// accessed through [`DeviceSlice`] and [`DeviceSliceMut`] views.
+///
+/// Pass `&buffer` to a kernel argument of type `&[T]` and `&mut buffer` to a
+/// [`ThreadIndexedSlice<T>`](crate::ThreadIndexedSlice).
+/// With the `cpu` feature, kernels run on CPU threads that cannot access GPU memory, so buffers
+/// cannot be passed to kernels and the example below does not compile.
+///
+/// The buffer is allocated on the current device and freed when it is dropped.
+///
+/// # Example
+///
+#[cfg_attr(not(feature = "cpu"), doc = "```no_run")]
+#[cfg_attr(feature = "cpu", doc = "```ignore")]
+/// use gpu_kernel::{DeviceBuffer, LaunchConfig, ThreadIndexedSlice, kernel};
+///
+/// gpu_kernel::kernel_lib!();
+///
+/// #[kernel]
+/// fn double(a: &[u32], mut b: ThreadIndexedSlice<'_, u32>) {
+///     let id = gpu_kernel::intrinsics::global_id()[0];
+///     *b.get_mut() = a[id] * 2;
+/// }
+///
+/// # fn main() -> Result<(), gpu_kernel::GpuError> {
+/// let a = DeviceBuffer::from_slice(&[1u32, 2, 3, 4]);
+/// let mut b = DeviceBuffer::from_slice(&[0u32; 4]);
+/// let launch_config = LaunchConfig::new()
+///     .workgroups([1, 1, 1])
+///     .threads_per_workgroup([4, 1, 1])
+///     .clone();
+/// double.launch(&launch_config, &a, &mut b);
+/// assert_eq!(b.to_vec()?, [2, 4, 6, 8]);
+/// # Ok(())
+/// # }
+/// ```
+pub struct DeviceBuffer<T> {
+    ptr: *mut T,
+    len: usize,
+    device: Device,
+}
+
+// SAFETY: The buffer owns its elements like a `Vec<T>`
+unsafe impl<T: Send> Send for DeviceBuffer<T> {}
+unsafe impl<T: Sync> Sync for DeviceBuffer<T> {}
+
+/// A shared view of a range of a [`DeviceBuffer`].
+///
+/// Passed to a kernel argument of type `&[T]`.
+pub struct DeviceSlice<'a, T> {
+    ptr: *const T,
+    len: usize,
+    phantom: PhantomData<&'a [T]>,
+}
+
+// SAFETY: The view borrows its elements like a `&[T]`
+unsafe impl<T: Sync> Send for DeviceSlice<'_, T> {}
+unsafe impl<T: Sync> Sync for DeviceSlice<'_, T> {}
+
+/// A mutable view of a range of a [`DeviceBuffer`].
+///
+/// Passed to a kernel argument of type [`ThreadIndexedSlice<T>`](crate::ThreadIndexedSlice) as
+/// `&mut slice`.
+pub struct DeviceSliceMut<'a, T> {
+    ptr: *mut T,
+    len: usize,
+    phantom: PhantomData<&'a mut [T]>,
+}
+
+// SAFETY: The view borrows its elements like a `&mut [T]`
+unsafe impl<T: Send> Send for DeviceSliceMut<'_, T> {}
+unsafe impl<T: Sync> Sync for DeviceSliceMut<'_, T> {}
+
+/// Resolve a range of elements, panicking like slice indexing if it is out of bounds.
+fn resolve(range: impl RangeBounds<usize>, len: usize) -> Range<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.checked_add(1).expect("Range start overflows"),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end.checked_add(1).expect("Range end overflows"),
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    assert!(start <= end, "Range starts at {start} but ends at {end}");
+    assert!(
+        end <= len,
+        "Range end {end} is out of range for a device slice of length {len}"
+    );
+    start..end
+}
+
+impl<T> DeviceBuffer<T> {
+    /// Allocate an uninitialized buffer for `len` elements on the current device.
+    fn try_alloc(len: usize) -> Result<Self, GpuError> {
+        let device = Device::current()?;
+        let size = size_of::<T>()
+            .checked_mul(len)
+            .expect("DeviceBuffer capacity overflow");
+        // The runtimes cannot allocate zero bytes
+        if size == 0 {
+            return Ok(Self {
+                ptr: NonNull::dangling().as_ptr(),
+                len,
+                device,
+            });
+        }
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut ptr = std::ptr::null_mut();
+            check(runtime().malloc(&mut ptr, size), "hipMalloc", None)?;
+            Ok(Self {
+                ptr: ptr.cast(),
+                len,
+                device,
+            })
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let mut ptr = 0;
+            check(cuda::cuMemAlloc(&mut ptr, size), "cuMemAlloc_v2", None)?;
+            Ok(Self {
+                ptr: ptr as *mut T,
+                len,
+                device,
+            })
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Err(GpuError::NoRuntime)
+    }
+
+    /// The number of elements in the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the buffer has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The device the buffer is allocated on.
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    /// Get the address of the buffer on the device.
+    ///
+    /// The pointer must not be dereferenced on the CPU.
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr
+    }
+
+    /// Get the mutable address of the buffer on the device, e.g. to pass it to a kernel.
+    ///
+    /// The pointer must not be dereferenced on the CPU.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.ptr
+    }
+
+    /// View the whole buffer.
+    pub fn as_slice(&self) -> DeviceSlice<'_, T> {
+        self.slice(..)
+    }
+
+    /// View the whole buffer mutably.
+    pub fn as_mut_slice(&mut self) -> DeviceSliceMut<'_, T> {
+        self.slice_mut(..)
+    }
+
+    /// View a range of elements of the buffer.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> DeviceSlice<'_, T> {
+        let range = resolve(range, self.len);
+        DeviceSlice {
+            ptr: self.ptr.wrapping_add(range.start),
+            len: range.len(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// View a range of elements of the buffer mutably.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn slice_mut(&mut self, range: impl RangeBounds<usize>) -> DeviceSliceMut<'_, T> {
+        let range = resolve(range, self.len);
+        DeviceSliceMut {
+            ptr: self.ptr.wrapping_add(range.start),
+            len: range.len(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: Copy> DeviceBuffer<T> {
+    /// Allocate a buffer on the current device and copy `data` into it.
+    ///
+    /// Panics if allocating or copying fails, see [`Self::try_from_slice`] for a non-panicking
+    /// variant.
+    pub fn from_slice(data: &[T]) -> Self {
+        Self::try_from_slice(data).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Allocate a buffer on the current device and copy `data` into it.
+    pub fn try_from_slice(data: &[T]) -> Result<Self, GpuError> {
+        let mut buffer = Self::try_alloc(data.len())?;
+        buffer.copy_from_host(data)?;
+        Ok(buffer)
+    }
+
+    /// Copy `src` from the CPU into the buffer.
+    ///
+    /// Panics if `src` has a different length than the buffer.
+    pub fn copy_from_host(&mut self, src: &[T]) -> Result<(), GpuError> {
+        self.as_mut_slice().copy_from_host(src)
+    }
+
+    /// Copy the buffer into `dst` on the CPU.
+    ///
+    /// Panics if `dst` has a different length than the buffer.
+    pub fn copy_to_host(&self, dst: &mut [T]) -> Result<(), GpuError> {
+        self.as_slice().copy_to_host(dst)
+    }
+
+    /// Copy the buffer into a new `Vec` on the CPU.
+    pub fn to_vec(&self) -> Result<Vec<T>, GpuError> {
+        self.as_slice().to_vec()
+    }
+
+    /// Queue a copy of the buffer into `dst` on `stream`, without waiting for it to finish.
+    ///
+    /// See [`DeviceSlice::copy_to_host_async`].
+    ///
+    /// # Safety
+    ///
+    /// The returned handle must not be leaked, e.g. with [`std::mem::forget`].
+    pub unsafe fn copy_to_host_async<'a>(
+        &'a self,
+        dst: &'a mut [T],
+        stream: &Stream,
+    ) -> Result<LaunchHandle<'a>, GpuError> {
+        unsafe { self.as_slice().copy_to_host_async(dst, stream) }
+    }
+
+    /// Copy the contents of another buffer or view on the GPU into this buffer.
+    ///
+    /// Panics if `src` has a different length than the buffer.
+    pub fn copy_from_device(&mut self, src: DeviceSlice<'_, T>) -> Result<(), GpuError> {
+        self.as_mut_slice().copy_from_device(src)
+    }
+}
+
+impl<T> Drop for DeviceBuffer<T> {
+    fn drop(&mut self) {
+        #[cfg(any(feature = "hip", feature = "nvidia"))]
+        if size_of::<T>() == 0 || self.len == 0 {
+            return;
+        }
+        #[cfg(feature = "hip")]
+        {
+            // Errors cannot be reported when dropping
+            let _ = self.device.with_current(|| unsafe {
+                check(runtime().free(self.ptr.cast()), "hipFree", None)
+            });
+        }
+        #[cfg(feature = "nvidia")]
+        {
+            // Errors cannot be reported when dropping
+            let _ = self.device.with_current(|| unsafe {
+                check(cuda::cuMemFree(self.ptr as u64), "cuMemFree_v2", None)
+            });
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for DeviceBuffer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceBuffer")
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .field("device", &self.device)
+            .finish()
+    }
+}
+
+impl<'a, T> DeviceSlice<'a, T> {
+    /// The number of elements in the view.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the view has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the address of the view on the device.
+    ///
+    /// The pointer must not be dereferenced on the CPU.
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr
+    }
+
+    /// View a range of elements of this view.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn slice(self, range: impl RangeBounds<usize>) -> Self {
+        let range = resolve(range, self.len);
+        Self {
+            ptr: self.ptr.wrapping_add(range.start),
+            len: range.len(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: Copy> DeviceSlice<'a, T> {
+    /// Copy the view into `dst` on the CPU.
+    ///
+    /// Panics if `dst` has a different length than the view.
+    pub fn copy_to_host(&self, dst: &mut [T]) -> Result<(), GpuError> {
+        assert_eq!(
+            self.len,
+            dst.len(),
+            "Destination slice has a different length than the device slice"
+        );
+        unsafe { self.copy_to_ptr(dst.as_mut_ptr()) }
+    }
+
+    /// Copy the view into a new `Vec` on the CPU.
+    pub fn to_vec(&self) -> Result<Vec<T>, GpuError> {
+        let mut data = Vec::with_capacity(self.len);
+        unsafe {
+            self.copy_to_ptr(data.as_mut_ptr())?;
+            data.set_len(self.len);
+        }
+        Ok(data)
+    }
+
+    /// Copy all elements to `dst`, which must be valid for writing `self.len` elements.
+    unsafe fn copy_to_ptr(&self, dst: *mut T) -> Result<(), GpuError> {
+        let size = size_of::<T>() * self.len;
+        if size == 0 {
+            return Ok(());
+        }
+        #[cfg(feature = "hip")]
+        unsafe {
+            let result = runtime().memcpy_dtoh(dst.cast(), self.ptr.cast_mut().cast(), size);
+            check(result, "hipMemcpyDtoH", None)
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            cuda::init()?;
+            let result = cuda::cuMemcpyDtoH(dst.cast(), self.ptr as u64, size);
+            check(result, "cuMemcpyDtoH_v2", None)
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        {
+            let _ = dst;
+            Err(GpuError::NoRuntime)
+        }
+    }
+
+    /// Queue a copy of the view into `dst` on `stream`, without waiting for it to finish.
+    ///
+    /// Wait for the copy with [`LaunchHandle::wait`] or `.await` the handle.
+    /// The handle borrows the view and `dst` until the copy finished, dropping it blocks until
+    /// then.
+    /// The copy only runs in the background if `dst` is pinned memory, e.g. a
+    /// [`PinnedVec`](crate::PinnedVec), otherwise the runtime may copy before returning.
+    ///
+    /// Panics if `dst` has a different length than the view.
+    ///
+    /// # Safety
+    ///
+    /// The returned handle must not be leaked, e.g. with [`std::mem::forget`].
+    /// Otherwise `dst` can be accessed while the copy is still writing into it.
+    pub unsafe fn copy_to_host_async(
+        self,
+        dst: &'a mut [T],
+        stream: &Stream,
+    ) -> Result<LaunchHandle<'a>, GpuError> {
+        assert_eq!(
+            self.len,
+            dst.len(),
+            "Destination slice has a different length than the device slice"
+        );
+        #[cfg(any(feature = "hip", feature = "nvidia"))]
+        {
+            let size = size_of_val(dst);
+            stream.device().with_current(|| {
+                let event = Event::try_new_without_timing()?;
+                if size != 0 {
+                    #[cfg(feature = "hip")]
+                    unsafe {
+                        let result = runtime().memcpy_dtoh_async(
+                            dst.as_mut_ptr().cast(),
+                            self.ptr.cast_mut().cast(),
+                            size,
+                            stream.raw(),
+                        );
+                        check(result, "hipMemcpyDtoHAsync", None)?;
+                    }
+                    #[cfg(feature = "nvidia")]
+                    unsafe {
+                        let result = cuda::cuMemcpyDtoHAsync(
+                            dst.as_mut_ptr().cast(),
+                            self.ptr as u64,
+                            size,
+                            stream.raw(),
+                        );
+                        check(result, "cuMemcpyDtoHAsync_v2", None)?;
+                    }
+                }
+                // Mark the end of the copy in the stream
+                event.record(stream)?;
+                LaunchHandle::copy(event, stream.clone())
+            })
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        {
+            let _ = stream;
+            Err(GpuError::NoRuntime)
+        }
+    }
+}
+
+impl<T> Clone for DeviceSlice<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for DeviceSlice<'_, T> {}
+
+impl<T> std::fmt::Debug for DeviceSlice<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceSlice")
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<T> DeviceSliceMut<'_, T> {
+    /// The number of elements in the view.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the view has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the address of the view on the device.
+    ///
+    /// The pointer must not be dereferenced on the CPU.
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr
+    }
+
+    /// Get the mutable address of the view on the device, e.g. to pass it to a kernel.
+    ///
+    /// The pointer must not be dereferenced on the CPU.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.ptr
+    }
+
+    /// View this view as shared, e.g. to copy it to the CPU.
+    pub fn as_slice(&self) -> DeviceSlice<'_, T> {
+        DeviceSlice {
+            ptr: self.ptr,
+            len: self.len,
+            phantom: PhantomData,
+        }
+    }
+
+    /// View a range of elements of this view mutably.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn slice_mut(&mut self, range: impl RangeBounds<usize>) -> DeviceSliceMut<'_, T> {
+        let range = resolve(range, self.len);
+        DeviceSliceMut {
+            ptr: self.ptr.wrapping_add(range.start),
+            len: range.len(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: Copy> DeviceSliceMut<'_, T> {
+    /// Copy `src` from the CPU into the view.
+    ///
+    /// Panics if `src` has a different length than the view.
+    pub fn copy_from_host(&mut self, src: &[T]) -> Result<(), GpuError> {
+        assert_eq!(
+            self.len,
+            src.len(),
+            "Source slice has a different length than the device slice"
+        );
+        let size = size_of_val(src);
+        if size == 0 {
+            return Ok(());
+        }
+        #[cfg(feature = "hip")]
+        unsafe {
+            let result =
+                runtime().memcpy_htod(self.ptr.cast(), src.as_ptr().cast_mut().cast(), size);
+            check(result, "hipMemcpyHtoD", None)
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            cuda::init()?;
+            let result = cuda::cuMemcpyHtoD(self.ptr as u64, src.as_ptr().cast(), size);
+            check(result, "cuMemcpyHtoD_v2", None)
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Err(GpuError::NoRuntime)
+    }
+
+    /// Copy the contents of another view on the GPU into this view.
+    ///
+    /// The source can be on a different device.
+    ///
+    /// Panics if `src` has a different length than the view.
+    pub fn copy_from_device(&mut self, src: DeviceSlice<'_, T>) -> Result<(), GpuError> {
+        assert_eq!(
+            self.len, src.len,
+            "Source slice has a different length than the device slice"
+        );
+        let size = size_of::<T>() * self.len;
+        if size == 0 {
+            return Ok(());
+        }
+        #[cfg(feature = "hip")]
+        unsafe {
+            let result = runtime().memcpy_dtod(self.ptr.cast(), src.ptr.cast_mut().cast(), size);
+            check(result, "hipMemcpyDtoD", None)
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            cuda::init()?;
+            let result = cuda::cuMemcpyDtoD(self.ptr as u64, src.ptr as u64, size);
+            check(result, "cuMemcpyDtoD_v2", None)
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Err(GpuError::NoRuntime)
+    }
+}
+
+impl<T> std::fmt::Debug for DeviceSliceMut<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceSliceMut")
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
@@ -0,0 +1,351 @@
+use std::fmt;
+
+/// An error code returned by the HIP runtime.
+///
+/// Common codes get their own variant, all other codes are reported as [`HipError::Other`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum HipError {
+    /// One or more of the parameters passed to the call are invalid (`hipErrorInvalidValue`).
+    InvalidValue,
+    /// Not enough memory is available (`hipErrorOutOfMemory`).
+    OutOfMemory,
+    /// The HIP runtime is not initialized (`hipErrorNotInitialized`).
+    NotInitialized,
+    /// The device ordinal is invalid (`hipErrorInvalidDevice`).
+    InvalidDevice,
+    /// No GPU was found (`hipErrorNoDevice`).
+    NoDevice,
+    /// The loaded binary is not a valid code object (`hipErrorInvalidImage`).
+    InvalidImage,
+    /// The loaded binary contains no code for the current GPU (`hipErrorNoBinaryForGpu`).
+    NoBinaryForGpu,
+    /// A handle like a stream or module is invalid (`hipErrorInvalidHandle`).
+    InvalidHandle,
+    /// A named symbol, e.g. a kernel, was not found (`hipErrorNotFound`).
+    NotFound,
+    /// An asynchronous operation is not yet finished (`hipErrorNotReady`).
+    NotReady,
+    /// The launch configuration is invalid (`hipErrorInvalidConfiguration`).
+    InvalidConfiguration,
+    /// The launch requests more resources than available (`hipErrorLaunchOutOfResources`).
+    LaunchOutOfResources,
+    /// The kernel crashed while executing (`hipErrorLaunchFailure`).
+    LaunchFailure,
+    /// The operation is not supported on this system (`hipErrorNotSupported`).
+    NotSupported,
+    /// Any other error, contains the raw `hipError_t` value.
+    Other(i32),
+}
+
+/// An error code returned by the CUDA driver with the `nvidia` feature.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct CudaError {
+    code: i32,
+}
+
+/// An error returned when using the GPU fails.
+///
+/// Functions that panic on errors usually have a `try_` counterpart that returns this error instead.
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GpuError {
+    /// A call to the HIP runtime failed.
+    Hip {
+        /// The name of the HIP function that failed, e.g. `hipModuleLaunchKernel`.
+        operation: &'static str,
+        /// The name of the kernel that was used in the operation, if any.
+        kernel: Option<String>,
+        /// The error returned by the runtime.
+        error: HipError,
+    },
+    /// A call to the CUDA driver failed.
+    Cuda {
+        /// The name of the CUDA function that failed, e.g. `cuLaunchKernel`.
+        operation: &'static str,
+        /// The name of the kernel that was used in the operation, if any.
+        kernel: Option<String>,
+        /// The error returned by the driver.
+        error: CudaError,
+    },
+    /// A kernel name cannot be passed to the runtime as it contains a nul byte.
+    InvalidKernelName(String),
+    /// There is no device with the given index.
+    DeviceNotFound(u32),
+    /// The `LaunchConfig` cannot be used for launching, contains a description of the problem.
+    InvalidLaunchConfig(String),
+    /// A code object or its metadata cannot be parsed, contains a description of the problem.
+    InvalidCodeObject(String),
+    /// The arguments passed to a kernel do not match the arguments expected by the compiled
+    /// kernel, contains a description of the problem.
+    KernelArgumentMismatch(String),
+    /// A module file cannot be loaded, contains a description of the problem.
+    ModuleFile(String),
+    /// A global variable in the module has a different size than the type on the CPU.
+    GlobalSizeMismatch {
+        /// The name of the global variable.
+        name: String,
+        /// The size of the type on the CPU.
+        expected: usize,
+        /// The size of the variable in the module.
+        actual: usize,
+    },
+    /// A kernel running on the CPU with the `cpu` feature panicked.
+    KernelPanicked {
+        /// The name of the kernel.
+        kernel: String,
+        /// The panic message.
+        message: String,
+    },
+    /// No GPU runtime is enabled, the `amd` or `nvidia` feature is needed to use GPUs.
+    NoRuntime,
+    /// A module contains no code object that runs on the device.
+    NoCodeObjectForDevice {
+        /// The architecture of the device including target features, e.g. `gfx90a:xnack-`.
+        arch: String,
+        /// The targets contained in the module.
+        available: Vec<String>,
+        /// If the module was compiled with `kernel_lib!()` and embedded into the executable, so
+        /// it can be rebuilt for more GPUs.
+        embedded: bool,
+    },
+}
+
+impl HipError {
+    fn description(&self) -> &'static str {
+        match self {
+            Self::InvalidValue => "invalid argument",
+            Self::OutOfMemory => "out of memory",
+            Self::NotInitialized => "runtime not initialized",
+            Self::InvalidDevice => "invalid device ordinal",
+            Self::NoDevice => "no GPU available",
+            Self::InvalidImage => "invalid code object",
+            Self::NoBinaryForGpu => "no code object for the current GPU",
+            Self::InvalidHandle => "invalid handle",
+            Self::NotFound => "symbol not found",
+            Self::NotReady => "operation not ready",
+            Self::InvalidConfiguration => "invalid launch configuration",
+            Self::LaunchOutOfResources => "too many resources requested for launch",
+            Self::LaunchFailure => "kernel execution failed",
+            Self::NotSupported => "operation not supported",
+            Self::Other(_) => "unknown error",
+        }
+    }
+}
+
+impl fmt::Display for HipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Self::Other(code) = self {
+            write!(f, "{} ({code})", self.description())
+        } else {
+            write!(f, "{} ({self:?})", self.description())
+        }
+    }
+}
+
+impl std::error::Error for HipError {}
+
+#[cfg(feature = "hip")]
+impl From<crate::hip::hipError_t> for HipError {
+    fn from(error: crate::hip::hipError_t) -> Self {
+        use crate::hip::hipError_t;
+
+        match error {
+            hipError_t::hipErrorInvalidValue => Self::InvalidValue,
+            hipError_t::hipErrorOutOfMemory => Self::OutOfMemory,
+            hipError_t::hipErrorNotInitialized => Self::NotInitialized,
+            hipError_t::hipErrorInvalidDevice => Self::InvalidDevice,
+            hipError_t::hipErrorNoDevice => Self::NoDevice,
+            hipError_t::hipErrorInvalidImage => Self::InvalidImage,
+            hipError_t::hipErrorNoBinaryForGpu => Self::NoBinaryForGpu,
+            hipError_t::hipErrorInvalidHandle => Self::InvalidHandle,
+            hipError_t::hipErrorNotFound => Self::NotFound,
+            hipError_t::hipErrorNotReady => Self::NotReady,
+            hipError_t::hipErrorInvalidConfiguration => Self::InvalidConfiguration,
+            hipError_t::hipErrorLaunchOutOfResources => Self::LaunchOutOfResources,
+            hipError_t::hipErrorLaunchFailure => Self::LaunchFailure,
+            hipError_t::hipErrorNotSupported => Self::NotSupported,
+            e => Self::Other(e as i32),
+        }
+    }
+}
+
+impl CudaError {
+    /// The raw `CUresult` value.
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    /// The name of the error code, e.g. `CUDA_ERROR_OUT_OF_MEMORY`, if the driver knows it.
+    pub fn name(&self) -> Option<&'static str> {
+        #[cfg(feature = "nvidia")]
+        {
+            crate::cuda::error_name(crate::cuda::CUresult(self.code))
+        }
+        #[cfg(not(feature = "nvidia"))]
+        None
+    }
+
+    /// The description of the error code, if the driver knows it.
+    fn description(&self) -> Option<&'static str> {
+        #[cfg(feature = "nvidia")]
+        {
+            crate::cuda::error_string(crate::cuda::CUresult(self.code))
+        }
+        #[cfg(not(feature = "nvidia"))]
+        None
+    }
+}
+
+impl fmt::Display for CudaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = self.description().unwrap_or("unknown error");
+        match self.name() {
+            Some(name) => write!(f, "{description} ({name})"),
+            None => write!(f, "{description} ({})", self.code),
+        }
+    }
+}
+
+impl std::error::Error for CudaError {}
+
+#[cfg(feature = "nvidia")]
+impl From<crate::cuda::CUresult> for CudaError {
+    fn from(error: crate::cuda::CUresult) -> Self {
+        Self { code: error.0 }
+    }
+}
+
+impl GpuError {
+    /// Mark a missing code object as coming from a module that was embedded by `kernel_lib!()`.
+    pub(crate) fn in_embedded_module(mut self) -> Self {
+        if let Self::NoCodeObjectForDevice { embedded, .. } = &mut self {
+            *embedded = true;
+        }
+        self
+    }
+
+    /// Attach the name of the kernel that the failed operation belongs to.
+    pub(crate) fn with_kernel(mut self, name: &str) -> Self {
+        if let Self::Hip { kernel, .. } | Self::Cuda { kernel, .. } = &mut self
+            && kernel.is_none()
+        {
+            *kernel = Some(name.to_string());
+        }
+        self
+    }
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hip {
+                operation,
+                kernel,
+                error,
+            } => {
+                write!(f, "{operation} failed")?;
+                if let Some(kernel) = kernel {
+                    write!(f, " for kernel `{kernel}`")?;
+                }
+                write!(f, ": {error}")
+            }
+            Self::Cuda {
+                operation,
+                kernel,
+                error,
+            } => {
+                write!(f, "{operation} failed")?;
+                if let Some(kernel) = kernel {
+                    write!(f, " for kernel `{kernel}`")?;
+                }
+                write!(f, ": {error}")
+            }
+            Self::InvalidKernelName(name) => write!(f, "Invalid kernel name {name:?}"),
+            Self::DeviceNotFound(index) => write!(f, "No device with index {index}"),
+            Self::InvalidLaunchConfig(reason) => write!(f, "Invalid launch config: {reason}"),
+            Self::InvalidCodeObject(reason) => write!(f, "Invalid code object: {reason}"),
+            Self::KernelArgumentMismatch(reason) => write!(f, "Kernel argument mismatch: {reason}"),
+            Self::ModuleFile(reason) => write!(f, "Cannot load module file: {reason}"),
+            Self::GlobalSizeMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Global variable `{name}` has {actual} bytes on the GPU but {expected} bytes on the CPU"
+            ),
+            Self::KernelPanicked { kernel, message } => {
+                write!(f, "Kernel `{kernel}` panicked: {message}")
+            }
+            Self::NoRuntime => write!(
+                f,
+                "No GPU runtime is enabled, enable the `amd` or `nvidia` feature of gpu-kernel"
+            ),
+            Self::NoCodeObjectForDevice {
+                arch,
+                available,
+                embedded,
+            } => {
+                write!(f, "No code object for {arch} in the module")?;
+                if available.is_empty() {
+                    write!(f, ", it contains no AMD GPU code objects")?;
+                } else {
+                    write!(f, ", it contains code for {}", available.join(", "))?;
+                }
+                if *embedded {
+                    // Target features are not part of the target CPU
+                    let gfx = arch.split(':').next().unwrap_or(arch);
+                    write!(f, ". Add {gfx} to CARGO_TARGET_AMDGCN_AMD_AMDHSA_CPUS")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for GpuError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Hip { error, .. } => Some(error),
+            Self::Cuda { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Convert a HIP result into a `Result`, attaching the failing operation.
+#[cfg(feature = "hip")]
+pub(crate) fn check(
+    result: crate::hip::hipError_t,
+    operation: &'static str,
+    kernel: Option<&str>,
+) -> Result<(), GpuError> {
+    if result == crate::hip::hipError_t::hipSuccess {
+        Ok(())
+    } else {
+        Err(GpuError::Hip {
+            operation,
+            kernel: kernel.map(str::to_string),
+            error: result.into(),
+        })
+    }
+}
+
+/// Convert a CUDA result into a `Result`, attaching the failing operation.
+#[cfg(feature = "nvidia")]
+pub(crate) fn check(
+    result: crate::cuda::CUresult,
+    operation: &'static str,
+    kernel: Option<&str>,
+) -> Result<(), GpuError> {
+    if result == crate::cuda::CUresult::SUCCESS {
+        Ok(())
+    } else {
+        Err(GpuError::Cuda {
+            operation,
+            kernel: kernel.map(str::to_string),
+            error: result.into(),
+        })
+    }
+}
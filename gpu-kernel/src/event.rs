@@ -0,0 +1,220 @@
+use std::time::Duration;
+
+#[cfg(feature = "nvidia")]
+use crate::cuda;
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use crate::error::check;
+#[cfg(feature = "hip")]
+use crate::runtime::runtime;
+use crate::{GpuError, Stream};
+
+/// A marker in a [`Stream`] that can be waited on and used to measure time on the GPU.
+///
+/// # Example
+///
+/// ```no_run
+/// # use gpu_kernel::{Event, Stream};
+/// let stream = Stream::new();
+/// let start = Event::new();
+/// let end = Event::new();
+/// start.record(&stream).unwrap();
+/// // Launch kernels on `stream`…
+/// end.record(&stream).unwrap();
+/// end.synchronize().unwrap();
+/// println!("Took {:?}", start.elapsed_time(&end).unwrap());
+/// ```
+pub struct Event {
+    #[cfg(feature = "hip")]
+    event: crate::hip::hipEvent_t,
+    #[cfg(feature = "nvidia")]
+    event: cuda::CUevent,
+}
+unsafe impl Send for Event {}
+unsafe impl Sync for Event {}
+
+/// GPU execution times measured by the `bench` function that the `#[kernel]` macro adds.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct BenchResult {
+    /// The number of measured launches, excluding warm-up.
+    pub iterations: u32,
+    /// The fastest launch.
+    pub min: Duration,
+    /// The median of all launches.
+    pub median: Duration,
+    /// The slowest launch.
+    pub max: Duration,
+}
+
+impl Event {
+    /// Create a new event that records timing information.
+    ///
+    /// Panics if creating the event fails, see [`Self::try_new`] for a non-panicking variant.
+    pub fn new() -> Self {
+        Self::try_new().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a new event that records timing information.
+    pub fn try_new() -> Result<Self, GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut event: crate::hip::hipEvent_t = std::ptr::null_mut();
+            let result = runtime().event_create(&mut event);
+            check(result, "hipEventCreate", None)?;
+            Ok(Self { event })
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            cuda::init()?;
+            let mut event = std::ptr::null_mut();
+            check(cuda::cuEventCreate(&mut event, 0), "cuEventCreate", None)?;
+            Ok(Self { event })
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Err(GpuError::NoRuntime)
+    }
+
+    /// Create an event that is used for synchronization only, without timing information.
+    pub(crate) fn try_new_without_timing() -> Result<Self, GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut event: crate::hip::hipEvent_t = std::ptr::null_mut();
+            let result =
+                runtime().event_create_with_flags(&mut event, crate::hip::hipEventDisableTiming);
+            check(result, "hipEventCreateWithFlags", None)?;
+            Ok(Self { event })
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            cuda::init()?;
+            let mut event = std::ptr::null_mut();
+            let result = cuda::cuEventCreate(&mut event, cuda::CU_EVENT_DISABLE_TIMING);
+            check(result, "cuEventCreate", None)?;
+            Ok(Self { event })
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Err(GpuError::NoRuntime)
+    }
+
+    /// Queue the event in the stream.
+    ///
+    /// The event completes when all work that was queued in the stream before finished.
+    /// Recording an event again overwrites the previous recording.
+    #[cfg_attr(not(any(feature = "hip", feature = "nvidia")), allow(unused_variables))]
+    pub fn record(&self, stream: &Stream) -> Result<(), GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let result = runtime().event_record(self.event, stream.raw());
+            check(result, "hipEventRecord", None)?;
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let result = cuda::cuEventRecord(self.event, stream.raw());
+            check(result, "cuEventRecord", None)?;
+        }
+        Ok(())
+    }
+
+    /// Block until the event completed.
+    pub fn synchronize(&self) -> Result<(), GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let result = runtime().event_synchronize(self.event);
+            check(result, "hipEventSynchronize", None)?;
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let result = cuda::cuEventSynchronize(self.event);
+            check(result, "cuEventSynchronize", None)?;
+        }
+        Ok(())
+    }
+
+    /// Check if the event completed without blocking.
+    pub fn is_done(&self) -> Result<bool, GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let result = runtime().event_query(self.event);
+            if result == crate::hip::hipError_t::hipErrorNotReady {
+                return Ok(false);
+            }
+            check(result, "hipEventQuery", None)?;
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let result = cuda::cuEventQuery(self.event);
+            if result == cuda::CUresult::NOT_READY {
+                return Ok(false);
+            }
+            check(result, "cuEventQuery", None)?;
+        }
+        Ok(true)
+    }
+
+    /// The GPU time between this event and the `end` event.
+    ///
+    /// Both events must have completed.
+    pub fn elapsed_time(&self, end: &Event) -> Result<Duration, GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut ms = 0f32;
+            let result = runtime().event_elapsed_time(&mut ms, self.event, end.event);
+            check(result, "hipEventElapsedTime", None)?;
+            Ok(Duration::from_secs_f32(ms.max(0.0) / 1000.0))
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let mut ms = 0f32;
+            let result = cuda::cuEventElapsedTime(&mut ms, self.event, end.event);
+            check(result, "cuEventElapsedTime", None)?;
+            Ok(Duration::from_secs_f32(ms.max(0.0) / 1000.0))
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        {
+            let _ = end;
+            Err(GpuError::NoRuntime)
+        }
+    }
+
+    /// Get the raw HIP event.
+    #[cfg(feature = "hip")]
+    pub fn raw(&self) -> crate::hip::hipEvent_t {
+        self.event
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let result = runtime().event_destroy(self.event);
+            assert_eq!(result, crate::hip::hipError_t::hipSuccess);
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let result = cuda::cuEventDestroy(self.event);
+            assert_eq!(result, cuda::CUresult::SUCCESS);
+        }
+    }
+}
+
+impl BenchResult {
+    /// Summarize a list of measured times.
+    ///
+    /// `times` must not be empty, callers reject zero iterations before measuring.
+    pub(crate) fn from_times(mut times: Vec<Duration>) -> Self {
+        times.sort_unstable();
+        Self {
+            iterations: times.len() as u32,
+            min: times[0],
+            median: times[times.len() / 2],
+            max: times[times.len() - 1],
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+#[cfg(feature = "nvidia")]
+use crate::cuda;
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use crate::error::check;
+#[cfg(feature = "hip")]
+use crate::runtime::runtime;
+use crate::{Device, GpuError, LazyModule, Module};
+
+/// A global variable on the GPU that is accessed from the CPU.
+///
+/// The `#[global]` macro declares a handle for every static, which dereferences to a
+/// `LazyGlobal`.
+/// The module containing the variable is loaded on first use for each device and every device
+/// has its own copy of the variable.
+/// With the `cpu` feature, kernels share the variable with the CPU and the handle accesses it
+/// directly instead of dereferencing to a `LazyGlobal`.
+///
+/// # Example
+///
+/// ```no_run
+/// use gpu_kernel::{Device, global};
+///
+/// gpu_kernel::kernel_lib!();
+///
+/// #[global]
+/// static mut SEED: u64 = 0;
+///
+/// # fn main() -> Result<(), gpu_kernel::GpuError> {
+/// // Set a different seed on every device
+/// for device in Device::all()? {
+///     device.set_current()?;
+///     unsafe { SEED.write(&(device.index() as u64))? };
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct LazyGlobal<T> {
+    module: &'static LazyModule,
+    name: &'static str,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> LazyGlobal<T> {
+    /// Create a global variable that is loaded from `module` on first use.
+    #[doc(hidden)]
+    pub const fn new(module: &'static LazyModule, name: &'static str) -> Self {
+        Self {
+            module,
+            name,
+            _type: PhantomData,
+        }
+    }
+
+    /// Get the name of the variable in the compiled binary.
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// Get the module that contains the variable, e.g. to reload it.
+    pub fn module(&self) -> &'static LazyModule {
+        self.module
+    }
+
+    /// Get the address of the variable on the current device, e.g. to pass it to a kernel.
+    ///
+    /// The pointer is only valid while the module is loaded.
+    /// After reloading the module, the variable has a new address and the initial value.
+    pub fn as_ptr(&self) -> Result<*mut T, GpuError> {
+        self.as_ptr_on(Device::current()?)
+    }
+
+    /// Get the address of the variable on a device, loading the module if it is not loaded yet.
+    ///
+    /// See [`Self::as_ptr`].
+    pub fn as_ptr_on(&self, device: Device) -> Result<*mut T, GpuError> {
+        self.lookup(device).map(|(_, ptr)| ptr)
+    }
+
+    /// Get the loaded module and the address of the variable in it.
+    fn lookup(&self, device: Device) -> Result<(Arc<Module>, *mut T), GpuError> {
+        let module = self.module.get(device)?;
+        let (ptr, size) = module.get_global(self.name)?;
+        if size != size_of::<T>() {
+            return Err(GpuError::GlobalSizeMismatch {
+                name: self.name.to_string(),
+                expected: size_of::<T>(),
+                actual: size,
+            });
+        }
+        Ok((module, ptr.cast()))
+    }
+}
+
+impl<T: Copy> LazyGlobal<T> {
+    /// Copy the value of the variable from the current device.
+    ///
+    /// # Safety
+    ///
+    /// The variable must have the same layout on the CPU and the GPU and no kernel may write it
+    /// while it is read.
+    #[doc(hidden)]
+    pub unsafe fn read_impl(&self) -> Result<T, GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            // Keep the module loaded while copying
+            let (_module, ptr) = self.lookup(Device::current()?)?;
+            let mut value = std::mem::MaybeUninit::<T>::uninit();
+            let result =
+                runtime().memcpy_dtoh(value.as_mut_ptr().cast(), ptr.cast(), size_of::<T>());
+            check(result, "hipMemcpyDtoH", None)?;
+            Ok(value.assume_init())
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let (_module, ptr) = self.lookup(Device::current()?)?;
+            let mut value = std::mem::MaybeUninit::<T>::uninit();
+            let result = cuda::cuMemcpyDtoH(value.as_mut_ptr().cast(), ptr as u64, size_of::<T>());
+            check(result, "cuMemcpyDtoH_v2", None)?;
+            Ok(value.assume_init())
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Err(GpuError::NoRuntime)
+    }
+
+    /// Copy a new value of the variable to the current device.
+    ///
+    /// # Safety
+    ///
+    /// The variable must have the same layout on the CPU and the GPU, it must be mutable on the
+    /// GPU and no kernel may access it while it is written.
+    #[doc(hidden)]
+    pub unsafe fn write_impl(&self, value: &T) -> Result<(), GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let (_module, ptr) = self.lookup(Device::current()?)?;
+            let result = runtime().memcpy_htod(
+                ptr.cast(),
+                (value as *const T).cast_mut().cast(),
+                size_of::<T>(),
+            );
+            check(result, "hipMemcpyHtoD", None)
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let (_module, ptr) = self.lookup(Device::current()?)?;
+            let result = cuda::cuMemcpyHtoD(ptr as u64, (value as *const T).cast(), size_of::<T>());
+            check(result, "cuMemcpyHtoD_v2", None)
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        {
+            let _ = value;
+            Err(GpuError::NoRuntime)
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! The types and constants of the HIP runtime that gpu-kernel uses.
+//!
+//! With the `amd` feature, they come from `hip-runtime-sys`, which needs the HIP headers and
+//! links the HIP library.
+//! The `mock` feature alone replaces the runtime without needing HIP, so the types are declared
+//! here with the same names and values.
+
+#[cfg(all(feature = "amd", feature = "mock"))]
+pub(crate) use hip_runtime_sys::hipEventDefault;
+#[cfg(feature = "amd")]
+pub(crate) use hip_runtime_sys::{
+    hipDeviceProp_t, hipDeviceptr_t, hipError_t, hipEvent_t, hipEventDisableTiming,
+    hipFunction_attribute, hipFunction_t, hipHostFn_t, hipHostMallocDefault, hipMemAttachGlobal,
+    hipModule_t, hipStream_t,
+};
+
+#[cfg(not(feature = "amd"))]
+pub(crate) use types::*;
+
+#[cfg(not(feature = "amd"))]
+// Mirrors the C API, so not all of it is used
+#[allow(
+    dead_code,
+    non_camel_case_types,
+    non_snake_case,
+    non_upper_case_globals,
+    clippy::enum_variant_names
+)]
+mod types {
+    use std::ffi::{c_char, c_int, c_void};
+
+    #[repr(u32)]
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum hipError_t {
+        hipSuccess = 0,
+        hipErrorInvalidValue = 1,
+        hipErrorOutOfMemory = 2,
+        hipErrorNotInitialized = 3,
+        hipErrorInvalidConfiguration = 9,
+        hipErrorNoDevice = 100,
+        hipErrorInvalidDevice = 101,
+        hipErrorInvalidImage = 200,
+        hipErrorNoBinaryForGpu = 209,
+        hipErrorInvalidHandle = 400,
+        hipErrorNotFound = 500,
+        hipErrorNotReady = 600,
+        hipErrorLaunchOutOfResources = 701,
+        hipErrorLaunchFailure = 719,
+        hipErrorNotSupported = 801,
+        hipErrorUnknown = 999,
+    }
+
+    #[repr(u32)]
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum hipFunction_attribute {
+        HIP_FUNC_ATTRIBUTE_MAX_THREADS_PER_BLOCK = 0,
+        HIP_FUNC_ATTRIBUTE_SHARED_SIZE_BYTES = 1,
+        HIP_FUNC_ATTRIBUTE_CONST_SIZE_BYTES = 2,
+        HIP_FUNC_ATTRIBUTE_LOCAL_SIZE_BYTES = 3,
+        HIP_FUNC_ATTRIBUTE_NUM_REGS = 4,
+        HIP_FUNC_ATTRIBUTE_PTX_VERSION = 5,
+        HIP_FUNC_ATTRIBUTE_BINARY_VERSION = 6,
+        HIP_FUNC_ATTRIBUTE_CACHE_MODE_CA = 7,
+        HIP_FUNC_ATTRIBUTE_MAX_DYNAMIC_SHARED_SIZE_BYTES = 8,
+        HIP_FUNC_ATTRIBUTE_PREFERRED_SHARED_MEMORY_CARVEOUT = 9,
+        HIP_FUNC_ATTRIBUTE_MAX = 10,
+    }
+
+    /// The fields of `hipDeviceProp_t` that gpu-kernel reads.
+    #[derive(Clone, Copy, Debug)]
+    pub struct hipDeviceProp_t {
+        pub name: [c_char; 256],
+        pub totalGlobalMem: usize,
+        pub sharedMemPerBlock: usize,
+        pub warpSize: c_int,
+        pub maxThreadsPerBlock: c_int,
+        pub maxThreadsDim: [c_int; 3],
+        pub maxGridSize: [c_int; 3],
+        pub multiProcessorCount: c_int,
+        pub l2CacheSize: c_int,
+        pub maxThreadsPerMultiProcessor: c_int,
+        pub gcnArchName: [c_char; 256],
+        pub integrated: c_int,
+        pub managedMemory: c_int,
+        pub concurrentManagedAccess: c_int,
+        pub pageableMemoryAccess: c_int,
+    }
+
+    pub struct ihipEvent_t {
+        _unused: [u8; 0],
+    }
+    pub struct ihipModule_t {
+        _unused: [u8; 0],
+    }
+    pub struct ihipModuleSymbol_t {
+        _unused: [u8; 0],
+    }
+    pub struct ihipStream_t {
+        _unused: [u8; 0],
+    }
+
+    pub type hipDeviceptr_t = *mut c_void;
+    pub type hipEvent_t = *mut ihipEvent_t;
+    pub type hipFunction_t = *mut ihipModuleSymbol_t;
+    pub type hipHostFn_t = Option<unsafe extern "C" fn(userData: *mut c_void)>;
+    pub type hipModule_t = *mut ihipModule_t;
+    pub type hipStream_t = *mut ihipStream_t;
+
+    pub const hipEventDefault: u32 = 0;
+    pub const hipEventDisableTiming: u32 = 2;
+    pub const hipHostMallocDefault: u32 = 0;
+    pub const hipMemAttachGlobal: u32 = 1;
+}
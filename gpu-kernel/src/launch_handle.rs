@@ -0,0 +1,264 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use std::sync::Mutex;
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use std::task::Waker;
+use std::task::{Context, Poll};
+
+#[cfg(feature = "nvidia")]
+use crate::cuda;
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use crate::error::check;
+#[cfg(feature = "hip")]
+use crate::runtime::runtime;
+use crate::{Event, GpuError, ModuleHandle, Stream};
+
+/// A kernel launch or memory copy that may still be running on the GPU.
+///
+/// Returned by the `launch_async` function that the `#[kernel]` macro adds and by
+/// [`DeviceBuffer::copy_to_host_async`](crate::DeviceBuffer::copy_to_host_async).
+/// The handle borrows the kernel arguments, so they cannot be modified or freed while the kernel
+/// is running.
+///
+/// Wait for the kernel with [`Self::wait`], or `.await` the handle in async code.
+/// Awaiting does not block the thread, it works with any async runtime.
+///
+/// Dropping the handle blocks until the kernel finished.
+///
+/// # Example
+///
+/// ```no_run
+/// use gpu_kernel::{GpuError, LaunchConfig, kernel};
+///
+/// gpu_kernel::kernel_lib!();
+///
+/// #[kernel]
+/// fn kernel(s: &str) {
+///     println!("Hello {s}!");
+/// }
+///
+/// async fn run() -> Result<(), GpuError> {
+///     let s = "World".to_string();
+///     let launch_config = LaunchConfig::new()
+///         .threads_per_workgroup([10, 1, 1])
+///         .workgroups([1, 1, 1])
+///         .clone();
+///     // SAFETY: The handle is not leaked
+///     unsafe { kernel.launch_async(&launch_config, &s) }?.await
+/// }
+/// # fn main() {}
+/// ```
+#[must_use = "dropping a `LaunchHandle` blocks until the kernel finished"]
+pub struct LaunchHandle<'a> {
+    /// `None` if the kernel finished before the handle was created.
+    pending: Option<PendingLaunch>,
+    /// `None` for memory copies.
+    kernel: Option<String>,
+    waited: bool,
+    /// Shared with the callback that is queued on the stream behind the work.
+    #[cfg(any(feature = "hip", feature = "nvidia"))]
+    completion: Option<Arc<Completion>>,
+    phantom: PhantomData<&'a mut ()>,
+}
+
+/// A kernel that was queued in the GPU runtime.
+struct PendingLaunch {
+    /// Completes when the kernel finished.
+    event: Event,
+    #[cfg_attr(not(any(feature = "hip", feature = "nvidia")), allow(dead_code))]
+    stream: Stream,
+    /// Keeps the module loaded until the kernel finished, `None` for memory copies.
+    _module: Option<Arc<ModuleHandle>>,
+}
+
+/// State shared with the host function that is called by the GPU runtime once the kernel finished.
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+struct Completion {
+    done: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// Called by the GPU runtime when all work before it in the stream finished.
+///
+/// Must not call into the GPU runtime.
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+unsafe extern "C" fn completion_callback(data: *mut std::ffi::c_void) {
+    // Take back the reference that was leaked when queuing the callback
+    let completion = unsafe { Arc::from_raw(data as *const Completion) };
+    completion.done.store(true, Ordering::Release);
+    let waker = completion.waker.lock().unwrap().take();
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+impl LaunchHandle<'_> {
+    /// Create a handle for a kernel that was queued on `stream` and completes with `event`.
+    pub(crate) fn new(
+        event: Event,
+        stream: Stream,
+        kernel: String,
+        module: Arc<ModuleHandle>,
+    ) -> Result<Self, GpuError> {
+        let pending = PendingLaunch {
+            event,
+            stream,
+            _module: Some(module),
+        };
+        Self::queued(pending, Some(kernel))
+    }
+
+    /// Create a handle for a memory copy that was queued on `stream` and completes with `event`.
+    #[cfg(any(feature = "hip", feature = "nvidia"))]
+    pub(crate) fn copy(event: Event, stream: Stream) -> Result<Self, GpuError> {
+        let pending = PendingLaunch {
+            event,
+            stream,
+            _module: None,
+        };
+        Self::queued(pending, None)
+    }
+
+    /// Create a handle for queued work.
+    ///
+    /// Queues the callback that wakes awaiting tasks right behind the work, so awaiting does not
+    /// wait for work that is queued on the stream later.
+    fn queued(pending: PendingLaunch, kernel: Option<String>) -> Result<Self, GpuError> {
+        #[cfg(any(feature = "hip", feature = "nvidia"))]
+        let completion = Arc::new(Completion {
+            done: AtomicBool::new(false),
+            waker: Mutex::new(None),
+        });
+        #[cfg(any(feature = "hip", feature = "nvidia"))]
+        let stream = pending.stream.raw();
+        let handle = Self {
+            pending: Some(pending),
+            kernel,
+            waited: false,
+            #[cfg(any(feature = "hip", feature = "nvidia"))]
+            completion: Some(completion.clone()),
+            phantom: PhantomData,
+        };
+
+        // The callback owns one reference
+        #[cfg(any(feature = "hip", feature = "nvidia"))]
+        unsafe {
+            let data = Arc::into_raw(completion) as *mut std::ffi::c_void;
+            #[cfg(feature = "hip")]
+            let result = check(
+                runtime().launch_host_func(stream, Some(completion_callback), data),
+                "hipLaunchHostFunc",
+                handle.kernel.as_deref(),
+            );
+            #[cfg(feature = "nvidia")]
+            let result = check(
+                cuda::cuLaunchHostFunc(stream, Some(completion_callback), data),
+                "cuLaunchHostFunc",
+                handle.kernel.as_deref(),
+            );
+            if let Err(e) = result {
+                // The callback will never run, free its reference
+                drop(Arc::from_raw(data as *const Completion));
+                // Dropping the handle waits for the work, so its arguments stay valid until then
+                drop(handle);
+                return Err(e);
+            }
+        }
+        Ok(handle)
+    }
+
+    /// Create a handle for a kernel that already finished.
+    #[cfg(feature = "cpu")]
+    pub(crate) fn finished(kernel: String) -> Self {
+        Self {
+            pending: None,
+            kernel: Some(kernel),
+            waited: true,
+            #[cfg(any(feature = "hip", feature = "nvidia"))]
+            completion: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Block until the kernel or copy finished.
+    ///
+    /// Returns an error if the kernel or copy failed.
+    pub fn wait(mut self) -> Result<(), GpuError> {
+        self.wait_impl()
+    }
+
+    /// Check if the kernel or copy finished without blocking.
+    pub fn is_done(&self) -> Result<bool, GpuError> {
+        let Some(pending) = self.pending.as_ref().filter(|_| !self.waited) else {
+            return Ok(true);
+        };
+        pending.event.is_done().map_err(|e| self.with_kernel(e))
+    }
+
+    /// Attach the kernel name to an error, if this handle belongs to a kernel.
+    fn with_kernel(&self, error: GpuError) -> GpuError {
+        match &self.kernel {
+            Some(kernel) => error.with_kernel(kernel),
+            None => error,
+        }
+    }
+
+    fn wait_impl(&mut self) -> Result<(), GpuError> {
+        let Some(pending) = self.pending.as_ref().filter(|_| !self.waited) else {
+            return Ok(());
+        };
+        self.waited = true;
+        pending.event.synchronize().map_err(|e| self.with_kernel(e))
+    }
+}
+
+impl Future for LaunchHandle<'_> {
+    type Output = Result<(), GpuError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        let _ = cx;
+        #[cfg(any(feature = "hip", feature = "nvidia"))]
+        if !this.waited
+            && let Some(completion) = this.completion.clone()
+        {
+            // The callback sets `done` before taking the waker, so checking under the lock cannot
+            // miss a wake-up
+            let stored = {
+                let mut waker = completion.waker.lock().unwrap();
+                let done = completion.done.load(Ordering::Acquire);
+                if !done && !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                    *waker = Some(cx.waker().clone());
+                }
+                !done
+            };
+            if stored {
+                // Failed work does not always run the callback, so check the event as well
+                match this.is_done() {
+                    Ok(true) => {}
+                    Ok(false) => return Poll::Pending,
+                    Err(e) => {
+                        this.waited = true;
+                        return Poll::Ready(Err(e));
+                    }
+                }
+            }
+        }
+        // Finished, this does not block anymore but reports errors
+        Poll::Ready(this.wait_impl())
+    }
+}
+
+impl Drop for LaunchHandle<'_> {
+    fn drop(&mut self) {
+        // Arguments must not be freed while the kernel or copy is running.
+        // Errors are reported by `wait`, ignore them here.
+        let _ = self.wait_impl();
+    }
+}
@@ -0,0 +1,383 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::{ArgLayout, BenchResult, Device, GpuError, Kernel, LaunchConfig, LaunchHandle, Module};
+
+/// A compiled GPU binary that is loaded on first use for each device.
+///
+/// Declared by `kernel_lib!()`, get it through [`LazyKernel::module`].
+///
+/// The module can be reloaded while the program is running, e.g. to try changes in GPU code
+/// without restarting.
+/// Launches after a reload use the new kernels, kernels that are still running keep the old module
+/// loaded until they finished.
+///
+/// # Example
+///
+/// ```no_run
+/// use gpu_kernel::{LaunchConfig, kernel};
+///
+/// gpu_kernel::kernel_lib!();
+///
+/// #[kernel]
+/// fn kernel() {}
+///
+/// # fn main() -> Result<(), gpu_kernel::GpuError> {
+/// loop {
+///     // Pick up the new GPU code after running `cargo build`
+///     kernel
+///         .module()
+///         .reload_if_changed("target/gpu-kernel/amdgcn-amd-amdhsa/debug/my_crate.elf")?;
+///     kernel.launch(&LaunchConfig::for_elements(1, 1));
+/// }
+/// # }
+/// ```
+pub struct LazyModule {
+    source: ModuleSource,
+    state: Mutex<ModuleState>,
+    /// Incremented on every reload, kernels loaded before are reloaded on their next use.
+    generation: AtomicU64,
+}
+
+/// The mutable state of a [`LazyModule`].
+struct ModuleState {
+    /// The binary of the last reload, replaces the source.
+    reloaded: Option<Vec<u8>>,
+    /// The modification time of the file of the last reload.
+    modified: Option<SystemTime>,
+    /// Loaded modules, indexed by device.
+    modules: Vec<Option<Arc<Module>>>,
+}
+
+/// Where the binary of a [`LazyModule`] comes from.
+enum ModuleSource {
+    /// Embedded into the executable.
+    Embedded(&'static [u8]),
+    /// A precompiled file that is shipped alongside the executable.
+    File {
+        /// The file name.
+        name: &'static str,
+        /// The checksum of the file that the executable was built against.
+        checksum: u64,
+    },
+}
+
+/// A GPU kernel that is loaded on first use for each device.
+///
+/// The `#[kernel]` macro declares a `LazyKernel` for every kernel.
+/// Launches pick the device from the [`LaunchConfig`], see [`LaunchConfig::device`].
+///
+/// # Example
+///
+/// ```no_run
+/// use gpu_kernel::{Device, kernel};
+///
+/// gpu_kernel::kernel_lib!();
+///
+/// #[kernel]
+/// fn kernel() {}
+///
+/// # fn main() -> Result<(), gpu_kernel::GpuError> {
+/// // Load the kernel on all devices up front
+/// for device in Device::all()? {
+///     kernel.get(device)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct LazyKernel {
+    module: &'static LazyModule,
+    name: &'static str,
+    /// Loaded kernels, indexed by device.
+    kernels: Mutex<Vec<Option<LoadedKernel>>>,
+}
+
+/// A kernel loaded from a generation of the module.
+struct LoadedKernel {
+    generation: u64,
+    kernel: Arc<Kernel>,
+}
+
+/// Get the entry for a device in a list indexed by device, growing the list if needed.
+fn entry<T>(list: &mut Vec<Option<T>>, device: Device) -> &mut Option<T> {
+    let index = device.index() as usize;
+    if list.len() <= index {
+        list.resize_with(index + 1, || None);
+    }
+    &mut list[index]
+}
+
+impl LazyModule {
+    /// Create a module from a binary, without loading it yet.
+    #[doc(hidden)]
+    pub const fn new(data: &'static [u8]) -> Self {
+        Self::with_source(ModuleSource::Embedded(data))
+    }
+
+    /// Create a module from a precompiled file, without loading it yet.
+    ///
+    /// The file is searched in `$GPU_KERNEL_MODULE_DIR` or next to the executable and needs to
+    /// match `checksum`.
+    #[doc(hidden)]
+    pub const fn from_file(name: &'static str, checksum: u64) -> Self {
+        Self::with_source(ModuleSource::File { name, checksum })
+    }
+
+    const fn with_source(source: ModuleSource) -> Self {
+        Self {
+            source,
+            state: Mutex::new(ModuleState {
+                reloaded: None,
+                modified: None,
+                modules: Vec::new(),
+            }),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the module for a device, loading it if it is not loaded yet.
+    pub fn get(&self, device: Device) -> Result<Arc<Module>, GpuError> {
+        self.get_with_generation(device).map(|(_, module)| module)
+    }
+
+    /// Get the module for a device and the generation it belongs to.
+    fn get_with_generation(&self, device: Device) -> Result<(u64, Arc<Module>), GpuError> {
+        let mut state = self.state.lock().unwrap();
+        let generation = self.generation.load(Ordering::Acquire);
+        let reloaded = state.reloaded.as_deref();
+        let loaded = match (&state.modules.get(device.index() as usize), reloaded) {
+            (Some(Some(module)), _) => return Ok((generation, module.clone())),
+            (_, Some(data)) => Module::try_new_on(data, device)?,
+            _ => match self.source {
+                ModuleSource::Embedded(data) => {
+                    Module::try_new_on(data, device).map_err(GpuError::in_embedded_module)?
+                }
+                ModuleSource::File { name, checksum } => {
+                    let module = Module::try_new_on(&read_precompiled(name, checksum)?, device)?;
+                    self.check_declared_kernels(&module, name)?;
+                    module
+                }
+            },
+        };
+        let module = entry(&mut state.modules, device).insert(Arc::new(loaded));
+        Ok((generation, module.clone()))
+    }
+
+    /// Check that a precompiled module contains all kernels declared with `#[kernel]` for it.
+    ///
+    /// The checksum only detects that the file changed, this catches kernels that were added or
+    /// renamed without rebuilding the module, before the first launch fails.
+    fn check_declared_kernels(&self, module: &Module, name: &str) -> Result<(), GpuError> {
+        let declared = crate::kernels()
+            .iter()
+            .filter(|d| std::ptr::eq(d.kernel.lazy_kernel().module(), self));
+        for descriptor in declared {
+            if let Err(e) = module.try_get_kernel(descriptor.name) {
+                return Err(GpuError::ModuleFile(format!(
+                    "{name} does not contain the kernel `{}` declared at {}:{}, rebuild the \
+                     module or ship the matching module: {e}",
+                    descriptor.name, descriptor.file, descriptor.line
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the module with a new binary.
+    ///
+    /// The binary is loaded right away on all devices that the module is loaded on.
+    /// If that fails, the old module is kept.
+    /// All kernels use the new module on their next launch.
+    pub fn reload(&self, data: Vec<u8>) -> Result<(), GpuError> {
+        let mut state = self.state.lock().unwrap();
+        self.reload_locked(&mut state, data)
+    }
+
+    /// Replace the module with a new binary from a file.
+    ///
+    /// See [`Self::reload`].
+    pub fn reload_from_file(&self, path: impl AsRef<Path>) -> Result<(), GpuError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)
+            .map_err(|e| GpuError::ModuleFile(format!("cannot read {}: {e}", path.display())))?;
+        self.reload(data)
+    }
+
+    /// Replace the module with a new binary from a file if the file changed since the last call.
+    ///
+    /// Returns `true` if the module was reloaded.
+    /// The first call always reloads the module.
+    /// Call this regularly, e.g. before every launch, to pick up changes when recompiling the GPU
+    /// code.
+    ///
+    /// See [`Self::reload`].
+    pub fn reload_if_changed(&self, path: impl AsRef<Path>) -> Result<bool, GpuError> {
+        let path = path.as_ref();
+        let file_error = |e: std::io::Error| {
+            GpuError::ModuleFile(format!("cannot read {}: {e}", path.display()))
+        };
+        let modified = std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map_err(file_error)?;
+
+        let mut state = self.state.lock().unwrap();
+        if state.modified == Some(modified) {
+            return Ok(false);
+        }
+        let data = std::fs::read(path).map_err(file_error)?;
+        self.reload_locked(&mut state, data)?;
+        state.modified = Some(modified);
+        Ok(true)
+    }
+
+    fn reload_locked(&self, state: &mut ModuleState, data: Vec<u8>) -> Result<(), GpuError> {
+        // Load everything first, so the old module stays if loading fails
+        let modules = state
+            .modules
+            .iter()
+            .map(|module| {
+                module
+                    .as_ref()
+                    .map(|m| Module::try_new_on(&data, m.device()).map(Arc::new))
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        state.modules = modules;
+        state.reloaded = Some(data);
+        self.generation.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+}
+
+/// Read a precompiled module and check that it is the file the executable was built against.
+fn read_precompiled(name: &str, checksum: u64) -> Result<Vec<u8>, GpuError> {
+    let dir = match std::env::var_os("GPU_KERNEL_MODULE_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+            .ok_or_else(|| {
+                GpuError::ModuleFile(format!(
+                    "cannot find the directory of the executable to search {name}, set \
+                     GPU_KERNEL_MODULE_DIR"
+                ))
+            })?,
+    };
+    let path = dir.join(name);
+    let data = std::fs::read(&path)
+        .map_err(|e| GpuError::ModuleFile(format!("cannot read {}: {e}", path.display())))?;
+    if gpu_kernel_bundle::fnv1a(&data) != checksum {
+        return Err(GpuError::ModuleFile(format!(
+            "{} is not the module the executable was built against, rebuild the executable or \
+             ship the matching module",
+            path.display()
+        )));
+    }
+    Ok(data)
+}
+
+impl LazyKernel {
+    /// Create a kernel that is loaded from `module` on first use.
+    #[doc(hidden)]
+    pub const fn new(module: &'static LazyModule, name: &'static str) -> Self {
+        Self {
+            module,
+            name,
+            kernels: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Get the name of the kernel in the compiled binary.
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// Get the module that contains the kernel, e.g. to reload it.
+    pub fn module(&self) -> &'static LazyModule {
+        self.module
+    }
+
+    /// Get the kernel for a device, loading it if it is not loaded yet.
+    ///
+    /// If the module was reloaded, the kernel is loaded from the new module.
+    pub fn get(&self, device: Device) -> Result<Arc<Kernel>, GpuError> {
+        let mut kernels = self.kernels.lock().unwrap();
+        let kernel = entry(&mut kernels, device);
+        if let Some(loaded) = kernel
+            && loaded.generation == self.module.generation.load(Ordering::Acquire)
+        {
+            return Ok(loaded.kernel.clone());
+        }
+        let (generation, module) = self
+            .module
+            .get_with_generation(device)
+            .map_err(|e| e.with_kernel(self.name))?;
+        let loaded = kernel.insert(LoadedKernel {
+            generation,
+            kernel: Arc::new(module.try_get_kernel(self.name)?),
+        });
+        Ok(loaded.kernel.clone())
+    }
+
+    /// Get the kernel for the current device, loading it if it is not loaded yet.
+    pub fn get_current(&self) -> Result<Arc<Kernel>, GpuError> {
+        self.get(Device::current()?)
+    }
+
+    /// Launch the kernel on the device selected by the launch config, passing the given type as
+    /// arguments, without waiting for it to finish.
+    ///
+    /// # Safety
+    ///
+    /// See [`Kernel::launch_async_impl`].
+    #[doc(hidden)]
+    pub unsafe fn launch_async_impl<'a, T: ?Sized>(
+        &self,
+        launch_config: &LaunchConfig,
+        layout: ArgLayout<'_>,
+        args: &mut T,
+    ) -> Result<LaunchHandle<'a>, GpuError> {
+        let stream = launch_config.get_stream()?;
+        let kernel = self.get(stream.device())?;
+        unsafe { kernel.launch_async_on(launch_config, stream, layout, args) }
+    }
+
+    /// Launch the kernel on the device selected by the launch config, passing the given type as
+    /// arguments, and wait for it to finish.
+    ///
+    /// # Safety
+    ///
+    /// See [`Kernel::launch_impl`].
+    #[doc(hidden)]
+    pub unsafe fn launch_impl<T: ?Sized>(
+        &self,
+        launch_config: &LaunchConfig,
+        layout: ArgLayout<'_>,
+        args: &mut T,
+    ) -> Result<(), GpuError> {
+        let stream = launch_config.get_stream()?;
+        let kernel = self.get(stream.device())?;
+        unsafe { kernel.launch_on(launch_config, stream, layout, args) }
+    }
+
+    /// Launch the kernel on the device selected by the launch config multiple times and measure
+    /// the time it takes on the GPU.
+    ///
+    /// # Safety
+    ///
+    /// See [`Kernel::bench_impl`].
+    #[doc(hidden)]
+    pub unsafe fn bench_impl<T: ?Sized>(
+        &self,
+        launch_config: &LaunchConfig,
+        iterations: u32,
+        layout: ArgLayout<'_>,
+        args: &mut T,
+    ) -> Result<BenchResult, GpuError> {
+        let stream = launch_config.get_stream()?;
+        let kernel = self.get(stream.device())?;
+        unsafe { kernel.bench_on(launch_config, stream, iterations, layout, args) }
+    }
+}
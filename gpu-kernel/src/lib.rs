@@ -65,7 +65,7 @@
 //!
 //! ## Setup
 //!
-//! Currently, AMD GPUs are supported.
+//! Currently, AMD GPUs and, with the `nvidia` feature, NVIDIA GPUs are supported (see [NVIDIA GPUs](#nvidia-gpus)).
 //! Contributions for other Rust GPU targets are welcome, adding support to `gpu-kernel` should be relatively straightforward.
 //!
 //! Nightly Rust is currently required for the gpu_kernel ABI and GPU intrinsics.
@@ -96,6 +96,9 @@
 //! | `HIP_DEVICE_LIB_PATH`                      | `$(hipconfig -l)/../lib/clang/*/amdgcn/bitcode` |                       | Path to device libs, ends with `amdgcn/bitcode` and contains `.bc` files |
 //! | `CARGO_TARGET_AMDGCN_AMD_AMDHSA_RUSTFLAGS` | empty                                           | `-Ctarget-cpu=gfx900` | RUSTFLAGS used to compile amdgpu GPU code                                |
 //! | `CARGO_TARGET_AMDGCN_AMD_AMDHSA_FLAGS`     | empty                                           | `-v`                  | Cargo flags used to compile amdgpu GPU code                              |
+//! | `CARGO_TARGET_AMDGCN_AMD_AMDHSA_CPUS`      | `target-cpu` from RUSTFLAGS                     | `gfx90a,gfx1100`      | Compile for multiple GPUs and select the matching code object at runtime |
+//! | `GPU_KERNEL_PRECOMPILED`                   | empty                                           | `gpu`                 | Directory with precompiled modules to load instead of compiling          |
+//! | `GPU_KERNEL_MODULE_DIR`                    | directory of the executable                     | `/opt/app/gpu`        | Where precompiled modules are searched at runtime                        |
 //!
 //! Several flags are added automatically to the GPU compilation.
 //!
@@ -103,37 +106,246 @@
 //! - The `crate-type` is set to `cdylib`
 //! - Device libs are added to `link-arg`s and `-Clinker-plugin-lto` is enabled
 //! - core and alloc are built with `-Zbuild-std=core,alloc`
+//! - If multiple CPUs are listed in `CARGO_TARGET_AMDGCN_AMD_AMDHSA_CPUS`, the crate is compiled once per CPU and all code objects are embedded as a clang offload bundle
 //! - In debug mode, `opt-level=2` is set, as no optimizations can lead to crashes or compilation failures in the backend
 //! - In release mode, `panic=immediate-abort` is set for performance, so no panic messages are available
+//!
+//! ### Precompiled modules
+//!
+//! To build on machines without the GPU toolchain, copy `<crate>.elf` or `<crate>.hipfb` from `target/gpu-kernel/amdgcn-amd-amdhsa/release` of a normal build into a directory and point `GPU_KERNEL_PRECOMPILED` to it.
+//! `kernel_lib!()` then skips compiling GPU code and the executable loads the module at runtime from the directory it is in (or `GPU_KERNEL_MODULE_DIR`).
+//! Loading fails if the module differs from the one the executable was built against.
+//!
+//! ### Running on the CPU
+//!
+//! To test kernels on machines without a GPU, disable the default features of `gpu-kernel` and enable the `cpu` feature.
+//! Kernels are then compiled as normal Rust functions and every workgroup runs its GPU threads as CPU threads, with a limited number of workgroups in parallel, so `s_barrier`, shared memory and the other intrinsics keep working.
+//! The dispatch packet only lives as long as the launch, so `dispatch_ptr` is not available, use `dispatch_packet` instead.
+//! A panic in a kernel is returned as `GpuError::KernelPanicked`.
+//! Functions that need a GPU runtime, like `Device::current`, return `GpuError::NoRuntime`.
+//!
+//! ```toml
+//! [dev-dependencies]
+//! gpu-kernel = { version = "0.1", default-features = false, features = ["cpu"] }
+//! ```
+//!
+//! ### Mocking the GPU runtime
+//!
+//! To test host code without a GPU, e.g. which kernels are launched with which arguments, enable the `mock` feature.
+//! All calls to the HIP runtime then go to a mock that records them for `mock::record` and finishes every launch without running the kernel.
+//!
+//! **The `mock` feature is not additive.**
+//! It replaces the HIP runtime for the whole build, if any crate in the dependency graph enables it, the executable never uses a GPU.
+//! Only enable it for tests, as a dev-dependency:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! gpu-kernel = { version = "0.1", features = ["mock"] }
+//! ```
+//!
+//! As long as no crate in the build enables the `amd` feature, the mock does not need HIP to be installed and `kernel_lib!()` compiles no GPU code, e.g. to run tests on machines without ROCm:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! gpu-kernel = { version = "0.1", default-features = false, features = ["mock"] }
+//! ```
+//!
+//! ### NVIDIA GPUs
+//!
+//! To run on NVIDIA GPUs, disable the default features and enable the `nvidia` feature, and `nvidia-allocator` to allocate all memory as managed memory like `amd-allocator`.
+//! GPU code is then compiled for the `nvptx64-nvidia-cuda` target into PTX, which is loaded and launched through the CUDA driver API (`libcuda.so`).
+//! Instead of ROCm, only the NVIDIA driver needs to be installed.
+//! Set the oldest GPU to support as `target-cpu`, e.g. `CARGO_TARGET_NVPTX64_NVIDIA_CUDA_RUSTFLAGS=-Ctarget-cpu=sm_80`, the driver compiles the PTX for newer GPUs when loading it.
+//! `DeviceProperties::arch` reports the compute capability in the same format.
+//!
+//! ```toml
+//! [dependencies]
+//! gpu-kernel = { version = "0.1", default-features = false, features = ["nvidia", "nvidia-allocator"] }
+//! ```
+//!
+//! The intrinsics, `print!` and `println!` (through `vprintf`), panics and allocations work the same as on AMD GPUs.
+//! There is no dispatch packet in memory, so `dispatch_ptr` is not available, `dispatch_packet` returns a copy on all targets.
+//! Code objects cannot be bundled for NVIDIA GPUs, so `CARGO_TARGET_NVPTX64_NVIDIA_CUDA_CPUS` must not contain more than one entry.
+//! Precompiled modules are `<crate>.ptx` files.
 #![deny(missing_docs)]
 #![cfg_attr(any(target_arch = "amdgpu", target_arch = "nvptx64"), no_std)]
 // Allocators will potentially be stabilized before all the GPU necessary stuff.
 #![cfg_attr(
-    not(any(target_arch = "amdgpu", target_arch = "nvptx64")),
+    all(
+        any(feature = "hip", feature = "nvidia"),
+        not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+    ),
     feature(allocator_api)
 )]
+#![cfg_attr(
+    target_arch = "nvptx64",
+    allow(internal_features),
+    feature(core_intrinsics, gpu_intrinsics, link_llvm_intrinsics, stdarch_nvptx)
+)]
+
+#[cfg(all(feature = "hip", feature = "nvidia"))]
+compile_error!(
+    "The `amd` or `mock` and the `nvidia` feature of gpu-kernel cannot be enabled at the same time"
+);
+#[cfg(all(feature = "hip", not(any(feature = "amd", feature = "mock"))))]
+compile_error!("The `hip` feature of gpu-kernel is internal, enable `amd` or `mock` instead");
+
+// Allow tests to use the macros, which refer to `::gpu_kernel`
+#[cfg(test)]
+extern crate self as gpu_kernel;
 
 #[cfg(all(
-    feature = "amd",
+    any(feature = "hip", feature = "nvidia"),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 use std::alloc::AllocError;
 #[cfg(all(
-    feature = "amd",
+    any(feature = "hip", feature = "nvidia"),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 use std::ptr::NonNull;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+use std::sync::{Arc, OnceLock};
 
 #[cfg(all(
-    feature = "amd",
+    feature = "hip",
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+use crate::hip::hipError_t::hipSuccess;
+
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
-use hip_runtime_sys::hipError_t::hipSuccess;
+use error::check;
+#[cfg(all(
+    feature = "hip",
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+use runtime::runtime;
 
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod attributes;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod bundle;
+#[cfg(all(
+    feature = "cpu",
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+#[doc(hidden)]
+pub mod cpu;
+#[cfg(all(
+    feature = "nvidia",
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+mod cuda;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod device;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod device_buffer;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod error;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod event;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod global;
+#[cfg(all(
+    feature = "hip",
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+mod hip;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod launch_handle;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod lazy_kernel;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod metadata;
+/// A mock of the GPU runtime to test host code on machines without a GPU.
+///
+/// With the `mock` feature, all calls that would go to the HIP library are handled by a mock
+/// instead.
+/// The mock pretends to have a single device (see [`mock::set_device_count`]), allocates memory
+/// on the CPU and finishes every kernel launch right away without running it.
+/// Record the calls that a piece of code makes with [`mock::record`], e.g. to check the
+/// dimensions and arguments of a launch.
+///
+/// **The feature is not additive**, it replaces the HIP library for the whole build.
+/// Only enable it as a dev-dependency, see [Mocking the GPU runtime](crate#mocking-the-gpu-runtime).
+///
+/// # Example
+///
+/// ```no_run
+/// use gpu_kernel::mock::{self, Call};
+/// use gpu_kernel::{LaunchConfig, kernel};
+///
+/// gpu_kernel::kernel_lib!();
+///
+/// #[kernel]
+/// fn kernel(value: u32) {}
+///
+/// # fn main() {
+/// let ((), calls) = mock::record(|| kernel.launch(&LaunchConfig::for_elements(100, 32), 42u32));
+/// let launches: Vec<_> = calls
+///     .iter()
+///     .filter_map(|call| match call {
+///         Call::Launch {
+///             workgroups, args, ..
+///         } => Some((*workgroups, args.clone())),
+///         _ => None,
+///     })
+///     .collect();
+/// assert_eq!(launches, [([4, 1, 1], 42u32.to_ne_bytes().to_vec())]);
+/// # }
+/// ```
+#[cfg(all(
+    feature = "mock",
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+pub mod mock;
+#[cfg(target_arch = "nvptx64")]
+#[doc(hidden)]
+pub mod nvptx;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod occupancy;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod registry;
+#[cfg(all(
+    feature = "hip",
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+mod runtime;
 mod safe_kernel_arg;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+mod stream;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use attributes::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use bundle::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use device::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use device_buffer::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use error::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use event::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use global::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use launch_handle::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use lazy_kernel::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use metadata::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use occupancy::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use registry::*;
 pub use safe_kernel_arg::*;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub use stream::*;
 
-pub use gpu_kernel_proc_macros::kernel;
+pub use gpu_kernel_proc_macros::{global, kernel};
 #[doc(hidden)]
 pub use gpu_kernel_proc_macros::{kernel_lib_impl_dbg, kernel_lib_impl_rel};
 
@@ -143,14 +355,24 @@ pub use gpu_kernel_proc_macros::{kernel_lib_impl_dbg, kernel_lib_impl_rel};
 ))]
 #[doc(hidden)]
 pub use hip_runtime_sys;
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+#[doc(hidden)]
+pub use linkme;
 
 /// Items automatically imported for kernels.
 ///
 /// These don’t appear in the docs as they are only available in GPU code.
-#[cfg(any(doc, target_arch = "amdgpu", target_arch = "nvptx64"))]
+#[cfg(any(doc, target_arch = "amdgpu", target_arch = "nvptx64", feature = "cpu"))]
 pub mod prelude {
+    #[cfg(target_arch = "nvptx64")]
+    pub use crate::{print, println};
     #[cfg(target_arch = "amdgpu")]
     pub use amdgpu_device_libs::prelude::{print, println};
+    #[cfg(all(
+        feature = "cpu",
+        not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+    ))]
+    pub use std::{print, println};
 }
 
 /// Some basic, useful intrinsics for GPU kernels.
@@ -158,15 +380,94 @@ pub mod prelude {
 /// Once there is more support in `core`, this will be removed.
 ///
 /// These don’t appear in the docs as they are only available in GPU code.
-#[cfg(any(doc, target_arch = "amdgpu"))]
+/// With the `cpu` feature, they are emulated for kernels running on the CPU.
+#[cfg(any(doc, target_arch = "amdgpu", target_arch = "nvptx64", feature = "cpu"))]
 pub mod intrinsics {
-    #[cfg(target_arch = "amdgpu")]
-    pub use amdgpu_device_libs::dispatch_ptr;
     #[cfg(target_arch = "amdgpu")]
     pub use amdgpu_device_libs::prelude::{
         s_barrier, workgroup_id_x, workgroup_id_y, workgroup_id_z, workitem_id_x, workitem_id_y,
         workitem_id_z,
     };
+    #[cfg(target_arch = "amdgpu")]
+    pub use amdgpu_device_libs::{dispatch_ptr, dynamic_shared_memory};
+
+    /// Get a copy of the packet that describes the launch of the running kernel.
+    ///
+    /// Unlike [`dispatch_ptr`], this is also available on NVIDIA GPUs and the CPU.
+    #[cfg(target_arch = "amdgpu")]
+    #[inline]
+    pub fn dispatch_packet() -> amdgpu_device_libs::HsaKernelDispatchPacket {
+        dispatch_ptr().clone()
+    }
+
+    #[cfg(target_arch = "nvptx64")]
+    pub use crate::nvptx::{
+        DispatchPacket, dispatch_packet, dynamic_shared_memory, s_barrier, workgroup_id_x,
+        workgroup_id_y, workgroup_id_z, workitem_id_x, workitem_id_y, workitem_id_z,
+    };
+
+    #[cfg(all(
+        feature = "cpu",
+        not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+    ))]
+    pub use crate::cpu::{
+        DispatchPacket, dispatch_packet, dynamic_shared_memory, s_barrier, workgroup_id_x,
+        workgroup_id_y, workgroup_id_z, workitem_id_x, workitem_id_y, workitem_id_z,
+    };
+
+    /// The index of this thread in the whole launch, for the x, y and z dimension.
+    #[cfg(any(target_arch = "amdgpu", target_arch = "nvptx64", feature = "cpu"))]
+    pub fn global_id() -> [usize; 3] {
+        let dispatch = dispatch_packet();
+        [
+            workitem_id_x() as usize
+                + dispatch.workgroup_size_x as usize * workgroup_id_x() as usize,
+            workitem_id_y() as usize
+                + dispatch.workgroup_size_y as usize * workgroup_id_y() as usize,
+            workitem_id_z() as usize
+                + dispatch.workgroup_size_z as usize * workgroup_id_z() as usize,
+        ]
+    }
+
+    /// The index of this thread in the x dimension if it is less than `elements`.
+    ///
+    /// Returns `None` for threads that were launched beyond `elements` because the number of
+    /// workgroups was rounded up, see `LaunchConfig::for_elements`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// #[gpu_kernel::kernel]
+    /// fn kernel(a: &[u32], b: *mut u32) {
+    ///     let Some(i) = gpu_kernel::intrinsics::element_index(a.len()) else {
+    ///         return;
+    ///     };
+    ///     unsafe { *b.add(i) = a[i] * 2 };
+    /// }
+    /// ```
+    #[cfg(any(target_arch = "amdgpu", target_arch = "nvptx64", feature = "cpu"))]
+    pub fn element_index(elements: usize) -> Option<usize> {
+        let id = global_id()[0];
+        (id < elements).then_some(id)
+    }
+
+    /// The index of this thread in the x and y dimensions if it is inside the `elements` grid.
+    ///
+    /// See [`element_index`].
+    #[cfg(any(target_arch = "amdgpu", target_arch = "nvptx64", feature = "cpu"))]
+    pub fn element_index_2d(elements: [usize; 2]) -> Option<[usize; 2]> {
+        let [x, y, _] = global_id();
+        (x < elements[0] && y < elements[1]).then_some([x, y])
+    }
+
+    /// The index of this thread in the x, y and z dimensions if it is inside the `elements` grid.
+    ///
+    /// See [`element_index`].
+    #[cfg(any(target_arch = "amdgpu", target_arch = "nvptx64", feature = "cpu"))]
+    pub fn element_index_3d(elements: [usize; 3]) -> Option<[usize; 3]> {
+        let id = global_id();
+        (0..3).all(|i| id[i] < elements[i]).then_some(id)
+    }
 }
 
 /// The `kernel_lib!()` macro declares a crate as a library of GPU kernels.
@@ -208,8 +509,8 @@ macro_rules! kernel_lib {
 /// ```
 /// # use gpu_kernel::LaunchConfig;
 /// let launch_config = LaunchConfig::new()
-///     .workgroups([1, 0, 0])
-///     .threads_per_workgroup([1, 0, 0]);
+///     .workgroups([1, 1, 1])
+///     .threads_per_workgroup([1, 1, 1]);
 /// ```
 #[non_exhaustive]
 #[derive(Clone, Default, Eq, Hash, PartialEq)]
@@ -224,26 +525,41 @@ pub struct LaunchConfig {
     /// A three-dimensional size for x, y, z dimensions.
     /// For a simple list of threads, this can be `[n, 1, 1]`.
     pub threads_per_workgroup: Option<[u32; 3]>,
+    /// The size of dynamically allocated shared memory per workgroup in bytes.
+    ///
+    /// Kernels access it through `intrinsics::dynamic_shared_memory`.
+    pub dynamic_shared_memory: u32,
+    /// The stream to launch the kernel on.
+    ///
+    /// If not set, a thread-local stream of the device is used.
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub stream: Option<Stream>,
+    /// The device to launch the kernel on.
+    ///
+    /// If not set, the device of the stream or the current device is used.
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub device: Option<Device>,
 }
 
-/// Allocate managed memory on AMD that lives on the CPU and is visible to the GPU as well.
+/// Allocate managed memory that lives on the CPU and is visible to the GPU as well.
 ///
-/// On GPUs that support it (mostly MI cards), managed memory can be automatically transferred
-/// between CPU and GPU.
+/// On GPUs that support it (mostly MI cards on AMD), managed memory can be automatically
+/// transferred between CPU and GPU.
 /// See the [unified memory management] documentation.
 ///
-/// With the `amd-allocator` crate feature (enabled by default), this is the default allocator.
+/// With the `amd-allocator` crate feature (enabled by default) or the `nvidia-allocator` feature,
+/// this is the default allocator.
 ///
 /// [unified memory management]: https://rocm.docs.amd.com/projects/HIP/en/latest/how-to/hip_runtime_api/memory_management/unified_memory.html
 #[cfg(all(
-    feature = "amd",
+    any(feature = "hip", feature = "nvidia"),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 pub struct ManagedMemAlloc;
 
 /// Define global allocator.
 #[cfg(all(
-    feature = "amd-allocator",
+    any(feature = "amd-allocator", feature = "nvidia-allocator"),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 #[global_allocator]
@@ -253,7 +569,7 @@ static HEAP: ManagedMemAlloc = ManagedMemAlloc;
 ///
 /// [`GpuBox`] is a convenient `Box` using this allocator.
 #[cfg(all(
-    feature = "amd",
+    any(feature = "hip", feature = "nvidia"),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 pub struct GpuAlloc;
@@ -269,62 +585,272 @@ pub struct GpuAlloc;
 /// let gpu_int = GpuBox::new(42);
 /// ```
 #[cfg(all(
-    feature = "amd",
+    any(feature = "hip", feature = "nvidia"),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 pub type GpuBox<T, A = GpuAlloc> = Box<T, A>;
 
+/// Allocate page-locked (pinned) memory on the CPU, visible to the GPU as well.
+///
+/// Pinned memory is never swapped out, so the GPU can access it directly and copies from and to
+/// it run asynchronously, e.g. with [`DeviceBuffer::copy_to_host_async`].
+/// Pinning a lot of memory can slow down the rest of the system.
+///
+/// [`PinnedBox`] and [`PinnedVec`] are a convenient `Box` and `Vec` using this allocator.
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PinnedAlloc;
+
+/// A `Box` allocated in pinned memory on the CPU, also accessible from the GPU.
+///
+/// # Example
+///
+/// ```no_run
+/// # #![feature(allocator_api)]
+/// # use gpu_kernel::{PinnedAlloc, PinnedBox};
+/// let pinned_int = PinnedBox::new_in(42, PinnedAlloc);
+/// ```
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+pub type PinnedBox<T, A = PinnedAlloc> = Box<T, A>;
+
+/// A `Vec` allocated in pinned memory on the CPU, also accessible from the GPU.
+///
+/// # Example
+///
+/// ```no_run
+/// # #![feature(allocator_api)]
+/// # use gpu_kernel::{DeviceBuffer, PinnedAlloc, PinnedVec, Stream};
+/// let buffer = DeviceBuffer::from_slice(&[1u32; 1024]);
+/// let mut data = PinnedVec::with_capacity_in(buffer.len(), PinnedAlloc);
+/// data.resize(buffer.len(), 0);
+/// let stream = Stream::new();
+/// // SAFETY: The handle is not leaked
+/// let copy = unsafe { buffer.copy_to_host_async(&mut data, &stream) }.unwrap();
+/// // Do other work while copying…
+/// copy.wait().unwrap();
+/// ```
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+pub type PinnedVec<T, A = PinnedAlloc> = Vec<T, A>;
+
 /// A loaded, compiled GPU binary.
+///
+/// A module is loaded on a single device.
+/// It is unloaded when the module and all kernels loaded from it are dropped.
 #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
 #[doc(hidden)]
 pub struct Module {
-    #[cfg(feature = "amd")]
-    module: hip_runtime_sys::hipModule_t,
+    handle: Arc<ModuleHandle>,
+    /// The error from parsing if the module contains no valid code object metadata.
+    metadata: Result<Arc<CodeObjectMetadata>, GpuError>,
+}
+
+/// A module loaded in the runtime, shared by a [`Module`] and its kernels.
+///
+/// Unloads the module when dropped.
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+pub(crate) struct ModuleHandle {
+    #[cfg(feature = "hip")]
+    module: crate::hip::hipModule_t,
+    #[cfg(feature = "nvidia")]
+    module: cuda::CUmodule,
+    device: Device,
 }
 #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
-unsafe impl Send for Module {}
+unsafe impl Send for ModuleHandle {}
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+unsafe impl Sync for ModuleHandle {}
+
 #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
-unsafe impl Sync for Module {}
+impl Drop for ModuleHandle {
+    fn drop(&mut self) {
+        #[cfg(feature = "hip")]
+        {
+            // Errors cannot be reported when dropping
+            let _ = self.device.with_current(|| unsafe {
+                let result = runtime().module_unload(self.module);
+                check(result, "hipModuleUnload", None)
+            });
+        }
+        #[cfg(feature = "nvidia")]
+        {
+            // Errors cannot be reported when dropping
+            let _ = self.device.with_current(|| unsafe {
+                check(cuda::cuModuleUnload(self.module), "cuModuleUnload", None)
+            });
+        }
+    }
+}
 
-/// A loaded, compiled GPU kernel.
+/// A compiled GPU kernel, loaded on a single device.
 ///
 /// Can be launched on the GPU.
+/// Kernels defined with `#[kernel]` are [`LazyKernel`]s that load a `Kernel` for every device they
+/// are launched on.
 ///
 /// The `#[kernel]` macro adds a `launch` function that takes a [`&LaunchConfig`](`LaunchConfig`)
 /// as first argument and all kernel arguments afterwards.
+/// `launch` panics if the launch fails, `try_launch` returns a [`GpuError`] instead.
+/// `launch_async` returns a [`LaunchHandle`] without waiting for the kernel to finish.
 #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
 pub struct Kernel {
-    #[cfg(feature = "amd")]
-    func: hip_runtime_sys::hipFunction_t,
+    #[cfg(feature = "hip")]
+    func: crate::hip::hipFunction_t,
+    #[cfg(feature = "nvidia")]
+    func: cuda::CUfunction,
+    name: String,
+    device: Device,
+    /// Keeps the module loaded as long as the kernel exists.
+    module: Arc<ModuleHandle>,
+    limits: LaunchLimits,
+    metadata: Option<KernelMetadata>,
+    /// The result of checking the arguments against the metadata, done on the first launch.
+    args_checked: OnceLock<Result<(), GpuError>>,
 }
 #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
 unsafe impl Send for Kernel {}
 #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
 unsafe impl Sync for Kernel {}
 
-#[cfg(all(
-    feature = "amd",
-    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
-))]
-struct HipStream(hip_runtime_sys::hipStream_t);
-
-#[cfg(all(
-    feature = "amd",
-    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
-))]
-thread_local! {
-    /// A thread-local stream to launch and wait for kernels.
-    static STREAM: std::cell::RefCell<HipStream> = std::cell::RefCell::new(HipStream::new());
+/// Limits for launching a kernel on its device, queried when loading the kernel.
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+#[derive(Clone, Copy, Debug)]
+struct LaunchLimits {
+    /// The maximum number of threads in a workgroup supported by the device.
+    device_max_threads_per_workgroup: u32,
+    /// The maximum number of threads in a workgroup that the kernel was compiled for.
+    kernel_max_threads_per_workgroup: u32,
+    /// The maximum number of threads in a workgroup for each dimension.
+    max_workgroup_size: [u32; 3],
+    /// The maximum number of workgroups for each dimension.
+    max_workgroups: [u32; 3],
+    /// The shared memory per workgroup that is left after the kernel’s static shared memory.
+    max_dynamic_shared_memory: usize,
 }
 
 impl LaunchConfig {
     /// Create an empty `LaunchConfig`.
     ///
-    /// At least [`Self::workgroups`] and [`Self::threads_per_workgroup`] need to be filled out, otherwise launching panics.
+    /// At least [`Self::workgroups`] and [`Self::threads_per_workgroup`] need to be filled out, otherwise launching fails.
     pub fn new() -> Self {
         Default::default()
     }
 
+    /// Create a `LaunchConfig` that launches at least one thread for each of `elements`.
+    ///
+    /// The number of workgroups is rounded up, so the last workgroup can contain threads beyond
+    /// `elements`.
+    /// Kernels can skip these threads with `intrinsics::element_index`.
+    ///
+    /// Panics if `elements` is 0 or the number of threads does not fit into an `u32`, see
+    /// [`Self::try_for_elements`] for a non-panicking variant.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use gpu_kernel::LaunchConfig;
+    /// let launch_config = LaunchConfig::for_elements(1000, 64);
+    /// assert_eq!(launch_config.workgroups, Some([16, 1, 1]));
+    /// assert_eq!(launch_config.threads_per_workgroup, Some([64, 1, 1]));
+    /// ```
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub fn for_elements(elements: usize, threads_per_workgroup: u32) -> Self {
+        Self::try_for_elements(elements, threads_per_workgroup).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a `LaunchConfig` that launches at least one thread for each of `elements`.
+    ///
+    /// See [`Self::for_elements`].
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub fn try_for_elements(elements: usize, threads_per_workgroup: u32) -> Result<Self, GpuError> {
+        Self::try_for_elements_3d([elements, 1, 1], [threads_per_workgroup, 1, 1])
+    }
+
+    /// Create a `LaunchConfig` that launches at least one thread for each element of a
+    /// two-dimensional `[x, y]` grid.
+    ///
+    /// Kernels can skip threads beyond the grid with `intrinsics::element_index_2d`.
+    ///
+    /// Panics if the grid is empty or the number of threads does not fit into an `u32`, see
+    /// [`Self::try_for_elements_2d`] for a non-panicking variant.
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub fn for_elements_2d(elements: [usize; 2], threads_per_workgroup: [u32; 2]) -> Self {
+        Self::try_for_elements_2d(elements, threads_per_workgroup).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a `LaunchConfig` that launches at least one thread for each element of a
+    /// two-dimensional `[x, y]` grid.
+    ///
+    /// See [`Self::for_elements_2d`].
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub fn try_for_elements_2d(
+        elements: [usize; 2],
+        threads_per_workgroup: [u32; 2],
+    ) -> Result<Self, GpuError> {
+        Self::try_for_elements_3d(
+            [elements[0], elements[1], 1],
+            [threads_per_workgroup[0], threads_per_workgroup[1], 1],
+        )
+    }
+
+    /// Create a `LaunchConfig` that launches at least one thread for each element of a
+    /// three-dimensional `[x, y, z]` grid.
+    ///
+    /// Kernels can skip threads beyond the grid with `intrinsics::element_index_3d`.
+    ///
+    /// Panics if the grid is empty or the number of threads does not fit into an `u32`, see
+    /// [`Self::try_for_elements_3d`] for a non-panicking variant.
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub fn for_elements_3d(elements: [usize; 3], threads_per_workgroup: [u32; 3]) -> Self {
+        Self::try_for_elements_3d(elements, threads_per_workgroup).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a `LaunchConfig` that launches at least one thread for each element of a
+    /// three-dimensional `[x, y, z]` grid.
+    ///
+    /// See [`Self::for_elements_3d`].
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub fn try_for_elements_3d(
+        elements: [usize; 3],
+        threads_per_workgroup: [u32; 3],
+    ) -> Result<Self, GpuError> {
+        let mut workgroups = [0; 3];
+        for (i, dimension) in DIMENSIONS.iter().enumerate() {
+            let threads = threads_per_workgroup[i];
+            if elements[i] == 0 {
+                return Err(GpuError::InvalidLaunchConfig(format!(
+                    "no elements in dimension {dimension}, a launch needs at least one workgroup"
+                )));
+            }
+            if threads == 0 {
+                return Err(GpuError::InvalidLaunchConfig(format!(
+                    "threads_per_workgroup must not be 0 in dimension {dimension}"
+                )));
+            }
+            let count = elements[i].div_ceil(threads as usize);
+            workgroups[i] = u32::try_from(count).map_err(|_| {
+                GpuError::InvalidLaunchConfig(format!(
+                    "{} elements in dimension {dimension} need more than u32::MAX threads",
+                    elements[i]
+                ))
+            })?;
+            check_thread_count(dimension, workgroups[i], threads)?;
+        }
+        Ok(Self {
+            workgroups: Some(workgroups),
+            threads_per_workgroup: Some(threads_per_workgroup),
+            ..Default::default()
+        })
+    }
+
     /// The number of workgroups launched on the GPU.
     ///
     /// A three-dimensional size for x, y, z dimensions.
@@ -342,10 +868,115 @@ impl LaunchConfig {
         self.threads_per_workgroup = Some(threads_per_workgroup);
         self
     }
+
+    /// The size of dynamically allocated shared memory per workgroup in bytes.
+    ///
+    /// Kernels access it through `intrinsics::dynamic_shared_memory`.
+    /// Defaults to `0`.
+    pub fn dynamic_shared_memory(&mut self, bytes: u32) -> &mut Self {
+        self.dynamic_shared_memory = bytes;
+        self
+    }
+
+    /// The stream to launch the kernel on.
+    ///
+    /// If not set, a thread-local stream of the device is used.
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub fn stream(&mut self, stream: &Stream) -> &mut Self {
+        self.stream = Some(stream.clone());
+        self
+    }
+
+    /// The device to launch the kernel on.
+    ///
+    /// If not set, the device of the stream or the current device is used.
+    /// If a stream is set as well, it must belong to this device.
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub fn device(&mut self, device: Device) -> &mut Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Check that the launch config is valid.
+    ///
+    /// Limits of the device are checked when launching a kernel, see `Kernel::check_limits`.
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub(crate) fn validate(&self) -> Result<(), GpuError> {
+        let invalid = |reason: String| Err(GpuError::InvalidLaunchConfig(reason));
+        let (workgroups, threads_per_workgroup) = self.dimensions()?;
+        for (i, dimension) in DIMENSIONS.iter().enumerate() {
+            if workgroups[i] == 0 {
+                return invalid(format!("workgroups must not be 0 in dimension {dimension}"));
+            }
+            if threads_per_workgroup[i] == 0 {
+                return invalid(format!(
+                    "threads_per_workgroup must not be 0 in dimension {dimension}"
+                ));
+            }
+            check_thread_count(dimension, workgroups[i], threads_per_workgroup[i])?;
+        }
+        Ok(())
+    }
+
+    /// Get the number of workgroups and threads per workgroup, returns an error if they are not
+    /// set.
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub(crate) fn dimensions(&self) -> Result<([u32; 3], [u32; 3]), GpuError> {
+        let Some(workgroups) = self.workgroups else {
+            return Err(GpuError::InvalidLaunchConfig(
+                "workgroups must be set".into(),
+            ));
+        };
+        let Some(threads_per_workgroup) = self.threads_per_workgroup else {
+            return Err(GpuError::InvalidLaunchConfig(
+                "threads_per_workgroup must be set".into(),
+            ));
+        };
+        Ok((workgroups, threads_per_workgroup))
+    }
+
+    /// Get the stream to launch on.
+    ///
+    /// This is the stream from the launch config or the thread-local stream of the device if none
+    /// is set.
+    #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+    pub(crate) fn get_stream(&self) -> Result<Stream, GpuError> {
+        #[cfg(any(feature = "hip", feature = "nvidia"))]
+        match (&self.stream, self.device) {
+            (Some(stream), Some(device)) if stream.device() != device => {
+                Err(GpuError::InvalidLaunchConfig(format!(
+                    "stream belongs to device {} but device {} was requested",
+                    stream.device().index(),
+                    device.index()
+                )))
+            }
+            (Some(stream), _) => Ok(stream.clone()),
+            (None, Some(device)) => Stream::thread_local(device),
+            (None, None) => Stream::thread_local(Device::current()?),
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        Err(GpuError::NoRuntime)
+    }
+}
+
+/// Names of the three launch dimensions, used in error messages.
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+const DIMENSIONS: [&str; 3] = ["x", "y", "z"];
+
+/// Check that the total number of threads in a dimension fits into an `u32`.
+#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+fn check_thread_count(dimension: &str, workgroups: u32, threads: u32) -> Result<(), GpuError> {
+    if workgroups.checked_mul(threads).is_none() {
+        return Err(GpuError::InvalidLaunchConfig(format!(
+            "{workgroups} workgroups with {threads} threads each in dimension {dimension} exceed \
+             u32::MAX threads"
+        )));
+    }
+    Ok(())
 }
 
 #[cfg(all(
-    feature = "amd",
+    feature = "hip",
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 unsafe impl std::alloc::GlobalAlloc for ManagedMemAlloc {
@@ -354,12 +985,12 @@ unsafe impl std::alloc::GlobalAlloc for ManagedMemAlloc {
         use std::ffi;
         unsafe {
             let mut ptr: *mut ffi::c_void = std::ptr::null_mut();
-            let result = hip_runtime_sys::hipMallocManaged(
-                &mut ptr,
-                layout.size(),
-                hip_runtime_sys::hipMemAttachGlobal,
-            );
-            assert_eq!(result, hipSuccess);
+            let result =
+                runtime().malloc_managed(&mut ptr, layout.size(), crate::hip::hipMemAttachGlobal);
+            // Returning null signals an allocation failure
+            if result != hipSuccess {
+                return std::ptr::null_mut();
+            }
             ptr as *mut _
         }
     }
@@ -367,14 +998,14 @@ unsafe impl std::alloc::GlobalAlloc for ManagedMemAlloc {
     #[inline]
     unsafe fn dealloc(&self, ptr: *mut u8, _: std::alloc::Layout) {
         unsafe {
-            let result = hip_runtime_sys::hipFree(ptr as *mut _);
+            let result = runtime().free(ptr as *mut _);
             assert_eq!(result, hipSuccess);
         };
     }
 }
 
 #[cfg(all(
-    feature = "amd",
+    feature = "hip",
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 unsafe impl std::alloc::Allocator for GpuAlloc {
@@ -383,8 +1014,10 @@ unsafe impl std::alloc::Allocator for GpuAlloc {
         use std::ffi;
         unsafe {
             let mut ptr: *mut ffi::c_void = std::ptr::null_mut();
-            let result = hip_runtime_sys::hipMalloc(&mut ptr, layout.size());
-            assert_eq!(result, hipSuccess);
+            let result = runtime().malloc(&mut ptr, layout.size());
+            if result != hipSuccess {
+                return Err(AllocError);
+            }
             Ok(NonNull::slice_from_raw_parts(
                 NonNull::new(ptr as *mut _).ok_or(AllocError)?,
                 layout.size(),
@@ -395,84 +1028,372 @@ unsafe impl std::alloc::Allocator for GpuAlloc {
     #[inline]
     unsafe fn deallocate(&self, ptr: NonNull<u8>, _: std::alloc::Layout) {
         unsafe {
-            let result = hip_runtime_sys::hipFree(ptr.as_ptr() as *mut _);
+            let result = runtime().free(ptr.as_ptr() as *mut _);
             assert_eq!(result, hipSuccess);
         };
     }
 }
 
 #[cfg(all(
-    feature = "amd",
+    feature = "nvidia",
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
-impl HipStream {
-    fn new() -> Self {
+unsafe impl std::alloc::GlobalAlloc for ManagedMemAlloc {
+    #[inline]
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        // Returning null signals an allocation failure
+        if cuda::init().is_err() {
+            return std::ptr::null_mut();
+        }
         unsafe {
-            let mut stream: hip_runtime_sys::hipStream_t = std::ptr::null_mut();
-            let result = hip_runtime_sys::hipStreamCreate(&mut stream);
-            assert_eq!(result, hipSuccess);
-            Self(stream)
+            let mut ptr = 0;
+            let result =
+                cuda::cuMemAllocManaged(&mut ptr, layout.size(), cuda::CU_MEM_ATTACH_GLOBAL);
+            if result != cuda::CUresult::SUCCESS {
+                return std::ptr::null_mut();
+            }
+            ptr as *mut _
         }
     }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, _: std::alloc::Layout) {
+        unsafe {
+            let result = cuda::cuMemFree(ptr as u64);
+            assert_eq!(result, cuda::CUresult::SUCCESS);
+        };
+    }
 }
 
 #[cfg(all(
-    feature = "amd",
+    feature = "nvidia",
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
-impl Drop for HipStream {
-    fn drop(&mut self) {
+unsafe impl std::alloc::Allocator for GpuAlloc {
+    #[inline]
+    fn allocate(&self, layout: std::alloc::Layout) -> Result<NonNull<[u8]>, AllocError> {
+        cuda::init().map_err(|_| AllocError)?;
         unsafe {
-            let result = hip_runtime_sys::hipStreamDestroy(self.0);
-            assert_eq!(result, hipSuccess);
+            let mut ptr = 0;
+            let result = cuda::cuMemAlloc(&mut ptr, layout.size());
+            if result != cuda::CUresult::SUCCESS {
+                return Err(AllocError);
+            }
+            Ok(NonNull::slice_from_raw_parts(
+                NonNull::new(ptr as *mut _).ok_or(AllocError)?,
+                layout.size(),
+            ))
         }
     }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _: std::alloc::Layout) {
+        unsafe {
+            let result = cuda::cuMemFree(ptr.as_ptr() as u64);
+            assert_eq!(result, cuda::CUresult::SUCCESS);
+        };
+    }
 }
 
-/// Get the thread-local stream.
-///
-/// Internally copies the reference to make access simpler.
 #[cfg(all(
-    feature = "amd",
+    feature = "hip",
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
-fn thread_local_stream() -> hip_runtime_sys::hipStream_t {
-    STREAM.with_borrow(|s| s.0)
+unsafe impl std::alloc::Allocator for PinnedAlloc {
+    #[inline]
+    fn allocate(&self, layout: std::alloc::Layout) -> Result<NonNull<[u8]>, AllocError> {
+        use std::ffi;
+        unsafe {
+            let mut ptr: *mut ffi::c_void = std::ptr::null_mut();
+            let result =
+                runtime().host_malloc(&mut ptr, layout.size(), crate::hip::hipHostMallocDefault);
+            if result != hipSuccess {
+                return Err(AllocError);
+            }
+            Ok(NonNull::slice_from_raw_parts(
+                NonNull::new(ptr as *mut _).ok_or(AllocError)?,
+                layout.size(),
+            ))
+        }
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _: std::alloc::Layout) {
+        unsafe {
+            let result = runtime().host_free(ptr.as_ptr() as *mut _);
+            assert_eq!(result, hipSuccess);
+        };
+    }
+}
+
+#[cfg(all(
+    feature = "nvidia",
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+unsafe impl std::alloc::Allocator for PinnedAlloc {
+    #[inline]
+    fn allocate(&self, layout: std::alloc::Layout) -> Result<NonNull<[u8]>, AllocError> {
+        cuda::init().map_err(|_| AllocError)?;
+        unsafe {
+            let mut ptr = std::ptr::null_mut();
+            // Portable and mapped, so every device can access the memory
+            let flags = cuda::CU_MEMHOSTALLOC_PORTABLE | cuda::CU_MEMHOSTALLOC_DEVICEMAP;
+            let result = cuda::cuMemHostAlloc(&mut ptr, layout.size(), flags);
+            if result != cuda::CUresult::SUCCESS {
+                return Err(AllocError);
+            }
+            Ok(NonNull::slice_from_raw_parts(
+                NonNull::new(ptr as *mut _).ok_or(AllocError)?,
+                layout.size(),
+            ))
+        }
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _: std::alloc::Layout) {
+        unsafe {
+            let result = cuda::cuMemFreeHost(ptr.as_ptr() as *mut _);
+            assert_eq!(result, cuda::CUresult::SUCCESS);
+        };
+    }
 }
 
 #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
 impl Module {
-    /// Load a module from a binary.
+    /// Load a module from a binary on the current device.
+    ///
+    /// Panics if loading fails, see [`Self::try_new`] for a non-panicking variant.
     pub fn new(data: &[u8]) -> Self {
-        #[cfg(feature = "amd")]
+        Self::try_new(data).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Load a module from a binary on the current device.
+    ///
+    /// The binary is either a single code object or an [`OffloadBundle`] with code objects for
+    /// multiple GPUs, e.g. a `.hipfb` file created by `hipcc --genco`.
+    /// For bundles, the code object matching the architecture of the device is loaded.
+    /// With the `nvidia` feature, the binary is PTX or a cubin.
+    pub fn try_new(data: &[u8]) -> Result<Self, GpuError> {
+        let device = Device::current()?;
+        let bundle;
+        let data = if OffloadBundle::is_bundle(data) {
+            bundle = OffloadBundle::parse(data)?;
+            bundle.code_object_for(&device.properties()?)?
+        } else {
+            data
+        };
+        #[cfg(feature = "hip")]
         unsafe {
-            let mut module: hip_runtime_sys::hipModule_t = std::ptr::null_mut();
-            let result = hip_runtime_sys::hipModuleLoadData(
-                &mut module,
-                data.as_ptr() as *const std::ffi::c_void,
-            );
-            assert_eq!(result, hipSuccess);
-            Self { module }
+            let mut module: crate::hip::hipModule_t = std::ptr::null_mut();
+            let result =
+                runtime().module_load_data(&mut module, data.as_ptr() as *const std::ffi::c_void);
+            check(result, "hipModuleLoadData", None)?;
+            let metadata = CodeObjectMetadata::parse(data).map(Arc::new);
+            Ok(Self {
+                handle: Arc::new(ModuleHandle { module, device }),
+                metadata,
+            })
         }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            // PTX is loaded as a nul-terminated string
+            let mut image = data.to_vec();
+            if image.last() != Some(&0) {
+                image.push(0);
+            }
+            let mut module = std::ptr::null_mut();
+            let result = cuda::cuModuleLoadData(&mut module, image.as_ptr().cast());
+            check(result, "cuModuleLoadData", None)?;
+            Ok(Self {
+                handle: Arc::new(ModuleHandle { module, device }),
+                metadata: Err(GpuError::InvalidCodeObject(
+                    "CUDA modules contain no AMDGPU metadata".into(),
+                )),
+            })
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        {
+            let _ = (data, device);
+            Err(GpuError::NoRuntime)
+        }
+    }
+
+    /// Load a module from a file on the current device.
+    ///
+    /// The file is either a single code object or an [`OffloadBundle`].
+    ///
+    /// Panics if loading fails, see [`Self::try_from_file`] for a non-panicking variant.
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Self {
+        Self::try_from_file(path).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Load a module from a file on the current device.
+    ///
+    /// See [`Self::from_file`].
+    pub fn try_from_file(path: impl AsRef<std::path::Path>) -> Result<Self, GpuError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)
+            .map_err(|e| GpuError::ModuleFile(format!("cannot read {}: {e}", path.display())))?;
+        Self::try_new(&data)
+    }
+
+    /// Load a module from a binary on the given device.
+    pub fn try_new_on(data: &[u8], device: Device) -> Result<Self, GpuError> {
+        device.with_current(|| Self::try_new(data))
+    }
+
+    /// The device this module is loaded on.
+    pub fn device(&self) -> Device {
+        self.handle.device
+    }
+
+    /// The metadata of the loaded code object, if it contains metadata.
+    ///
+    /// See [`Self::try_metadata`] for why parsing the metadata failed.
+    pub fn metadata(&self) -> Option<&CodeObjectMetadata> {
+        self.metadata.as_deref().ok()
+    }
+
+    /// The metadata of the loaded code object.
+    ///
+    /// Returns the error from parsing the metadata, e.g. if the code object is malformed.
+    /// Kernels of modules without metadata are launched without checking their arguments.
+    pub fn try_metadata(&self) -> Result<&CodeObjectMetadata, GpuError> {
+        self.metadata.as_deref().map_err(Clone::clone)
     }
 
     /// Get the kernel with the specified name from the loaded binary.
+    ///
+    /// Panics if the kernel does not exist, see [`Self::try_get_kernel`] for a non-panicking variant.
     pub fn get_kernel(&self, name: &str) -> Kernel {
-        #[cfg(feature = "amd")]
+        self.try_get_kernel(name).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Get the kernel with the specified name from the loaded binary.
+    pub fn try_get_kernel(&self, name: &str) -> Result<Kernel, GpuError> {
+        #[cfg(feature = "hip")]
         unsafe {
-            let mut function: hip_runtime_sys::hipFunction_t = std::ptr::null_mut();
-            let kernel_name = std::ffi::CString::new(name).expect("Invalid kernel name");
-            let result = hip_runtime_sys::hipModuleGetFunction(
+            let mut function: crate::hip::hipFunction_t = std::ptr::null_mut();
+            let kernel_name = std::ffi::CString::new(name)
+                .map_err(|_| GpuError::InvalidKernelName(name.to_string()))?;
+            let result = runtime().module_get_function(
                 &mut function,
-                self.module,
+                self.handle.module,
                 kernel_name.as_ptr(),
             );
-            assert_eq!(
-                result, hipSuccess,
-                "Failed to find kernel {:?}",
-                kernel_name
+            check(result, "hipModuleGetFunction", Some(name))?;
+
+            let props = self
+                .device()
+                .properties()
+                .map_err(|e| e.with_kernel(name))?;
+            use crate::hip::hipFunction_attribute::*;
+            let attribute = |attribute| attributes::func_attribute(function, attribute, name);
+            let kernel_max_threads = attribute(HIP_FUNC_ATTRIBUTE_MAX_THREADS_PER_BLOCK)?;
+            let static_shared_memory = attribute(HIP_FUNC_ATTRIBUTE_SHARED_SIZE_BYTES)?;
+            let limits = LaunchLimits {
+                device_max_threads_per_workgroup: props.max_threads_per_workgroup,
+                kernel_max_threads_per_workgroup: kernel_max_threads,
+                max_workgroup_size: props.max_workgroup_size,
+                max_workgroups: props.max_workgroups,
+                max_dynamic_shared_memory: props
+                    .shared_memory_per_workgroup
+                    .saturating_sub(static_shared_memory as usize),
+            };
+
+            Ok(Kernel {
+                func: function,
+                name: name.to_string(),
+                device: self.device(),
+                module: self.handle.clone(),
+                limits,
+                metadata: self.metadata().and_then(|m| m.kernel(name)).cloned(),
+                args_checked: OnceLock::new(),
+            })
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let mut function = std::ptr::null_mut();
+            let kernel_name = std::ffi::CString::new(name)
+                .map_err(|_| GpuError::InvalidKernelName(name.to_string()))?;
+            let result =
+                cuda::cuModuleGetFunction(&mut function, self.handle.module, kernel_name.as_ptr());
+            check(result, "cuModuleGetFunction", Some(name))?;
+
+            let props = self
+                .device()
+                .properties()
+                .map_err(|e| e.with_kernel(name))?;
+            use cuda::*;
+            let attribute = |attribute| attributes::func_attribute(function, attribute, name);
+            let kernel_max_threads = attribute(CU_FUNC_ATTRIBUTE_MAX_THREADS_PER_BLOCK)?;
+            let static_shared_memory = attribute(CU_FUNC_ATTRIBUTE_SHARED_SIZE_BYTES)?;
+            let limits = LaunchLimits {
+                device_max_threads_per_workgroup: props.max_threads_per_workgroup,
+                kernel_max_threads_per_workgroup: kernel_max_threads,
+                max_workgroup_size: props.max_workgroup_size,
+                max_workgroups: props.max_workgroups,
+                max_dynamic_shared_memory: props
+                    .shared_memory_per_workgroup
+                    .saturating_sub(static_shared_memory as usize),
+            };
+
+            Ok(Kernel {
+                func: function,
+                name: name.to_string(),
+                device: self.device(),
+                module: self.handle.clone(),
+                limits,
+                metadata: None,
+                args_checked: OnceLock::new(),
+            })
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        {
+            let _ = name;
+            Err(GpuError::NoRuntime)
+        }
+    }
+
+    /// Get the address and size of the global variable with the specified name on the device.
+    pub(crate) fn get_global(
+        &self,
+        name: &str,
+    ) -> Result<(*mut std::ffi::c_void, usize), GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut ptr: crate::hip::hipDeviceptr_t = std::ptr::null_mut();
+            let mut size = 0;
+            let global_name = std::ffi::CString::new(name)
+                .map_err(|_| GpuError::InvalidKernelName(name.to_string()))?;
+            let result = runtime().module_get_global(
+                &mut ptr,
+                &mut size,
+                self.handle.module,
+                global_name.as_ptr(),
+            );
+            check(result, "hipModuleGetGlobal", None)?;
+            Ok((ptr, size))
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let mut ptr = 0;
+            let mut size = 0;
+            let global_name = std::ffi::CString::new(name)
+                .map_err(|_| GpuError::InvalidKernelName(name.to_string()))?;
+            let result = cuda::cuModuleGetGlobal(
+                &mut ptr,
+                &mut size,
+                self.handle.module,
+                global_name.as_ptr(),
             );
-            Kernel { func: function }
+            check(result, "cuModuleGetGlobal_v2", None)?;
+            Ok((ptr as *mut _, size))
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        {
+            let _ = name;
+            Err(GpuError::NoRuntime)
         }
     }
 }
@@ -480,19 +1401,287 @@ impl Module {
 #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
 impl Kernel {
     /// Get the raw kernel function.
-    #[cfg(feature = "amd")]
-    pub fn func(&self) -> hip_runtime_sys::hipFunction_t {
+    #[cfg(feature = "hip")]
+    pub fn func(&self) -> crate::hip::hipFunction_t {
         self.func
     }
 
-    /// Launch a kernel, passing the given type as arguments.
+    /// Get the name of the kernel in the compiled binary.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The device this kernel is loaded on.
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    /// The metadata of the kernel from the code object, if the code object contains metadata.
+    pub fn metadata(&self) -> Option<&KernelMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Launch a kernel, passing the given type as arguments, without waiting for it to finish.
     ///
     /// # Safety
     ///
-    /// `T` must be the actual arguments expected by the kernel.
+    /// `T` must be the actual arguments expected by the kernel, packed as described by `layout`.
+    /// All data referenced by the arguments must stay valid until the returned handle is waited
+    /// on or dropped.
+    #[doc(hidden)]
+    pub unsafe fn launch_async_impl<'a, T: ?Sized>(
+        &self,
+        launch_config: &LaunchConfig,
+        layout: ArgLayout<'_>,
+        args: &mut T,
+    ) -> Result<LaunchHandle<'a>, GpuError> {
+        let stream = launch_config.get_stream()?;
+        unsafe { self.launch_async_on(launch_config, stream, layout, args) }
+    }
+
+    /// Launch a kernel on the given stream without waiting for it to finish.
+    ///
+    /// # Safety
+    ///
+    /// See [`Self::launch_async_impl`].
+    pub(crate) unsafe fn launch_async_on<'a, T: ?Sized>(
+        &self,
+        launch_config: &LaunchConfig,
+        stream: Stream,
+        layout: ArgLayout<'_>,
+        args: &mut T,
+    ) -> Result<LaunchHandle<'a>, GpuError> {
+        launch_config.validate()?;
+        self.check_limits(launch_config)?;
+        self.check_device(&stream)?;
+        self.check_args(layout)?;
+        // Create the event first, so we do not fail after launching
+        let event = stream
+            .device()
+            .with_current(Event::try_new_without_timing)?;
+        unsafe {
+            self.enqueue(launch_config, &stream, args)?;
+        }
+        // Mark the end of the kernel in the stream
+        event
+            .record(&stream)
+            .map_err(|e| e.with_kernel(&self.name))?;
+        LaunchHandle::new(event, stream, self.name.clone(), self.module.clone())
+    }
+
+    /// Launch a kernel, passing the given type as arguments, and wait for it to finish.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the actual arguments expected by the kernel, packed as described by `layout`.
+    #[doc(hidden)]
+    pub unsafe fn launch_impl<T: ?Sized>(
+        &self,
+        launch_config: &LaunchConfig,
+        layout: ArgLayout<'_>,
+        args: &mut T,
+    ) -> Result<(), GpuError> {
+        let stream = launch_config.get_stream()?;
+        unsafe { self.launch_on(launch_config, stream, layout, args) }
+    }
+
+    /// Launch a kernel on the given stream and wait for it to finish.
+    ///
+    /// Blocks on the stream instead of an event, so no completion callback is queued.
+    ///
+    /// # Safety
+    ///
+    /// See [`Self::launch_impl`].
+    pub(crate) unsafe fn launch_on<T: ?Sized>(
+        &self,
+        launch_config: &LaunchConfig,
+        stream: Stream,
+        layout: ArgLayout<'_>,
+        args: &mut T,
+    ) -> Result<(), GpuError> {
+        launch_config.validate()?;
+        self.check_limits(launch_config)?;
+        self.check_device(&stream)?;
+        self.check_args(layout)?;
+        unsafe {
+            self.enqueue(launch_config, &stream, args)?;
+        }
+        stream.synchronize().map_err(|e| e.with_kernel(&self.name))
+    }
+
+    /// Launch a kernel multiple times and measure the time it takes on the GPU.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the actual arguments expected by the kernel, packed as described by `layout`,
+    /// and it must be valid to run the kernel multiple times with the same arguments.
     #[doc(hidden)]
-    pub unsafe fn launch_impl<T: ?Sized>(&self, launch_config: &LaunchConfig, args: &mut T) {
-        #[cfg(feature = "amd")]
+    pub unsafe fn bench_impl<T: ?Sized>(
+        &self,
+        launch_config: &LaunchConfig,
+        iterations: u32,
+        layout: ArgLayout<'_>,
+        args: &mut T,
+    ) -> Result<BenchResult, GpuError> {
+        let stream = launch_config.get_stream()?;
+        unsafe { self.bench_on(launch_config, stream, iterations, layout, args) }
+    }
+
+    /// Launch a kernel multiple times on the given stream and measure the time it takes on the GPU.
+    ///
+    /// # Safety
+    ///
+    /// See [`Self::bench_impl`].
+    pub(crate) unsafe fn bench_on<T: ?Sized>(
+        &self,
+        launch_config: &LaunchConfig,
+        stream: Stream,
+        iterations: u32,
+        layout: ArgLayout<'_>,
+        args: &mut T,
+    ) -> Result<BenchResult, GpuError> {
+        if iterations == 0 {
+            return Err(GpuError::InvalidLaunchConfig(
+                "must run at least one iteration".into(),
+            ));
+        }
+        launch_config.validate()?;
+        self.check_limits(launch_config)?;
+        self.check_device(&stream)?;
+        self.check_args(layout)?;
+        let events = stream.device().with_current(|| {
+            (0..iterations)
+                .map(|_| Ok((Event::try_new()?, Event::try_new()?)))
+                .collect::<Result<Vec<_>, GpuError>>()
+        })?;
+
+        let with_kernel = |e: GpuError| e.with_kernel(&self.name);
+        // Warm up
+        unsafe {
+            self.enqueue(launch_config, &stream, args)?;
+        }
+        for (start, end) in &events {
+            start.record(&stream).map_err(with_kernel)?;
+            unsafe {
+                self.enqueue(launch_config, &stream, args)?;
+            }
+            end.record(&stream).map_err(with_kernel)?;
+        }
+        stream.synchronize().map_err(with_kernel)?;
+
+        let times = events
+            .iter()
+            .map(|(start, end)| start.elapsed_time(end))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(with_kernel)?;
+        Ok(BenchResult::from_times(times))
+    }
+
+    /// Check that the launch config is within the limits of the device and the kernel.
+    fn check_limits(&self, launch_config: &LaunchConfig) -> Result<(), GpuError> {
+        let limits = &self.limits;
+        let device = self.device.index();
+        let invalid = |reason: String| Err(GpuError::InvalidLaunchConfig(reason));
+        // Checked by `LaunchConfig::validate`
+        let workgroups = launch_config.workgroups.unwrap_or_default();
+        let threads_per_workgroup = launch_config.threads_per_workgroup.unwrap_or_default();
+
+        for (i, dimension) in DIMENSIONS.iter().enumerate() {
+            if threads_per_workgroup[i] > limits.max_workgroup_size[i] {
+                return invalid(format!(
+                    "threads_per_workgroup is {} in dimension {dimension}, but device {device} \
+                     supports at most {}",
+                    threads_per_workgroup[i], limits.max_workgroup_size[i]
+                ));
+            }
+            if workgroups[i] > limits.max_workgroups[i] {
+                return invalid(format!(
+                    "workgroups is {} in dimension {dimension}, but device {device} supports at \
+                     most {}",
+                    workgroups[i], limits.max_workgroups[i]
+                ));
+            }
+        }
+
+        let threads = threads_per_workgroup
+            .iter()
+            .map(|t| *t as u64)
+            .product::<u64>();
+        if threads > limits.device_max_threads_per_workgroup as u64 {
+            return invalid(format!(
+                "threads_per_workgroup {threads_per_workgroup:?} has {threads} threads, but device \
+                 {device} supports at most {} threads per workgroup",
+                limits.device_max_threads_per_workgroup
+            ));
+        }
+        if threads > limits.kernel_max_threads_per_workgroup as u64 {
+            return invalid(format!(
+                "threads_per_workgroup {threads_per_workgroup:?} has {threads} threads, but kernel \
+                 `{}` was compiled for at most {} threads per workgroup",
+                self.name, limits.kernel_max_threads_per_workgroup
+            ));
+        }
+        if launch_config.dynamic_shared_memory as usize > limits.max_dynamic_shared_memory {
+            return invalid(format!(
+                "dynamic_shared_memory is {} bytes, but only {} bytes are available for kernel \
+                 `{}` on device {device}",
+                launch_config.dynamic_shared_memory, limits.max_dynamic_shared_memory, self.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that the arguments packed on the host match the metadata of the kernel.
+    ///
+    /// The layout is the same for every launch, so it is only checked once.
+    /// Packed arguments can change between launches and are checked every time.
+    /// Kernels without metadata are not checked.
+    fn check_args(&self, layout: ArgLayout<'_>) -> Result<(), GpuError> {
+        match layout {
+            ArgLayout::Declared(layout) => self
+                .args_checked
+                .get_or_init(|| match &self.metadata {
+                    Some(metadata) => metadata.check_arg_layout(layout),
+                    None => Ok(()),
+                })
+                .clone(),
+            ArgLayout::Packed(size) => match &self.metadata {
+                Some(metadata) => metadata.check_arg_layout(&[KernelArgLayout {
+                    name: "packed arguments",
+                    offset: 0,
+                    size,
+                }]),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Check that the stream belongs to the device the kernel is loaded on.
+    fn check_device(&self, stream: &Stream) -> Result<(), GpuError> {
+        if stream.device() != self.device {
+            return Err(GpuError::InvalidLaunchConfig(format!(
+                "kernel `{}` is loaded on device {} but the stream belongs to device {}",
+                self.name,
+                self.device.index(),
+                stream.device().index()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Queue the kernel on a stream.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the actual arguments expected by the kernel.
+    #[cfg_attr(not(any(feature = "hip", feature = "nvidia")), allow(unused_variables))]
+    unsafe fn enqueue<T: ?Sized>(
+        &self,
+        launch_config: &LaunchConfig,
+        stream: &Stream,
+        args: &mut T,
+    ) -> Result<(), GpuError> {
+        #[cfg(feature = "hip")]
         {
             use std::ffi;
 
@@ -507,17 +1696,11 @@ impl Kernel {
                 0x3 as *mut ffi::c_void,                          // End
             ];
 
-            let workgroups = launch_config
-                .workgroups
-                .expect("Must set `workgroups` in LaunchConfig");
-            let threads_per_workgroup = launch_config
-                .threads_per_workgroup
-                .expect("Must set `threads_per_workgroup` in LaunchConfig");
+            let (workgroups, threads_per_workgroup) = launch_config.dimensions()?;
 
             unsafe {
-                let stream = thread_local_stream();
-                // Launch two workgroups (2x1x1), each of the size (LEN/2)x1x1
-                let result = hip_runtime_sys::hipModuleLaunchKernel(
+                // Arguments are copied when launching, so they can be freed afterwards
+                let result = runtime().module_launch_kernel(
                     self.func,
                     workgroups[0],
                     workgroups[1],
@@ -525,16 +1708,47 @@ impl Kernel {
                     threads_per_workgroup[0],
                     threads_per_workgroup[1],
                     threads_per_workgroup[2],
-                    0,                    // sharedMemBytes for extern shared variables
-                    stream,               // stream
-                    std::ptr::null_mut(), // params (unimplemented in hip)
-                    config.as_mut_ptr(),  // arguments
+                    launch_config.dynamic_shared_memory, // sharedMemBytes for extern shared variables
+                    stream.raw(),                        // stream
+                    std::ptr::null_mut(),                // params (unimplemented in hip)
+                    config.as_mut_ptr(),                 // arguments
                 );
-                assert_eq!(result, hipSuccess, "Failed to launch kernel");
+                check(result, "hipModuleLaunchKernel", Some(&self.name))?;
+            }
+        }
+        #[cfg(feature = "nvidia")]
+        {
+            use std::ffi;
 
-                let result = hip_runtime_sys::hipStreamSynchronize(stream);
-                assert_eq!(result, hipSuccess, "Failed to wait for kernel to finish");
+            let mut size = std::mem::size_of_val(args);
+            let mut config = [
+                cuda::CU_LAUNCH_PARAM_BUFFER_POINTER,
+                args as *mut _ as *mut ffi::c_void,
+                cuda::CU_LAUNCH_PARAM_BUFFER_SIZE,
+                std::ptr::addr_of_mut!(size) as *mut ffi::c_void,
+                cuda::CU_LAUNCH_PARAM_END,
+            ];
+
+            let (workgroups, threads_per_workgroup) = launch_config.dimensions()?;
+
+            unsafe {
+                // Arguments are copied when launching, so they can be freed afterwards
+                let result = cuda::cuLaunchKernel(
+                    self.func,
+                    workgroups[0],
+                    workgroups[1],
+                    workgroups[2],
+                    threads_per_workgroup[0],
+                    threads_per_workgroup[1],
+                    threads_per_workgroup[2],
+                    launch_config.dynamic_shared_memory,
+                    stream.raw(),
+                    std::ptr::null_mut(),
+                    config.as_mut_ptr(),
+                );
+                check(result, "cuLaunchKernel", Some(&self.name))?;
             }
         }
+        Ok(())
     }
 }
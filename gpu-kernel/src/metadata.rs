@@ -0,0 +1,709 @@
+use crate::GpuError;
+
+/// The metadata of a compiled AMDGPU code object, listing all kernels it contains.
+///
+/// Code objects are ELF files that contain a note with msgpack encoded metadata, see the
+/// [LLVM AMDGPU documentation] for the format.
+/// Parsing works entirely offline, no GPU is needed.
+///
+/// # Example
+///
+/// ```no_run
+/// # use gpu_kernel::CodeObjectMetadata;
+/// let data = std::fs::read("kernels.elf").unwrap();
+/// let metadata = CodeObjectMetadata::parse(&data).unwrap();
+/// println!("Compiled for {}", metadata.gfx());
+/// for kernel in &metadata.kernels {
+///     println!(
+///         "{}: {} SGPRs, {} VGPRs, {} bytes of arguments",
+///         kernel.name, kernel.sgpr_count, kernel.vgpr_count, kernel.kernarg_segment_size
+///     );
+/// }
+/// ```
+///
+/// [LLVM AMDGPU documentation]: https://llvm.org/docs/AMDGPUUsage.html#code-object-v3-metadata
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CodeObjectMetadata {
+    /// The version of the metadata format as `[major, minor]`.
+    pub version: [u32; 2],
+    /// The target id the code object was compiled for, e.g. `amdgcn-amd-amdhsa--gfx90a:xnack-`.
+    pub target: String,
+    /// All kernels in the code object.
+    pub kernels: Vec<KernelMetadata>,
+}
+
+/// The metadata of a single kernel in a code object, see [`CodeObjectMetadata`].
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct KernelMetadata {
+    /// The name of the kernel.
+    pub name: String,
+    /// The name of the kernel descriptor symbol, usually the name with a `.kd` suffix.
+    pub symbol: String,
+    /// The arguments of the kernel in the kernarg segment, including hidden arguments.
+    pub args: Vec<KernelArgMetadata>,
+    /// The size of the kernarg segment in bytes.
+    pub kernarg_segment_size: u32,
+    /// The alignment of the kernarg segment in bytes.
+    pub kernarg_segment_align: u32,
+    /// The size of statically allocated shared memory (LDS) per workgroup in bytes.
+    pub group_segment_fixed_size: u32,
+    /// The size of private (scratch) memory per thread in bytes, excluding a dynamic stack.
+    pub private_segment_fixed_size: u32,
+    /// The number of scalar registers (SGPRs) used by each wavefront.
+    pub sgpr_count: u32,
+    /// The number of vector registers (VGPRs) used by each thread.
+    pub vgpr_count: u32,
+    /// The number of accumulation registers (AGPRs) used by each thread, if reported.
+    pub agpr_count: Option<u32>,
+    /// The number of spilled SGPRs, if reported.
+    pub sgpr_spill_count: Option<u32>,
+    /// The number of spilled VGPRs, if reported.
+    pub vgpr_spill_count: Option<u32>,
+    /// The number of threads in a wavefront, 32 or 64.
+    pub wavefront_size: u32,
+    /// The maximum number of threads in a workgroup that the kernel was compiled for.
+    pub max_flat_workgroup_size: u32,
+}
+
+/// The metadata of a single kernel argument, see [`KernelMetadata`].
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct KernelArgMetadata {
+    /// The name of the argument, if known.
+    pub name: Option<String>,
+    /// The offset of the argument in the kernarg segment in bytes.
+    pub offset: u32,
+    /// The size of the argument in bytes.
+    pub size: u32,
+    /// The kind of the argument, e.g. `by_value`, `global_buffer` or `hidden_block_count_x`.
+    pub value_kind: String,
+    /// The address space for pointer arguments, e.g. `global`.
+    pub address_space: Option<String>,
+}
+
+/// The position of a kernel argument as packed on the host.
+///
+/// Generated by the `#[kernel]` macro to check that it matches the metadata of the compiled
+/// kernel.
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug)]
+pub struct KernelArgLayout {
+    /// The name of the argument in the kernel signature.
+    pub name: &'static str,
+    /// The offset of the argument in bytes.
+    pub offset: usize,
+    /// The size of the argument in bytes.
+    pub size: usize,
+}
+
+/// How the arguments of a launch are packed on the host.
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug)]
+pub enum ArgLayout<'a> {
+    /// Arguments packed by the `#[kernel]` macro, the layout is the same for every launch.
+    Declared(&'a [KernelArgLayout]),
+    /// All arguments packed into bytes by the caller, see [`crate::KernelFn::launch_packed`].
+    ///
+    /// Contains the size of the packed arguments, which can change between launches.
+    Packed(usize),
+}
+
+impl CodeObjectMetadata {
+    /// Parse the metadata from a code object ELF file.
+    pub fn parse(elf: &[u8]) -> Result<Self, GpuError> {
+        let note = find_metadata_note(elf)?;
+        let mut reader = Reader {
+            data: note,
+            depth: 0,
+        };
+        let value = reader.read()?;
+        Self::from_value(&value)
+    }
+
+    /// The gfx version of the target, e.g. `gfx90a`.
+    pub fn gfx(&self) -> &str {
+        let target = self.target.split("--").nth(1).unwrap_or_default();
+        target.split(':').next().unwrap_or_default()
+    }
+
+    /// Get the metadata of the kernel with the given name.
+    pub fn kernel(&self, name: &str) -> Option<&KernelMetadata> {
+        self.kernels.iter().find(|k| k.name == name)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, GpuError> {
+        let version = match value.get("amdhsa.version")? {
+            Value::Array(v) if v.len() == 2 => [v[0].as_u32()?, v[1].as_u32()?],
+            _ => return Err(invalid("amdhsa.version is not a pair of integers")),
+        };
+        let kernels = value
+            .get("amdhsa.kernels")?
+            .as_array()?
+            .iter()
+            .map(KernelMetadata::from_value)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            version,
+            target: value.get("amdhsa.target")?.as_str()?.to_string(),
+            kernels,
+        })
+    }
+}
+
+impl KernelMetadata {
+    fn from_value(value: &Value) -> Result<Self, GpuError> {
+        let u32_field = |key| value.get(key)?.as_u32();
+        let optional_u32 = |key| value.get_optional(key).map(Value::as_u32).transpose();
+        let args = match value.get_optional(".args") {
+            Some(args) => args
+                .as_array()?
+                .iter()
+                .map(KernelArgMetadata::from_value)
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            name: value.get(".name")?.as_str()?.to_string(),
+            symbol: value.get(".symbol")?.as_str()?.to_string(),
+            args,
+            kernarg_segment_size: u32_field(".kernarg_segment_size")?,
+            kernarg_segment_align: u32_field(".kernarg_segment_align")?,
+            group_segment_fixed_size: u32_field(".group_segment_fixed_size")?,
+            private_segment_fixed_size: u32_field(".private_segment_fixed_size")?,
+            sgpr_count: u32_field(".sgpr_count")?,
+            vgpr_count: u32_field(".vgpr_count")?,
+            agpr_count: optional_u32(".agpr_count")?,
+            sgpr_spill_count: optional_u32(".sgpr_spill_count")?,
+            vgpr_spill_count: optional_u32(".vgpr_spill_count")?,
+            wavefront_size: u32_field(".wavefront_size")?,
+            max_flat_workgroup_size: u32_field(".max_flat_workgroup_size")?,
+        })
+    }
+}
+
+impl KernelMetadata {
+    /// Check that arguments packed on the host match the arguments expected by the kernel.
+    ///
+    /// Rust arguments can be split into multiple arguments on the GPU, e.g. a slice is passed as
+    /// pointer and length.
+    /// So each argument on the host needs to start where an argument in the metadata starts and
+    /// cover the following arguments in the metadata completely.
+    pub(crate) fn check_arg_layout(&self, layout: &[KernelArgLayout]) -> Result<(), GpuError> {
+        let mismatch = |reason: String| {
+            Err(GpuError::KernelArgumentMismatch(format!(
+                "kernel `{}`: {reason}",
+                self.name
+            )))
+        };
+        let explicit = self
+            .args
+            .iter()
+            .filter(|a| !a.value_kind.starts_with("hidden_"))
+            .collect::<Vec<_>>();
+
+        let mut i = 0;
+        for arg in layout {
+            // Zero-sized arguments are not passed to the kernel
+            if arg.size == 0 {
+                continue;
+            }
+            let end = arg.offset + arg.size;
+            match explicit.get(i) {
+                Some(expected) if expected.offset as usize == arg.offset => {}
+                Some(expected) => {
+                    return mismatch(format!(
+                        "argument `{}` is passed at offset {}, but the kernel expects an argument \
+                         at offset {}",
+                        arg.name, arg.offset, expected.offset
+                    ));
+                }
+                None => {
+                    return mismatch(format!(
+                        "argument `{}` is passed at offset {}, but the kernel expects no more \
+                         arguments",
+                        arg.name, arg.offset
+                    ));
+                }
+            }
+            // Skip all parts of this argument
+            while let Some(expected) = explicit.get(i)
+                && (expected.offset as usize) < end
+            {
+                let expected_end = expected.offset as usize + expected.size as usize;
+                if expected_end > end {
+                    return mismatch(format!(
+                        "argument `{}` is passed with {} bytes, but the kernel expects {} bytes",
+                        arg.name,
+                        arg.size,
+                        expected_end - arg.offset
+                    ));
+                }
+                i += 1;
+            }
+        }
+        if let Some(expected) = explicit.get(i) {
+            let name = expected
+                .name
+                .as_ref()
+                .map(|n| format!(" `{n}`"))
+                .unwrap_or_default();
+            return mismatch(format!(
+                "the kernel expects an argument{name} at offset {} that is not passed",
+                expected.offset
+            ));
+        }
+
+        let size = layout.last().map(|a| a.offset + a.size).unwrap_or_default();
+        if size > self.kernarg_segment_size as usize {
+            return mismatch(format!(
+                "arguments are {size} bytes, but the kernarg segment has only {} bytes",
+                self.kernarg_segment_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl KernelArgMetadata {
+    fn from_value(value: &Value) -> Result<Self, GpuError> {
+        let optional_str = |key| {
+            value
+                .get_optional(key)
+                .map(|v| v.as_str().map(str::to_string))
+                .transpose()
+        };
+        Ok(Self {
+            name: optional_str(".name")?,
+            offset: value.get(".offset")?.as_u32()?,
+            size: value.get(".size")?.as_u32()?,
+            value_kind: value.get(".value_kind")?.as_str()?.to_string(),
+            address_space: optional_str(".address_space")?,
+        })
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> GpuError {
+    GpuError::InvalidCodeObject(reason.into())
+}
+
+/// Read a little-endian integer of `N` bytes at `offset`.
+fn read_le<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], GpuError> {
+    offset
+        .checked_add(N)
+        .and_then(|end| data.get(offset..end))
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| invalid("unexpected end of ELF file"))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, GpuError> {
+    read_le(data, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, GpuError> {
+    read_le(data, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, GpuError> {
+    read_le(data, offset).map(u64::from_le_bytes)
+}
+
+/// Find the content of the `NT_AMDGPU_METADATA` note in an ELF file.
+fn find_metadata_note(elf: &[u8]) -> Result<&[u8], GpuError> {
+    const SHT_NOTE: u32 = 7;
+    const NT_AMDGPU_METADATA: u32 = 32;
+
+    if elf.get(..4) != Some(b"\x7fELF") {
+        return Err(invalid("not an ELF file"));
+    }
+    // 64-bit, little-endian
+    if elf.get(4..6) != Some(&[2, 1]) {
+        return Err(invalid("not a 64-bit little-endian ELF file"));
+    }
+
+    let section_offset = read_u64(elf, 0x28)? as usize;
+    let section_size = read_u16(elf, 0x3a)? as usize;
+    let section_count = read_u16(elf, 0x3c)? as usize;
+    let out_of_bounds = || invalid("section header out of bounds");
+    for i in 0..section_count {
+        let header = i
+            .checked_mul(section_size)
+            .and_then(|o| o.checked_add(section_offset))
+            .ok_or_else(out_of_bounds)?;
+        let field = |offset: usize| header.checked_add(offset).ok_or_else(out_of_bounds);
+        if read_u32(elf, field(4)?)? != SHT_NOTE {
+            continue;
+        }
+        let offset = read_u64(elf, field(0x18)?)? as usize;
+        let size = read_u64(elf, field(0x20)?)? as usize;
+        let notes = offset
+            .checked_add(size)
+            .and_then(|end| elf.get(offset..end))
+            .ok_or_else(|| invalid("note section out of bounds"))?;
+
+        // Iterate over notes in the section
+        let mut pos = 0;
+        while notes.len() - pos >= 12 {
+            let name_size = read_u32(notes, pos)? as usize;
+            let desc_size = read_u32(notes, pos + 4)? as usize;
+            let ty = read_u32(notes, pos + 8)?;
+            let name_start = pos + 12;
+            let name = name_start
+                .checked_add(name_size)
+                .and_then(|end| notes.get(name_start..end))
+                .ok_or_else(|| invalid("note out of bounds"))?;
+            let desc_start = name_start + name_size.next_multiple_of(4);
+            let desc = desc_start
+                .checked_add(desc_size)
+                .and_then(|end| notes.get(desc_start..end))
+                .ok_or_else(|| invalid("note out of bounds"))?;
+            if name == b"AMDGPU\0" && ty == NT_AMDGPU_METADATA {
+                return Ok(desc);
+            }
+            pos = desc_start + desc_size.next_multiple_of(4);
+            if pos > notes.len() {
+                break;
+            }
+        }
+    }
+    Err(invalid("no AMDGPU metadata note found"))
+}
+
+/// A decoded msgpack value.
+// Some contents are only read through `Debug` in error messages
+#[allow(dead_code)]
+#[derive(Debug)]
+enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Str(String),
+    Bin(Vec<u8>),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    fn get_optional(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| matches!(k, Self::Str(k) if k == key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<&Value, GpuError> {
+        self.get_optional(key)
+            .ok_or_else(|| invalid(format!("missing metadata field {key}")))
+    }
+
+    fn as_u32(&self) -> Result<u32, GpuError> {
+        let value = match self {
+            Self::Int(i) => u32::try_from(*i).ok(),
+            Self::UInt(i) => u32::try_from(*i).ok(),
+            _ => None,
+        };
+        value.ok_or_else(|| invalid(format!("expected an integer but got {self:?}")))
+    }
+
+    fn as_str(&self) -> Result<&str, GpuError> {
+        match self {
+            Self::Str(s) => Ok(s),
+            _ => Err(invalid(format!("expected a string but got {self:?}"))),
+        }
+    }
+
+    fn as_array(&self) -> Result<&[Value], GpuError> {
+        match self {
+            Self::Array(a) => Ok(a),
+            _ => Err(invalid(format!("expected an array but got {self:?}"))),
+        }
+    }
+}
+
+/// A minimal msgpack decoder, supporting the types used in code object metadata.
+struct Reader<'a> {
+    data: &'a [u8],
+    /// The number of arrays and maps containing the current value.
+    depth: u32,
+}
+
+impl Reader<'_> {
+    /// Metadata is nested only a few levels deep, this limits recursion on malformed input.
+    const MAX_DEPTH: u32 = 32;
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], GpuError> {
+        if self.data.len() < len {
+            return Err(invalid("unexpected end of metadata"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], GpuError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, GpuError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, GpuError> {
+        self.array().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, GpuError> {
+        self.array().map(u32::from_be_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, GpuError> {
+        self.array().map(u64::from_be_bytes)
+    }
+
+    fn string(&mut self, len: usize) -> Result<Value, GpuError> {
+        let s = std::str::from_utf8(self.bytes(len)?)
+            .map_err(|_| invalid("metadata string is not UTF-8"))?;
+        Ok(Value::Str(s.to_string()))
+    }
+
+    fn list(&mut self, len: usize) -> Result<Value, GpuError> {
+        self.nested(|reader| {
+            (0..len)
+                .map(|_| reader.read())
+                .collect::<Result<_, _>>()
+                .map(Value::Array)
+        })
+    }
+
+    fn map(&mut self, len: usize) -> Result<Value, GpuError> {
+        self.nested(|reader| {
+            (0..len)
+                .map(|_| Ok((reader.read()?, reader.read()?)))
+                .collect::<Result<_, _>>()
+                .map(Value::Map)
+        })
+    }
+
+    fn nested(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<Value, GpuError>,
+    ) -> Result<Value, GpuError> {
+        if self.depth >= Self::MAX_DEPTH {
+            return Err(invalid("metadata is nested too deeply"));
+        }
+        self.depth += 1;
+        let value = read(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn read(&mut self) -> Result<Value, GpuError> {
+        let marker = self.u8()?;
+        Ok(match marker {
+            0x00..=0x7f => Value::UInt(marker as u64),
+            0x80..=0x8f => self.map((marker & 0xf) as usize)?,
+            0x90..=0x9f => self.list((marker & 0xf) as usize)?,
+            0xa0..=0xbf => self.string((marker & 0x1f) as usize)?,
+            0xc0 => Value::Nil,
+            0xc2 => Value::Bool(false),
+            0xc3 => Value::Bool(true),
+            0xc4 => {
+                let len = self.u8()? as usize;
+                Value::Bin(self.bytes(len)?.to_vec())
+            }
+            0xc5 => {
+                let len = self.u16()? as usize;
+                Value::Bin(self.bytes(len)?.to_vec())
+            }
+            0xc6 => {
+                let len = self.u32()? as usize;
+                Value::Bin(self.bytes(len)?.to_vec())
+            }
+            0xca => Value::Float(f32::from_be_bytes(self.array()?) as f64),
+            0xcb => Value::Float(f64::from_be_bytes(self.array()?)),
+            0xcc => Value::UInt(self.u8()? as u64),
+            0xcd => Value::UInt(self.u16()? as u64),
+            0xce => Value::UInt(self.u32()? as u64),
+            0xcf => Value::UInt(self.u64()?),
+            0xd0 => Value::Int(i8::from_be_bytes(self.array()?) as i64),
+            0xd1 => Value::Int(i16::from_be_bytes(self.array()?) as i64),
+            0xd2 => Value::Int(i32::from_be_bytes(self.array()?) as i64),
+            0xd3 => Value::Int(i64::from_be_bytes(self.array()?)),
+            0xd9 => {
+                let len = self.u8()? as usize;
+                self.string(len)?
+            }
+            0xda => {
+                let len = self.u16()? as usize;
+                self.string(len)?
+            }
+            0xdb => {
+                let len = self.u32()? as usize;
+                self.string(len)?
+            }
+            0xdc => {
+                let len = self.u16()? as usize;
+                self.list(len)?
+            }
+            0xdd => {
+                let len = self.u32()? as usize;
+                self.list(len)?
+            }
+            0xde => {
+                let len = self.u16()? as usize;
+                self.map(len)?
+            }
+            0xdf => {
+                let len = self.u32()? as usize;
+                self.map(len)?
+            }
+            0xe0..=0xff => Value::Int(marker as i8 as i64),
+            _ => {
+                return Err(invalid(format!(
+                    "unsupported msgpack type {marker:#x} in metadata"
+                )));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE_OBJECT: &[u8] = include_bytes!("../tests/data/add.co");
+
+    #[test]
+    fn parse_code_object() {
+        let metadata = CodeObjectMetadata::parse(CODE_OBJECT).unwrap();
+        assert_eq!(metadata.version, [1, 1]);
+        assert_eq!(metadata.target, "amdgcn-amd-amdhsa--gfx90a");
+        assert_eq!(metadata.gfx(), "gfx90a");
+        assert_eq!(metadata.kernels.len(), 1);
+
+        let kernel = metadata.kernel("add").unwrap();
+        assert_eq!(kernel.symbol, "add.kd");
+        assert_eq!(kernel.kernarg_segment_size, 12);
+        assert_eq!(kernel.kernarg_segment_align, 8);
+        assert_eq!(kernel.wavefront_size, 64);
+        assert_eq!(kernel.max_flat_workgroup_size, 1024);
+        assert_eq!(kernel.agpr_count, None);
+        assert_eq!(kernel.vgpr_spill_count, Some(0));
+        let args = kernel
+            .args
+            .iter()
+            .map(|a| (a.name.as_deref(), a.offset, a.size, a.value_kind.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            [
+                (Some("out"), 0, 8, "global_buffer"),
+                (Some("value"), 8, 4, "by_value"),
+            ]
+        );
+        assert_eq!(kernel.args[0].address_space.as_deref(), Some("global"));
+        assert!(metadata.kernel("sub").is_none());
+    }
+
+    #[test]
+    fn check_arg_layout() {
+        let metadata = CodeObjectMetadata::parse(CODE_OBJECT).unwrap();
+        let kernel = metadata.kernel("add").unwrap();
+        let arg = |name, offset, size| KernelArgLayout { name, offset, size };
+        assert!(
+            kernel
+                .check_arg_layout(&[arg("out", 0, 8), arg("value", 8, 4)])
+                .is_ok()
+        );
+        // Packed arguments cover all arguments of the kernel
+        assert!(kernel.check_arg_layout(&[arg("packed", 0, 12)]).is_ok());
+
+        let mismatch = |layout: &[KernelArgLayout]| {
+            matches!(
+                kernel.check_arg_layout(layout),
+                Err(GpuError::KernelArgumentMismatch(_))
+            )
+        };
+        assert!(mismatch(&[arg("out", 0, 8)]));
+        assert!(mismatch(&[arg("out", 0, 8), arg("value", 12, 4)]));
+        assert!(mismatch(&[arg("out", 0, 8), arg("value", 8, 8)]));
+        assert!(mismatch(&[arg("packed", 0, 4)]));
+    }
+
+    /// The offset of the `NT_AMDGPU_METADATA` note header in [`CODE_OBJECT`].
+    fn note_offset() -> usize {
+        let name = CODE_OBJECT.windows(7).position(|w| w == b"AMDGPU\0");
+        name.unwrap() - 12
+    }
+
+    #[test]
+    fn truncated() {
+        // Section headers are at the end of the file, after the metadata note
+        let note_end =
+            note_offset() + 12 + 8 + read_u32(CODE_OBJECT, note_offset() + 4).unwrap() as usize;
+        for len in 0..CODE_OBJECT.len() {
+            let result = CodeObjectMetadata::parse(&CODE_OBJECT[..len]);
+            if len <= note_end {
+                assert!(
+                    matches!(result, Err(GpuError::InvalidCodeObject(_))),
+                    "prefix of {len} bytes: {result:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_headers() {
+        let patched = |offset: usize, bytes: &[u8]| {
+            let mut data = CODE_OBJECT.to_vec();
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+            CodeObjectMetadata::parse(&data)
+        };
+        // Section header table out of bounds or overflowing
+        assert!(patched(0x28, &u64::MAX.to_le_bytes()).is_err());
+        assert!(patched(0x28, &(u64::MAX - 0x100).to_le_bytes()).is_err());
+        assert!(patched(0x3a, &u16::MAX.to_le_bytes()).is_err());
+        assert!(patched(0x3c, &0u16.to_le_bytes()).is_err());
+        // Not an ELF file or wrong class
+        assert!(patched(0, b"\x7fFLE").is_err());
+        assert!(patched(4, &[1]).is_err());
+
+        // Note name and description sizes out of bounds
+        let note = note_offset();
+        assert!(patched(note, &u32::MAX.to_le_bytes()).is_err());
+        assert!(patched(note + 4, &u32::MAX.to_le_bytes()).is_err());
+        assert!(patched(note + 4, &0x10000u32.to_le_bytes()).is_err());
+    }
+
+    #[test]
+    fn corrupted_bytes_do_not_panic() {
+        for i in 0..CODE_OBJECT.len() {
+            for byte in [0x00, 0x7f, 0xff] {
+                let mut data = CODE_OBJECT.to_vec();
+                data[i] = byte;
+                let _ = CodeObjectMetadata::parse(&data);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_msgpack() {
+        let read = |data: &[u8]| Reader { data, depth: 0 }.read();
+        assert!(matches!(read(&[0x92, 0x01, 0x02]), Ok(Value::Array(a)) if a.len() == 2));
+        // Truncated values
+        assert!(read(&[]).is_err());
+        assert!(read(&[0x92, 0x01]).is_err());
+        assert!(read(&[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(read(&[0xdb, 0xff, 0xff, 0xff, 0xff, b'a']).is_err());
+        assert!(read(&[0xa3, 0xff, 0xfe, 0xfd]).is_err());
+        assert!(read(&[0xc1]).is_err());
+        // Deep nesting must not overflow the stack
+        assert!(read(&[0x91; 100_000]).is_err());
+    }
+}
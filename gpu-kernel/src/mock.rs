@@ -0,0 +1,790 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, RefCell};
+use std::ffi::{CStr, CString, c_char, c_int, c_uint, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::hip::hipError_t::{self, *};
+use crate::hip::{
+    hipDeviceProp_t, hipDeviceptr_t, hipEvent_t, hipFunction_attribute, hipFunction_t, hipHostFn_t,
+    hipModule_t, hipStream_t,
+};
+
+use crate::runtime::Runtime;
+
+/// A call into the GPU runtime, recorded by [`record`].
+///
+/// Calls that create, destroy or modify memory and runtime objects are recorded.
+/// Queries, synchronization and switching the current device are not.
+///
+/// Addresses and handles are given as integers.
+/// Handles of modules, streams and events are unique for the lifetime of the program.
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Call {
+    /// Memory was allocated with `hipMalloc` or `hipMallocManaged`.
+    ///
+    /// With the `amd-allocator` feature, every heap allocation on the CPU is managed memory.
+    Malloc {
+        /// The address of the allocation.
+        ptr: usize,
+        /// The size of the allocation in bytes.
+        size: usize,
+        /// If the memory was allocated with `hipMallocManaged`.
+        managed: bool,
+    },
+    /// Memory was freed with `hipFree`.
+    Free {
+        /// The address of the allocation.
+        ptr: usize,
+    },
+    /// Pinned memory was allocated on the CPU with `hipHostMalloc`.
+    HostMalloc {
+        /// The address of the allocation.
+        ptr: usize,
+        /// The size of the allocation in bytes.
+        size: usize,
+    },
+    /// Pinned memory was freed with `hipHostFree`.
+    HostFree {
+        /// The address of the allocation.
+        ptr: usize,
+    },
+    /// Memory was copied with `hipMemcpyHtoD`, `hipMemcpyDtoH`, `hipMemcpyDtoD` or
+    /// `hipMemcpyDtoHAsync`.
+    Memcpy {
+        /// The destination address.
+        dst: usize,
+        /// The source address.
+        src: usize,
+        /// The number of copied bytes.
+        size: usize,
+    },
+    /// A module was loaded with `hipModuleLoadData`.
+    ModuleLoad {
+        /// The handle of the module.
+        module: usize,
+    },
+    /// A module was unloaded with `hipModuleUnload`.
+    ModuleUnload {
+        /// The handle of the module.
+        module: usize,
+    },
+    /// A stream was created with `hipStreamCreate`.
+    StreamCreate {
+        /// The handle of the stream.
+        stream: usize,
+    },
+    /// A stream was destroyed with `hipStreamDestroy`.
+    StreamDestroy {
+        /// The handle of the stream.
+        stream: usize,
+    },
+    /// An event was created with `hipEventCreate` or `hipEventCreateWithFlags`.
+    EventCreate {
+        /// The handle of the event.
+        event: usize,
+        /// If the event can measure time, `false` if it was created with `hipEventDisableTiming`.
+        timing: bool,
+    },
+    /// An event was recorded on a stream with `hipEventRecord`.
+    EventRecord {
+        /// The handle of the event.
+        event: usize,
+        /// The handle of the stream.
+        stream: usize,
+    },
+    /// An event was destroyed with `hipEventDestroy`.
+    EventDestroy {
+        /// The handle of the event.
+        event: usize,
+    },
+    /// A kernel was launched with `hipModuleLaunchKernel`.
+    Launch {
+        /// The name of the kernel in the compiled binary.
+        kernel: String,
+        /// The number of workgroups in each dimension.
+        workgroups: [u32; 3],
+        /// The number of threads in a workgroup in each dimension.
+        threads_per_workgroup: [u32; 3],
+        /// The size of dynamic shared memory in bytes.
+        dynamic_shared_memory: u32,
+        /// The handle of the stream.
+        stream: usize,
+        /// The packed kernel arguments.
+        ///
+        /// Padding between arguments has unspecified values.
+        args: Vec<u8>,
+    },
+}
+
+/// The mocked runtime, see the [module documentation](self).
+pub(crate) struct Mock;
+
+/// Allocations are aligned like allocations of the HIP runtime.
+///
+/// The size of an allocation is stored in front of it.
+const ALIGNMENT: usize = 256;
+/// The architecture of the mocked devices.
+const ARCH: &str = "gfx90a:sramecc+:xnack-";
+const COMPUTE_UNITS: c_int = 104;
+const MAX_THREADS_PER_COMPUTE_UNIT: c_int = 2048;
+const MAX_THREADS_PER_WORKGROUP: c_int = 1024;
+
+static DEVICE_COUNT: AtomicU32 = AtomicU32::new(1);
+/// The last handle that was given out.
+static LAST_HANDLE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static CURRENT_DEVICE: Cell<c_int> = const { Cell::new(0) };
+    /// The calls recorded by the innermost [`record`] on this thread.
+    static RECORDING: RefCell<Option<Vec<Call>>> = const { RefCell::new(None) };
+    /// Set while the mock does internal work, e.g. allocating memory to record a call.
+    /// Runtime calls made during that time are not recorded.
+    static UNTRACKED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f` and return the runtime calls that it made on this thread.
+///
+/// Calls of other threads are not recorded.
+/// Nested calls only record in the innermost `record`.
+pub fn record<R>(f: impl FnOnce() -> R) -> (R, Vec<Call>) {
+    /// Restores the recording of an outer `record`, also if `f` panics.
+    struct Restore(Option<Vec<Call>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            // Free the recorded calls after the recording was replaced, freeing is recorded too
+            let _calls = RECORDING.replace(self.0.take());
+        }
+    }
+
+    let mut restore = Restore(RECORDING.replace(Some(Vec::new())));
+    let result = f();
+    let calls = RECORDING.replace(restore.0.take()).unwrap_or_default();
+    std::mem::forget(restore);
+    (result, calls)
+}
+
+/// Set the number of devices that the mock pretends to have, `1` by default.
+///
+/// The count is shared by all threads.
+/// Every device has the same properties, they resemble an AMD Instinct MI250X (`gfx90a`).
+pub fn set_device_count(count: u32) {
+    DEVICE_COUNT.store(count, Ordering::Relaxed);
+}
+
+/// Run `f` without recording the runtime calls it makes.
+fn untracked<R>(f: impl FnOnce() -> R) -> R {
+    let previous = UNTRACKED.replace(true);
+    let result = f();
+    UNTRACKED.set(previous);
+    result
+}
+
+/// Add a call to the current recording, if there is one.
+fn push(call: impl FnOnce() -> Call) {
+    // Thread locals are unavailable while the thread exits, nothing is recorded anymore then
+    let _ = UNTRACKED.try_with(|untracked| {
+        if untracked.get() {
+            return;
+        }
+        untracked.set(true);
+        let _ = RECORDING.try_with(|recording| {
+            if let Ok(mut recording) = recording.try_borrow_mut()
+                && let Some(calls) = recording.as_mut()
+            {
+                calls.push(call());
+            }
+        });
+        untracked.set(false);
+    });
+}
+
+/// Create a new handle for a module, stream or event.
+fn new_handle<T>() -> *mut T {
+    ptr::without_provenance_mut(LAST_HANDLE.fetch_add(1, Ordering::Relaxed) + 1)
+}
+
+fn check_device(device: c_int) -> hipError_t {
+    if device < 0 || device as u32 >= DEVICE_COUNT.load(Ordering::Relaxed) {
+        return hipErrorInvalidDevice;
+    }
+    hipSuccess
+}
+
+/// Allocate memory that can be freed with [`deallocate`].
+unsafe fn allocate(ptr: *mut *mut c_void, size: usize) -> hipError_t {
+    let Some(layout) = size
+        .checked_add(ALIGNMENT)
+        .and_then(|total| Layout::from_size_align(total, ALIGNMENT).ok())
+    else {
+        return hipErrorOutOfMemory;
+    };
+    unsafe {
+        let base = System.alloc(layout);
+        if base.is_null() {
+            return hipErrorOutOfMemory;
+        }
+        base.cast::<usize>().write(size);
+        *ptr = base.add(ALIGNMENT).cast();
+    }
+    hipSuccess
+}
+
+/// Free memory allocated with [`allocate`].
+unsafe fn deallocate(ptr: *mut c_void) {
+    unsafe {
+        let base = ptr.cast::<u8>().sub(ALIGNMENT);
+        let size = base.cast::<usize>().read();
+        let layout = Layout::from_size_align_unchecked(size + ALIGNMENT, ALIGNMENT);
+        System.dealloc(base, layout);
+    }
+}
+
+/// Copy memory, the mock keeps all memory on the CPU.
+unsafe fn copy(dst: *mut c_void, src: *const c_void, size: usize) -> hipError_t {
+    unsafe { ptr::copy(src.cast::<u8>(), dst.cast::<u8>(), size) };
+    push(|| Call::Memcpy {
+        dst: dst.addr(),
+        src: src.addr(),
+        size,
+    });
+    hipSuccess
+}
+
+/// Copy the kernel arguments from the `extra` argument of `hipModuleLaunchKernel`.
+unsafe fn kernel_args(mut extra: *mut *mut c_void) -> Vec<u8> {
+    let mut args = ptr::null::<u8>();
+    let mut size = 0;
+    unsafe {
+        loop {
+            match (*extra).addr() {
+                // HIP_LAUNCH_PARAM_BUFFER_POINTER
+                0x1 => args = (*extra.add(1)).cast(),
+                // HIP_LAUNCH_PARAM_BUFFER_SIZE
+                0x2 => size = *(*extra.add(1)).cast::<usize>(),
+                // HIP_LAUNCH_PARAM_END
+                _ => break,
+            }
+            extra = extra.add(2);
+        }
+        if args.is_null() {
+            return Vec::new();
+        }
+        std::slice::from_raw_parts(args, size).to_vec()
+    }
+}
+
+/// Copy a string into a nul-terminated runtime string.
+fn copy_str(dst: &mut [c_char], s: &str) {
+    // Keep the last byte for the nul terminator
+    let len = dst.len() - 1;
+    for (d, b) in dst[..len].iter_mut().zip(s.bytes()) {
+        *d = b as c_char;
+    }
+}
+
+impl Runtime for Mock {
+    unsafe fn malloc_managed(&self, ptr: *mut *mut c_void, size: usize, _: c_uint) -> hipError_t {
+        let result = unsafe { allocate(ptr, size) };
+        if result == hipSuccess {
+            push(|| Call::Malloc {
+                ptr: unsafe { *ptr }.addr(),
+                size,
+                managed: true,
+            });
+        }
+        result
+    }
+
+    unsafe fn malloc(&self, ptr: *mut *mut c_void, size: usize) -> hipError_t {
+        let result = unsafe { allocate(ptr, size) };
+        if result == hipSuccess {
+            push(|| Call::Malloc {
+                ptr: unsafe { *ptr }.addr(),
+                size,
+                managed: false,
+            });
+        }
+        result
+    }
+
+    unsafe fn free(&self, ptr: *mut c_void) -> hipError_t {
+        if ptr.is_null() {
+            return hipSuccess;
+        }
+        push(|| Call::Free { ptr: ptr.addr() });
+        unsafe { deallocate(ptr) };
+        hipSuccess
+    }
+
+    unsafe fn host_malloc(&self, ptr: *mut *mut c_void, size: usize, _: c_uint) -> hipError_t {
+        let result = unsafe { allocate(ptr, size) };
+        if result == hipSuccess {
+            push(|| Call::HostMalloc {
+                ptr: unsafe { *ptr }.addr(),
+                size,
+            });
+        }
+        result
+    }
+
+    unsafe fn host_free(&self, ptr: *mut c_void) -> hipError_t {
+        if ptr.is_null() {
+            return hipSuccess;
+        }
+        push(|| Call::HostFree { ptr: ptr.addr() });
+        unsafe { deallocate(ptr) };
+        hipSuccess
+    }
+
+    unsafe fn memcpy_dtoh(&self, dst: *mut c_void, src: hipDeviceptr_t, size: usize) -> hipError_t {
+        unsafe { copy(dst, src, size) }
+    }
+
+    unsafe fn memcpy_htod(&self, dst: hipDeviceptr_t, src: *mut c_void, size: usize) -> hipError_t {
+        unsafe { copy(dst, src, size) }
+    }
+
+    unsafe fn memcpy_dtod(
+        &self,
+        dst: hipDeviceptr_t,
+        src: hipDeviceptr_t,
+        size: usize,
+    ) -> hipError_t {
+        unsafe { copy(dst, src, size) }
+    }
+
+    unsafe fn memcpy_dtoh_async(
+        &self,
+        dst: *mut c_void,
+        src: hipDeviceptr_t,
+        size: usize,
+        _: hipStream_t,
+    ) -> hipError_t {
+        // Copies finish right away, like kernels
+        unsafe { copy(dst, src, size) }
+    }
+
+    unsafe fn module_load_data(&self, module: *mut hipModule_t, _: *const c_void) -> hipError_t {
+        let handle = new_handle();
+        unsafe { *module = handle };
+        push(|| Call::ModuleLoad {
+            module: handle.addr(),
+        });
+        hipSuccess
+    }
+
+    unsafe fn module_unload(&self, module: hipModule_t) -> hipError_t {
+        push(|| Call::ModuleUnload {
+            module: module.addr(),
+        });
+        hipSuccess
+    }
+
+    unsafe fn module_get_function(
+        &self,
+        function: *mut hipFunction_t,
+        _: hipModule_t,
+        name: *const c_char,
+    ) -> hipError_t {
+        // Every module contains every kernel, the handle is the leaked name of the kernel
+        let name = untracked(|| CString::from(unsafe { CStr::from_ptr(name) }).into_raw());
+        unsafe { *function = name.cast() };
+        hipSuccess
+    }
+
+    unsafe fn module_get_global(
+        &self,
+        _: *mut hipDeviceptr_t,
+        _: *mut usize,
+        _: hipModule_t,
+        _: *const c_char,
+    ) -> hipError_t {
+        // The mock does not know the sizes of global variables
+        hipErrorNotFound
+    }
+
+    unsafe fn module_launch_kernel(
+        &self,
+        function: hipFunction_t,
+        grid_x: c_uint,
+        grid_y: c_uint,
+        grid_z: c_uint,
+        block_x: c_uint,
+        block_y: c_uint,
+        block_z: c_uint,
+        shared_memory: c_uint,
+        stream: hipStream_t,
+        params: *mut *mut c_void,
+        extra: *mut *mut c_void,
+    ) -> hipError_t {
+        // Like HIP, only arguments passed through `extra` are supported
+        if !params.is_null() || extra.is_null() {
+            return hipErrorNotSupported;
+        }
+        push(|| Call::Launch {
+            kernel: unsafe { CStr::from_ptr(function.cast()) }
+                .to_string_lossy()
+                .into_owned(),
+            workgroups: [grid_x, grid_y, grid_z],
+            threads_per_workgroup: [block_x, block_y, block_z],
+            dynamic_shared_memory: shared_memory,
+            stream: stream.addr(),
+            args: unsafe { kernel_args(extra) },
+        });
+        hipSuccess
+    }
+
+    unsafe fn stream_create(&self, stream: *mut hipStream_t) -> hipError_t {
+        let handle = new_handle();
+        unsafe { *stream = handle };
+        push(|| Call::StreamCreate {
+            stream: handle.addr(),
+        });
+        hipSuccess
+    }
+
+    unsafe fn stream_destroy(&self, stream: hipStream_t) -> hipError_t {
+        push(|| Call::StreamDestroy {
+            stream: stream.addr(),
+        });
+        hipSuccess
+    }
+
+    unsafe fn stream_synchronize(&self, _: hipStream_t) -> hipError_t {
+        hipSuccess
+    }
+
+    unsafe fn event_create(&self, event: *mut hipEvent_t) -> hipError_t {
+        unsafe { self.event_create_with_flags(event, crate::hip::hipEventDefault) }
+    }
+
+    unsafe fn event_create_with_flags(&self, event: *mut hipEvent_t, flags: c_uint) -> hipError_t {
+        let handle = new_handle();
+        unsafe { *event = handle };
+        push(|| Call::EventCreate {
+            event: handle.addr(),
+            timing: flags & crate::hip::hipEventDisableTiming == 0,
+        });
+        hipSuccess
+    }
+
+    unsafe fn event_record(&self, event: hipEvent_t, stream: hipStream_t) -> hipError_t {
+        push(|| Call::EventRecord {
+            event: event.addr(),
+            stream: stream.addr(),
+        });
+        hipSuccess
+    }
+
+    unsafe fn event_destroy(&self, event: hipEvent_t) -> hipError_t {
+        push(|| Call::EventDestroy {
+            event: event.addr(),
+        });
+        hipSuccess
+    }
+
+    unsafe fn event_synchronize(&self, _: hipEvent_t) -> hipError_t {
+        hipSuccess
+    }
+
+    unsafe fn event_query(&self, _: hipEvent_t) -> hipError_t {
+        // Kernels finish right away
+        hipSuccess
+    }
+
+    unsafe fn event_elapsed_time(&self, ms: *mut f32, _: hipEvent_t, _: hipEvent_t) -> hipError_t {
+        unsafe { *ms = 0.0 };
+        hipSuccess
+    }
+
+    unsafe fn launch_host_func(
+        &self,
+        _: hipStream_t,
+        function: hipHostFn_t,
+        data: *mut c_void,
+    ) -> hipError_t {
+        // All work in the stream already finished
+        if let Some(function) = function {
+            unsafe { function(data) };
+        }
+        hipSuccess
+    }
+
+    unsafe fn get_device_count(&self, count: *mut c_int) -> hipError_t {
+        let devices = DEVICE_COUNT.load(Ordering::Relaxed);
+        unsafe { *count = devices as c_int };
+        if devices == 0 {
+            return hipErrorNoDevice;
+        }
+        hipSuccess
+    }
+
+    unsafe fn get_device(&self, device: *mut c_int) -> hipError_t {
+        if DEVICE_COUNT.load(Ordering::Relaxed) == 0 {
+            return hipErrorNoDevice;
+        }
+        unsafe { *device = CURRENT_DEVICE.get() };
+        hipSuccess
+    }
+
+    unsafe fn set_device(&self, device: c_int) -> hipError_t {
+        let result = check_device(device);
+        if result == hipSuccess {
+            CURRENT_DEVICE.set(device);
+        }
+        result
+    }
+
+    unsafe fn get_device_properties(
+        &self,
+        props: *mut hipDeviceProp_t,
+        device: c_int,
+    ) -> hipError_t {
+        let result = check_device(device);
+        if result != hipSuccess {
+            return result;
+        }
+        let props = unsafe {
+            props.write(std::mem::zeroed());
+            &mut *props
+        };
+        copy_str(&mut props.name, "Mock GPU");
+        copy_str(&mut props.gcnArchName, ARCH);
+        props.warpSize = 64;
+        props.multiProcessorCount = COMPUTE_UNITS;
+        props.maxThreadsPerMultiProcessor = MAX_THREADS_PER_COMPUTE_UNIT;
+        props.maxThreadsPerBlock = MAX_THREADS_PER_WORKGROUP;
+        props.maxThreadsDim = [MAX_THREADS_PER_WORKGROUP; 3];
+        props.maxGridSize = [c_int::MAX; 3];
+        props.sharedMemPerBlock = 64 * 1024;
+        props.totalGlobalMem = 64 << 30;
+        props.l2CacheSize = 8 << 20;
+        props.managedMemory = 1;
+        props.concurrentManagedAccess = 1;
+        hipSuccess
+    }
+
+    unsafe fn func_get_attribute(
+        &self,
+        value: *mut c_int,
+        attribute: hipFunction_attribute,
+        _: hipFunction_t,
+    ) -> hipError_t {
+        use hipFunction_attribute::*;
+
+        let result = match attribute {
+            HIP_FUNC_ATTRIBUTE_MAX_THREADS_PER_BLOCK => MAX_THREADS_PER_WORKGROUP,
+            _ => 0,
+        };
+        unsafe { *value = result };
+        hipSuccess
+    }
+
+    unsafe fn module_occupancy_max_potential_block_size(
+        &self,
+        grid_size: *mut c_int,
+        block_size: *mut c_int,
+        _: hipFunction_t,
+        _: usize,
+        block_size_limit: c_int,
+    ) -> hipError_t {
+        let mut size = 256;
+        if block_size_limit > 0 {
+            size = size.min(block_size_limit);
+        }
+        unsafe {
+            *block_size = size;
+            *grid_size = COMPUTE_UNITS * (MAX_THREADS_PER_COMPUTE_UNIT / size);
+        }
+        hipSuccess
+    }
+
+    unsafe fn module_occupancy_max_active_blocks_per_multiprocessor(
+        &self,
+        blocks: *mut c_int,
+        _: hipFunction_t,
+        block_size: c_int,
+        _: usize,
+    ) -> hipError_t {
+        if block_size <= 0 {
+            return hipErrorInvalidValue;
+        }
+        unsafe { *blocks = MAX_THREADS_PER_COMPUTE_UNIT / block_size };
+        hipSuccess
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{ArgLayout, DeviceBuffer, GpuAlloc, KernelArgLayout, LaunchConfig, Module, Stream};
+
+    const CODE_OBJECT: &[u8] = include_bytes!("../tests/data/add.co");
+
+    /// Record the calls of `f`, without heap allocations made through `amd-allocator`.
+    fn record_explicit<R>(f: impl FnOnce() -> R) -> (R, Vec<Call>) {
+        let (result, calls) = record(f);
+        let mut explicit = HashSet::new();
+        let calls = calls
+            .into_iter()
+            .filter(|call| match call {
+                Call::Malloc { ptr, managed, .. } => !managed && explicit.insert(*ptr),
+                Call::Free { ptr } => explicit.remove(ptr),
+                _ => true,
+            })
+            .collect();
+        (result, calls)
+    }
+
+    #[test]
+    fn launch() {
+        #[repr(C)]
+        struct Args {
+            out: *mut u32,
+            value: u32,
+        }
+
+        let module = Module::new(CODE_OBJECT);
+        let kernel = module.get_kernel("add");
+        let stream = Stream::new();
+        let mut launch_config = LaunchConfig::for_elements(100, 32);
+        launch_config.stream(&stream);
+        let layout = [
+            KernelArgLayout {
+                name: "out",
+                offset: 0,
+                size: 8,
+            },
+            KernelArgLayout {
+                name: "value",
+                offset: 8,
+                size: 4,
+            },
+        ];
+        let mut args = Args {
+            out: ptr::without_provenance_mut(0x1000),
+            value: 42,
+        };
+
+        let (result, calls) = record_explicit(|| {
+            let layout = ArgLayout::Declared(&layout);
+            unsafe { kernel.launch_async_impl(&launch_config, layout, &mut args) }?.wait()
+        });
+        result.unwrap();
+        let stream = stream.raw().addr();
+        let [
+            Call::EventCreate {
+                event,
+                timing: false,
+            },
+            Call::Launch {
+                kernel,
+                workgroups,
+                threads_per_workgroup,
+                dynamic_shared_memory: 0,
+                stream: launch_stream,
+                args,
+            },
+            Call::EventRecord {
+                event: record_event,
+                stream: record_stream,
+            },
+            Call::EventDestroy {
+                event: destroy_event,
+            },
+        ] = calls.as_slice()
+        else {
+            panic!("unexpected calls {calls:?}");
+        };
+        assert_eq!(kernel, "add");
+        assert_eq!(*workgroups, [4, 1, 1]);
+        assert_eq!(*threads_per_workgroup, [32, 1, 1]);
+        assert_eq!(
+            args[..12],
+            [0x1000usize.to_ne_bytes(), 42u64.to_ne_bytes()].concat()[..12]
+        );
+        assert_eq!([*launch_stream, *record_stream], [stream; 2]);
+        assert_eq!([*record_event, *destroy_event], [*event; 2]);
+    }
+
+    #[test]
+    fn launch_blocking() {
+        let module = Module::new(CODE_OBJECT);
+        let kernel = module.get_kernel("add");
+        let stream = Stream::new();
+        let mut launch_config = LaunchConfig::for_elements(100, 32);
+        launch_config.stream(&stream);
+        let mut args = [0u8; 12];
+
+        let (result, calls) = record_explicit(|| {
+            let layout = ArgLayout::Packed(args.len());
+            unsafe { kernel.launch_impl(&launch_config, layout, &mut args) }
+        });
+        result.unwrap();
+        // Blocks on the stream, without an event
+        let [
+            Call::Launch {
+                kernel,
+                stream: launch_stream,
+                ..
+            },
+        ] = calls.as_slice()
+        else {
+            panic!("unexpected calls {calls:?}");
+        };
+        assert_eq!(kernel, "add");
+        assert_eq!(*launch_stream, stream.raw().addr());
+    }
+
+    #[test]
+    fn device_buffer() {
+        let (data, calls) = record_explicit(|| DeviceBuffer::from_slice(&[1u32, 2, 3]).to_vec());
+        assert_eq!(data.unwrap(), [1, 2, 3]);
+        let [
+            Call::Malloc {
+                ptr,
+                size: 12,
+                managed: false,
+            },
+            Call::Memcpy {
+                dst: upload_dst,
+                size: 12,
+                ..
+            },
+            Call::Memcpy {
+                src: download_src,
+                size: 12,
+                ..
+            },
+            Call::Free { ptr: free_ptr },
+        ] = calls.as_slice()
+        else {
+            panic!("unexpected calls {calls:?}");
+        };
+        assert_eq!([*upload_dst, *download_src, *free_ptr], [*ptr; 3]);
+    }
+
+    #[test]
+    fn gpu_box() {
+        let (ptr, calls) = record_explicit(|| {
+            let gpu_box = Box::new_in(7u64, GpuAlloc);
+            assert_eq!(*gpu_box, 7);
+            (&raw const *gpu_box).addr()
+        });
+        assert_eq!(
+            calls,
+            [
+                Call::Malloc {
+                    ptr,
+                    size: 8,
+                    managed: false,
+                },
+                Call::Free { ptr },
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::arch::nvptx;
+use core::ffi::{c_char, c_void};
+
+/// Prints to the standard output.
+///
+/// Formats all arguments to [`format!`](alloc::format!).
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::nvptx::print(&$crate::nvptx::alloc::format!($($arg)*));
+    };
+}
+
+/// Prints to the standard output, with a newline.
+///
+/// Formats all arguments to [`format!`](alloc::format!) and appends a `\n`.
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => {
+        let mut s = $crate::nvptx::alloc::format!($($arg)*);
+        s.push('\n');
+        $crate::nvptx::print(&s);
+    };
+}
+
+/// Re-exported for use in print macros
+pub extern crate alloc;
+
+unsafe extern "C" {
+    #[link_name = "llvm.nvvm.read.ptx.sreg.dynamic_smem_size"]
+    fn dynamic_smem_size() -> u32;
+
+    // Provided by the CUDA driver when loading the PTX module
+    fn vprintf(format: *const c_char, args: *const c_void) -> i32;
+    fn malloc(size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+/// Describes the launch of the running kernel, like the HSA dispatch packet on AMD GPUs.
+///
+/// Get it with [`dispatch_packet`] inside a kernel.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DispatchPacket {
+    /// X dimension of a workgroup, in threads.
+    pub workgroup_size_x: u16,
+    /// Y dimension of a workgroup, in threads.
+    pub workgroup_size_y: u16,
+    /// Z dimension of a workgroup, in threads.
+    pub workgroup_size_z: u16,
+    /// X dimension of the whole launch, in threads.
+    pub grid_size_x: u32,
+    /// Y dimension of the whole launch, in threads.
+    pub grid_size_y: u32,
+    /// Z dimension of the whole launch, in threads.
+    pub grid_size_z: u32,
+    /// Size of dynamic shared memory per workgroup in bytes.
+    pub group_segment_size: u32,
+}
+
+/// The index of the thread in its workgroup in the x dimension.
+#[inline]
+pub fn workitem_id_x() -> u32 {
+    unsafe { nvptx::_thread_idx_x() as u32 }
+}
+
+/// The index of the thread in its workgroup in the y dimension.
+#[inline]
+pub fn workitem_id_y() -> u32 {
+    unsafe { nvptx::_thread_idx_y() as u32 }
+}
+
+/// The index of the thread in its workgroup in the z dimension.
+#[inline]
+pub fn workitem_id_z() -> u32 {
+    unsafe { nvptx::_thread_idx_z() as u32 }
+}
+
+/// The index of the workgroup in the x dimension.
+#[inline]
+pub fn workgroup_id_x() -> u32 {
+    unsafe { nvptx::_block_idx_x() as u32 }
+}
+
+/// The index of the workgroup in the y dimension.
+#[inline]
+pub fn workgroup_id_y() -> u32 {
+    unsafe { nvptx::_block_idx_y() as u32 }
+}
+
+/// The index of the workgroup in the z dimension.
+#[inline]
+pub fn workgroup_id_z() -> u32 {
+    unsafe { nvptx::_block_idx_z() as u32 }
+}
+
+/// Wait until all threads of the workgroup reached the barrier.
+#[inline]
+pub fn s_barrier() {
+    unsafe { nvptx::_syncthreads() }
+}
+
+/// Get the packet that describes the launch of the running kernel.
+///
+/// NVIDIA GPUs have no dispatch packet in memory, so it is read from special registers and
+/// returned by value.
+/// There is no `dispatch_ptr` like on AMD GPUs, as there is no packet to point to.
+#[inline]
+pub fn dispatch_packet() -> DispatchPacket {
+    unsafe {
+        let size = [
+            nvptx::_block_dim_x(),
+            nvptx::_block_dim_y(),
+            nvptx::_block_dim_z(),
+        ];
+        let grid = [
+            nvptx::_grid_dim_x(),
+            nvptx::_grid_dim_y(),
+            nvptx::_grid_dim_z(),
+        ];
+        DispatchPacket {
+            workgroup_size_x: size[0] as u16,
+            workgroup_size_y: size[1] as u16,
+            workgroup_size_z: size[2] as u16,
+            grid_size_x: (size[0] * grid[0]) as u32,
+            grid_size_y: (size[1] * grid[1]) as u32,
+            grid_size_z: (size[2] * grid[2]) as u32,
+            group_segment_size: dynamic_smem_size(),
+        }
+    }
+}
+
+/// Get the dynamically sized shared memory of this workgroup.
+///
+/// The size is set when launching the kernel, see `LaunchConfig::dynamic_shared_memory`.
+#[inline]
+pub fn dynamic_shared_memory<T>() -> *mut [T] {
+    let ptr = core::intrinsics::gpu::gpu_launch_sized_workgroup_mem::<T>();
+    // The driver places dynamic shared memory after the static part
+    let bytes = unsafe { dynamic_smem_size() } as usize;
+    let len = bytes.checked_div(size_of::<T>()).unwrap_or(0);
+    core::ptr::slice_from_raw_parts_mut(ptr, len)
+}
+
+/// The bare print function underlying the `print!` macros.
+///
+/// Sends a string to the host console using `vprintf` of the CUDA driver.
+#[inline]
+pub fn print(s: &str) {
+    /// Arguments of the format string, laid out like C varargs.
+    #[repr(C)]
+    struct Args {
+        len: i32,
+        ptr: *const u8,
+    }
+
+    let args = Args {
+        len: s.len().try_into().expect("String too long to print"),
+        ptr: s.as_ptr(),
+    };
+    unsafe {
+        vprintf(c"%.*s".as_ptr(), &args as *const Args as *const c_void);
+    }
+}
+
+/// Panic handler.
+///
+/// Prints the panic message and aborts the kernel.
+#[panic_handler]
+fn panic(panic_info: &core::panic::PanicInfo) -> ! {
+    // workgroup x thread y panicked at …
+    crate::println!(
+        "workgroup {},{},{} thread {},{},{} {panic_info}",
+        workgroup_id_x(),
+        workgroup_id_y(),
+        workgroup_id_z(),
+        workitem_id_x(),
+        workitem_id_y(),
+        workitem_id_z()
+    );
+
+    unsafe { nvptx::trap() }
+}
+
+/// The memory allocator, using the device-side `malloc` of the CUDA driver.
+///
+/// The heap size can be changed on the host with `cuCtxSetLimit`, it defaults to 8 MiB.
+pub struct Allocator;
+
+unsafe impl GlobalAlloc for Allocator {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // malloc returns memory aligned to 16 bytes
+        if layout.align() > 16 {
+            return core::ptr::null_mut();
+        }
+        unsafe { malloc(layout.size()) as *mut _ }
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, _: Layout) {
+        unsafe { free(ptr as *mut _) };
+    }
+}
+
+#[global_allocator]
+static HEAP: Allocator = Allocator;
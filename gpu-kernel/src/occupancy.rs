@@ -0,0 +1,170 @@
+#[cfg(feature = "nvidia")]
+use crate::cuda;
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use crate::error::check;
+#[cfg(feature = "hip")]
+use crate::runtime::runtime;
+use crate::{GpuError, Kernel, LaunchConfig, LazyKernel};
+
+/// A workgroup size that maximizes occupancy, see [`Kernel::suggested_workgroup_size`].
+///
+/// Occupancy is the number of threads that run concurrently on a compute unit.
+/// Higher occupancy helps to hide memory latency.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct OccupancySuggestion {
+    /// The number of threads per workgroup that reaches the maximum occupancy.
+    pub threads_per_workgroup: u32,
+    /// The minimum number of workgroups needed to reach the maximum occupancy on the whole
+    /// device.
+    pub min_workgroups: u32,
+}
+
+impl Kernel {
+    /// Get the workgroup size that maximizes occupancy for this kernel on its device.
+    ///
+    /// The suggestion takes the register and shared memory usage of the kernel into account.
+    /// `dynamic_shared_memory` is the dynamic shared memory per workgroup in bytes, see
+    /// [`LaunchConfig::dynamic_shared_memory`].
+    pub fn suggested_workgroup_size(
+        &self,
+        dynamic_shared_memory: u32,
+    ) -> Result<OccupancySuggestion, GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut min_workgroups = 0;
+            let mut threads_per_workgroup = 0;
+            let result = runtime().module_occupancy_max_potential_block_size(
+                &mut min_workgroups,
+                &mut threads_per_workgroup,
+                self.func,
+                dynamic_shared_memory as usize,
+                0, // No additional limit for the workgroup size
+            );
+            check(
+                result,
+                "hipModuleOccupancyMaxPotentialBlockSize",
+                Some(&self.name),
+            )?;
+            Ok(OccupancySuggestion {
+                threads_per_workgroup: threads_per_workgroup as u32,
+                min_workgroups: min_workgroups as u32,
+            })
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let mut min_workgroups = 0;
+            let mut threads_per_workgroup = 0;
+            let result = cuda::cuOccupancyMaxPotentialBlockSize(
+                &mut min_workgroups,
+                &mut threads_per_workgroup,
+                self.func,
+                None, // Same dynamic shared memory for every workgroup size
+                dynamic_shared_memory as usize,
+                0, // No additional limit for the workgroup size
+            );
+            check(result, "cuOccupancyMaxPotentialBlockSize", Some(&self.name))?;
+            Ok(OccupancySuggestion {
+                threads_per_workgroup: threads_per_workgroup as u32,
+                min_workgroups: min_workgroups as u32,
+            })
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        {
+            let _ = dynamic_shared_memory;
+            Err(GpuError::NoRuntime)
+        }
+    }
+
+    /// Get the maximum number of workgroups of this kernel that can run concurrently on a single
+    /// compute unit.
+    ///
+    /// `dynamic_shared_memory` is the dynamic shared memory per workgroup in bytes, see
+    /// [`LaunchConfig::dynamic_shared_memory`].
+    pub fn max_active_workgroups_per_compute_unit(
+        &self,
+        threads_per_workgroup: u32,
+        dynamic_shared_memory: u32,
+    ) -> Result<u32, GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut workgroups = 0;
+            let result = runtime().module_occupancy_max_active_blocks_per_multiprocessor(
+                &mut workgroups,
+                self.func,
+                threads_per_workgroup as i32,
+                dynamic_shared_memory as usize,
+            );
+            check(
+                result,
+                "hipModuleOccupancyMaxActiveBlocksPerMultiprocessor",
+                Some(&self.name),
+            )?;
+            Ok(workgroups as u32)
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let mut workgroups = 0;
+            let result = cuda::cuOccupancyMaxActiveBlocksPerMultiprocessor(
+                &mut workgroups,
+                self.func,
+                threads_per_workgroup as i32,
+                dynamic_shared_memory as usize,
+            );
+            check(
+                result,
+                "cuOccupancyMaxActiveBlocksPerMultiprocessor",
+                Some(&self.name),
+            )?;
+            Ok(workgroups as u32)
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        {
+            let _ = (threads_per_workgroup, dynamic_shared_memory);
+            Err(GpuError::NoRuntime)
+        }
+    }
+}
+
+impl LaunchConfig {
+    /// Create a `LaunchConfig` that launches at least one thread for each of `elements`, with a
+    /// workgroup size that maximizes occupancy of the kernel on the current device.
+    ///
+    /// Like [`Self::for_elements`], the number of workgroups is rounded up, so kernels need to
+    /// skip threads beyond `elements`.
+    ///
+    /// Panics if querying the kernel fails, see [`Self::try_auto_for`] for a non-panicking
+    /// variant.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use gpu_kernel::{LaunchConfig, kernel};
+    ///
+    /// gpu_kernel::kernel_lib!();
+    ///
+    /// #[kernel]
+    /// fn double(a: &[u32], b: *mut u32) {
+    ///     // …
+    /// }
+    ///
+    /// # fn main() {
+    /// let a = vec![1; 1000];
+    /// let mut b = vec![0; 1000];
+    /// let launch_config = LaunchConfig::auto_for(&double, a.len());
+    /// double.launch(&launch_config, &a, b.as_mut_ptr());
+    /// # }
+    /// ```
+    pub fn auto_for(kernel: &LazyKernel, elements: usize) -> Self {
+        Self::try_auto_for(kernel, elements).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a `LaunchConfig` that launches at least one thread for each of `elements`, with a
+    /// workgroup size that maximizes occupancy of the kernel on the current device.
+    ///
+    /// See [`Self::auto_for`].
+    pub fn try_auto_for(kernel: &LazyKernel, elements: usize) -> Result<Self, GpuError> {
+        let suggestion = kernel.get_current()?.suggested_workgroup_size(0)?;
+        Self::try_for_elements(elements, suggestion.threads_per_workgroup)
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt;
+
+use crate::{ArgLayout, GpuError, LaunchConfig, LazyKernel};
+
+/// All kernels in the executable, filled by the `#[kernel]` macro.
+#[doc(hidden)]
+#[linkme::distributed_slice]
+pub static KERNELS: [KernelDescriptor];
+
+/// Get all kernels declared with `#[kernel]` in the executable and its dependencies.
+///
+/// The order of the kernels is unspecified.
+///
+/// # Example
+///
+/// ```no_run
+/// for descriptor in gpu_kernel::kernels() {
+///     let args: Vec<_> = descriptor
+///         .args
+///         .iter()
+///         .map(|arg| format!("{}: {}", arg.name, arg.ty))
+///         .collect();
+///     println!(
+///         "{}::{}({}) at {}:{}",
+///         descriptor.module_path,
+///         descriptor.name,
+///         args.join(", "),
+///         descriptor.file,
+///         descriptor.line
+///     );
+/// }
+/// ```
+pub fn kernels() -> &'static [KernelDescriptor] {
+    &KERNELS
+}
+
+/// Describes a kernel declared with `#[kernel]`.
+///
+/// Get all kernels with [`kernels`].
+pub struct KernelDescriptor {
+    /// The name of the kernel function.
+    pub name: &'static str,
+    /// The module the kernel is declared in, e.g. `my_crate::kernels`.
+    pub module_path: &'static str,
+    /// The arguments of the kernel function.
+    pub args: &'static [KernelArgDescriptor],
+    /// If the kernel function is `unsafe`.
+    pub is_unsafe: bool,
+    /// The source file that declares the kernel.
+    pub file: &'static str,
+    /// The line of the kernel function name in [`Self::file`], starting at 1.
+    pub line: u32,
+    /// The column of the kernel function name in [`Self::file`], starting at 1.
+    pub column: u32,
+    /// The kernel, to load and launch it.
+    pub kernel: &'static dyn KernelFn,
+}
+
+/// Describes an argument of a kernel.
+#[derive(Clone, Copy, Debug)]
+pub struct KernelArgDescriptor {
+    /// The name of the argument.
+    pub name: &'static str,
+    /// The type of the argument as written in the source, e.g. `&[u32]`.
+    pub ty: &'static str,
+}
+
+/// A kernel declared with `#[kernel]`, implemented by the generated kernel statics.
+///
+/// Allows to store kernels with different arguments together and launch them dynamically.
+pub trait KernelFn: Sync {
+    /// Describe the kernel.
+    fn descriptor(&self) -> &'static KernelDescriptor;
+
+    /// Get the kernel that is loaded on first use, e.g. to load it or to query its attributes.
+    fn lazy_kernel(&self) -> &LazyKernel;
+
+    /// Launch the kernel with arguments that are packed into bytes and wait for it to finish.
+    ///
+    /// The size of the arguments is checked against the metadata of the kernel if available.
+    ///
+    /// # Safety
+    ///
+    /// `args` must contain the arguments expected by the kernel, packed as a `#[repr(C)]` struct
+    /// of the argument types, and all data referenced by the arguments must be valid on the GPU.
+    unsafe fn launch_packed(
+        &self,
+        launch_config: &LaunchConfig,
+        args: &mut [u8],
+    ) -> Result<(), GpuError> {
+        let layout = ArgLayout::Packed(args.len());
+        unsafe { self.lazy_kernel().launch_impl(launch_config, layout, args) }
+    }
+}
+
+impl fmt::Debug for KernelDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KernelDescriptor")
+            .field("name", &self.name)
+            .field("module_path", &self.module_path)
+            .field("args", &self.args)
+            .field("is_unsafe", &self.is_unsafe)
+            .field("file", &self.file)
+            .field("line", &self.line)
+            .field("column", &self.column)
+            .finish_non_exhaustive()
+    }
+}
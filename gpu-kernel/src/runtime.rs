@@ -0,0 +1,132 @@
+use std::ffi::{c_char, c_int, c_uint, c_void};
+
+use crate::hip::{
+    hipDeviceProp_t, hipDeviceptr_t, hipError_t, hipEvent_t, hipFunction_attribute, hipFunction_t,
+    hipHostFn_t, hipModule_t, hipStream_t,
+};
+
+/// Declare the [`Runtime`] trait and implement it for [`Hip`] by forwarding to the HIP function
+/// with the given name.
+macro_rules! runtime {
+    ($(fn $name:ident = $hip:ident($($arg:ident: $ty:ty),* $(,)?);)*) => {
+        /// The HIP functions used by gpu-kernel.
+        ///
+        /// All calls into the GPU runtime go through [`runtime`], so the `mock` feature can
+        /// replace the HIP library with a mock that records the calls.
+        ///
+        /// Every method has the same arguments and safety requirements as the HIP function it
+        /// mirrors.
+        #[allow(clippy::too_many_arguments)]
+        pub(crate) trait Runtime: Sync {
+            $(unsafe fn $name(&self, $($arg: $ty),*) -> hipError_t;)*
+        }
+
+        #[cfg(not(feature = "mock"))]
+        impl Runtime for Hip {
+            $(
+                #[inline]
+                unsafe fn $name(&self, $($arg: $ty),*) -> hipError_t {
+                    unsafe { hip_runtime_sys::$hip($($arg),*) }
+                }
+            )*
+        }
+    };
+}
+
+runtime! {
+    fn malloc_managed = hipMallocManaged(ptr: *mut *mut c_void, size: usize, flags: c_uint);
+    fn malloc = hipMalloc(ptr: *mut *mut c_void, size: usize);
+    fn free = hipFree(ptr: *mut c_void);
+    fn host_malloc = hipHostMalloc(ptr: *mut *mut c_void, size: usize, flags: c_uint);
+    fn host_free = hipHostFree(ptr: *mut c_void);
+    fn memcpy_dtoh = hipMemcpyDtoH(dst: *mut c_void, src: hipDeviceptr_t, size: usize);
+    fn memcpy_htod = hipMemcpyHtoD(dst: hipDeviceptr_t, src: *mut c_void, size: usize);
+    fn memcpy_dtod = hipMemcpyDtoD(dst: hipDeviceptr_t, src: hipDeviceptr_t, size: usize);
+    fn memcpy_dtoh_async = hipMemcpyDtoHAsync(
+        dst: *mut c_void,
+        src: hipDeviceptr_t,
+        size: usize,
+        stream: hipStream_t,
+    );
+    fn module_load_data = hipModuleLoadData(module: *mut hipModule_t, image: *const c_void);
+    fn module_unload = hipModuleUnload(module: hipModule_t);
+    fn module_get_function = hipModuleGetFunction(
+        function: *mut hipFunction_t,
+        module: hipModule_t,
+        name: *const c_char,
+    );
+    fn module_get_global = hipModuleGetGlobal(
+        ptr: *mut hipDeviceptr_t,
+        size: *mut usize,
+        module: hipModule_t,
+        name: *const c_char,
+    );
+    fn module_launch_kernel = hipModuleLaunchKernel(
+        function: hipFunction_t,
+        grid_x: c_uint,
+        grid_y: c_uint,
+        grid_z: c_uint,
+        block_x: c_uint,
+        block_y: c_uint,
+        block_z: c_uint,
+        shared_memory: c_uint,
+        stream: hipStream_t,
+        params: *mut *mut c_void,
+        extra: *mut *mut c_void,
+    );
+    fn stream_create = hipStreamCreate(stream: *mut hipStream_t);
+    fn stream_destroy = hipStreamDestroy(stream: hipStream_t);
+    fn stream_synchronize = hipStreamSynchronize(stream: hipStream_t);
+    fn event_create = hipEventCreate(event: *mut hipEvent_t);
+    fn event_create_with_flags = hipEventCreateWithFlags(event: *mut hipEvent_t, flags: c_uint);
+    fn event_record = hipEventRecord(event: hipEvent_t, stream: hipStream_t);
+    fn event_destroy = hipEventDestroy(event: hipEvent_t);
+    fn event_synchronize = hipEventSynchronize(event: hipEvent_t);
+    fn event_query = hipEventQuery(event: hipEvent_t);
+    fn event_elapsed_time = hipEventElapsedTime(ms: *mut f32, start: hipEvent_t, end: hipEvent_t);
+    fn launch_host_func = hipLaunchHostFunc(
+        stream: hipStream_t,
+        function: hipHostFn_t,
+        data: *mut c_void,
+    );
+    fn get_device_count = hipGetDeviceCount(count: *mut c_int);
+    fn get_device = hipGetDevice(device: *mut c_int);
+    fn set_device = hipSetDevice(device: c_int);
+    fn get_device_properties = hipGetDeviceProperties(props: *mut hipDeviceProp_t, device: c_int);
+    fn func_get_attribute = hipFuncGetAttribute(
+        value: *mut c_int,
+        attribute: hipFunction_attribute,
+        function: hipFunction_t,
+    );
+    fn module_occupancy_max_potential_block_size = hipModuleOccupancyMaxPotentialBlockSize(
+        grid_size: *mut c_int,
+        block_size: *mut c_int,
+        function: hipFunction_t,
+        dynamic_shared_memory: usize,
+        block_size_limit: c_int,
+    );
+    fn module_occupancy_max_active_blocks_per_multiprocessor =
+        hipModuleOccupancyMaxActiveBlocksPerMultiprocessor(
+            blocks: *mut c_int,
+            function: hipFunction_t,
+            block_size: c_int,
+            dynamic_shared_memory: usize,
+        );
+}
+
+/// The HIP library.
+#[cfg(not(feature = "mock"))]
+pub(crate) struct Hip;
+
+/// Get the runtime that all calls go through.
+#[inline]
+pub(crate) fn runtime() -> &'static dyn Runtime {
+    #[cfg(not(feature = "mock"))]
+    {
+        &Hip
+    }
+    #[cfg(feature = "mock")]
+    {
+        &crate::mock::Mock
+    }
+}
@@ -1,7 +1,18 @@
 use core::marker::PhantomData;
 
 #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
-use crate::{GpuBox, LaunchConfig};
+use crate::LaunchConfig;
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(feature = "cpu"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+use crate::{DeviceBuffer, DeviceSlice, DeviceSliceMut};
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+use crate::{GpuBox, PinnedBox, PinnedVec};
 
 #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
 macro_rules! safe_kernel_arg_impl {
@@ -60,7 +71,7 @@ pub unsafe trait SafeKernelArg {
 /// gpu_kernel::kernel_lib!();
 ///
 /// #[kernel]
-/// fn kernel(elem: ThreadIndexedSlice<'_, i32>) {
+/// fn kernel(mut elem: ThreadIndexedSlice<'_, i32>) {
 ///     // Every thread writes two into the element assigned to this thread
 ///     *elem.get_mut() = 2;
 /// }
@@ -111,8 +122,13 @@ unsafe impl<T> SafeKernelArg for *mut T {
 
 // SAFETY: When using the allocator, heap memory is visible to the GPU, so the
 // slice is readable if `T` has the same layout on the GPU.
+// Kernels running on the CPU can read all memory.
 #[cfg(all(
-    feature = "amd-allocator",
+    any(
+        feature = "amd-allocator",
+        feature = "nvidia-allocator",
+        feature = "cpu"
+    ),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a Vec<T> {
@@ -125,7 +141,11 @@ unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a Vec<T> {
 
 // SAFETY: See Vec<T>
 #[cfg(all(
-    feature = "amd-allocator",
+    any(
+        feature = "amd-allocator",
+        feature = "nvidia-allocator",
+        feature = "cpu"
+    ),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 unsafe impl<'a> SafeKernelArg for &'a String {
@@ -138,7 +158,11 @@ unsafe impl<'a> SafeKernelArg for &'a String {
 
 // SAFETY: See Vec<T>
 #[cfg(all(
-    feature = "amd-allocator",
+    any(
+        feature = "amd-allocator",
+        feature = "nvidia-allocator",
+        feature = "cpu"
+    ),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a Box<T> {
@@ -151,7 +175,11 @@ unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a Box<T> {
 
 // SAFETY: See Vec<T>
 #[cfg(all(
-    feature = "amd-allocator",
+    any(
+        feature = "amd-allocator",
+        feature = "nvidia-allocator",
+        feature = "cpu"
+    ),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a Box<[T]> {
@@ -163,7 +191,10 @@ unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a Box<[T]> {
 }
 
 // SAFETY: See Vec<T>
-#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
 unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a GpuBox<T> {
     type Output = &'a T;
 
@@ -173,7 +204,10 @@ unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a GpuBox<T> {
 }
 
 // SAFETY: See Vec<T>
-#[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
 unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a GpuBox<[T]> {
     type Output = &'a [T];
 
@@ -182,9 +216,82 @@ unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a GpuBox<[T]>
     }
 }
 
+// SAFETY: Pinned memory is visible to the GPU, see Vec<T>
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a PinnedBox<T> {
+    type Output = &'a T;
+
+    fn into_kernel_arg(self, _: &LaunchConfig) -> Self::Output {
+        self.as_ref()
+    }
+}
+
+// SAFETY: See PinnedBox<T>
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a PinnedBox<[T]> {
+    type Output = &'a [T];
+
+    fn into_kernel_arg(self, _: &LaunchConfig) -> Self::Output {
+        self.as_ref()
+    }
+}
+
+// SAFETY: See PinnedBox<T>
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a PinnedVec<T> {
+    type Output = &'a [T];
+
+    fn into_kernel_arg(self, _: &LaunchConfig) -> Self::Output {
+        self.as_slice()
+    }
+}
+
+// SAFETY: The buffer is allocated in GPU memory and borrowed for the launch.
+// The slice is only passed to the kernel and never read on the CPU.
+// With the `cpu` feature, kernels run on CPU threads that cannot access GPU memory.
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(feature = "cpu"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a DeviceBuffer<T> {
+    type Output = &'a [T];
+
+    fn into_kernel_arg(self, launch_config: &LaunchConfig) -> Self::Output {
+        self.as_slice().into_kernel_arg(launch_config)
+    }
+}
+
+// SAFETY: See DeviceBuffer<T>
+#[cfg(all(
+    any(feature = "hip", feature = "nvidia"),
+    not(feature = "cpu"),
+    not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
+))]
+unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for DeviceSlice<'a, T> {
+    type Output = &'a [T];
+
+    fn into_kernel_arg(self, _: &LaunchConfig) -> Self::Output {
+        unsafe { std::slice::from_raw_parts(self.as_ptr(), self.len()) }
+    }
+}
+
 // SAFETY: See Vec<T>
 #[cfg(all(
-    feature = "amd-allocator",
+    any(
+        feature = "amd-allocator",
+        feature = "nvidia-allocator",
+        feature = "cpu"
+    ),
     not(any(target_arch = "amdgpu", target_arch = "nvptx64"))
 ))]
 unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a std::sync::Arc<T> {
@@ -196,6 +303,7 @@ unsafe impl<'a, T: SafeKernelArg<Output = T>> SafeKernelArg for &'a std::sync::A
 }
 
 /// Implement SafeKernelArg<Output = ThreadIndexedSlice<T>> for a list type
+#[cfg(any(feature = "hip", feature = "nvidia", feature = "cpu"))]
 macro_rules! safe_kernel_arg_list_impl {
     ($ty:ty: $len:expr; $ptr:expr) => {
         #[cfg(not(any(target_arch = "amdgpu", target_arch = "nvptx64")))]
@@ -203,16 +311,17 @@ macro_rules! safe_kernel_arg_list_impl {
             type Output = ThreadIndexedSlice<'a, T>;
 
             fn into_kernel_arg(self, launch_config: &LaunchConfig) -> ThreadIndexedSlice<'a, T> {
-                // assert that vector is long enough for launched threads
+                // assert that vector is long enough for launched threads.
+                // Unset sizes are reported as an error when launching.
                 let launch_size = launch_config
                     .threads_per_workgroup
-                    .unwrap()
+                    .unwrap_or_default()
                     .iter()
                     .map(|i| *i as usize)
                     .product::<usize>()
                     * launch_config
                         .workgroups
-                        .unwrap()
+                        .unwrap_or_default()
                         .iter()
                         .map(|i| *i as usize)
                         .product::<usize>();
@@ -231,16 +340,34 @@ macro_rules! safe_kernel_arg_list_impl {
 }
 
 // SAFETY: See Vec<T>
-#[cfg(feature = "amd-allocator")]
+#[cfg(any(
+    feature = "amd-allocator",
+    feature = "nvidia-allocator",
+    feature = "cpu"
+))]
 safe_kernel_arg_list_impl!(Vec<T>: |v: &[_]| v.len(); |v: &mut [_]| v.as_mut_ptr());
-#[cfg(feature = "amd-allocator")]
+#[cfg(any(
+    feature = "amd-allocator",
+    feature = "nvidia-allocator",
+    feature = "cpu"
+))]
 safe_kernel_arg_list_impl!(Box<[T]>: |v: &[_]| v.len(); |v: &mut [_]| v.as_mut_ptr());
+#[cfg(any(feature = "hip", feature = "nvidia"))]
 safe_kernel_arg_list_impl!(GpuBox<[T]>: |v: &[_]| v.len(); |v: &mut [_]| v.as_mut_ptr());
-
-#[cfg(any(target_arch = "amdgpu", target_arch = "nvptx64"))]
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+safe_kernel_arg_list_impl!(PinnedBox<[T]>: |v: &[_]| v.len(); |v: &mut [_]| v.as_mut_ptr());
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+safe_kernel_arg_list_impl!(PinnedVec<T>: |v: &[_]| v.len(); |v: &mut [_]| v.as_mut_ptr());
+// SAFETY: See DeviceBuffer<T>
+#[cfg(all(any(feature = "hip", feature = "nvidia"), not(feature = "cpu")))]
+safe_kernel_arg_list_impl!(DeviceBuffer<T>: DeviceBuffer::len; DeviceBuffer::as_mut_ptr);
+#[cfg(all(any(feature = "hip", feature = "nvidia"), not(feature = "cpu")))]
+safe_kernel_arg_list_impl!(DeviceSliceMut<'_, T>: DeviceSliceMut::len; DeviceSliceMut::as_mut_ptr);
+
+#[cfg(any(target_arch = "amdgpu", target_arch = "nvptx64", feature = "cpu"))]
 fn thread_id() -> usize {
     use crate::intrinsics::*;
-    let dispatch = crate::intrinsics::dispatch_ptr();
+    let dispatch = crate::intrinsics::dispatch_packet();
 
     // Compute size as ((z * dimY) + y) * dimX + x
     let mut id =
@@ -272,13 +399,13 @@ impl<'a, T> ThreadIndexedSlice<'a, T> {
     }
 
     /// Get a reference to the element for the current thread index.
-    #[cfg(any(doc, target_arch = "amdgpu", target_arch = "nvptx64"))]
+    #[cfg(any(doc, target_arch = "amdgpu", target_arch = "nvptx64", feature = "cpu"))]
     pub fn get(&self) -> &T {
         unsafe { &*self.ptr.add(thread_id()) }
     }
 
     /// Get a mutable reference to the element for the current thread index.
-    #[cfg(any(doc, target_arch = "amdgpu", target_arch = "nvptx64"))]
+    #[cfg(any(doc, target_arch = "amdgpu", target_arch = "nvptx64", feature = "cpu"))]
     pub fn get_mut(&mut self) -> &mut T {
         unsafe { &mut *self.ptr.add(thread_id()) }
     }
@@ -0,0 +1,203 @@
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+#[cfg(feature = "nvidia")]
+use crate::cuda;
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+use crate::error::check;
+#[cfg(feature = "hip")]
+use crate::runtime::runtime;
+use crate::{Device, GpuError};
+
+/// A queue of work on the GPU.
+///
+/// Kernels that are launched on the same stream run one after another, kernels on different
+/// streams can run concurrently.
+/// Set the stream for a launch with [`LaunchConfig::stream`](crate::LaunchConfig::stream).
+/// If no stream is set, kernels are launched on a thread-local stream.
+///
+/// A stream belongs to the device it was created on, kernels launched on it run on that device.
+///
+/// `Stream` is a cheap handle that can be cloned, the stream is destroyed when the last clone is
+/// dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// # use gpu_kernel::{LaunchConfig, Stream};
+/// let stream = Stream::new();
+/// let launch_config = LaunchConfig::new()
+///     .workgroups([1, 1, 1])
+///     .threads_per_workgroup([32, 1, 1])
+///     .stream(&stream)
+///     .clone();
+/// // Launch kernels with `launch_config`…
+/// stream.synchronize().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Stream {
+    inner: Arc<StreamInner>,
+}
+
+struct StreamInner {
+    #[cfg(feature = "hip")]
+    stream: crate::hip::hipStream_t,
+    #[cfg(feature = "nvidia")]
+    stream: cuda::CUstream,
+    device: Device,
+}
+unsafe impl Send for StreamInner {}
+unsafe impl Sync for StreamInner {}
+
+#[cfg(any(feature = "hip", feature = "nvidia"))]
+thread_local! {
+    /// Thread-local streams to launch and wait for kernels, indexed by device.
+    ///
+    /// Created on first use.
+    static STREAMS: std::cell::RefCell<Vec<Option<Stream>>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+impl Stream {
+    /// Create a new stream on the current device.
+    ///
+    /// Panics if creating the stream fails, see [`Self::try_new`] for a non-panicking variant.
+    pub fn new() -> Self {
+        Self::try_new().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a new stream on the current device.
+    pub fn try_new() -> Result<Self, GpuError> {
+        let device = Device::current()?;
+        #[cfg(feature = "hip")]
+        unsafe {
+            let mut stream: crate::hip::hipStream_t = std::ptr::null_mut();
+            let result = runtime().stream_create(&mut stream);
+            check(result, "hipStreamCreate", None)?;
+            Ok(Self {
+                inner: Arc::new(StreamInner { stream, device }),
+            })
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let mut stream = std::ptr::null_mut();
+            check(cuda::cuStreamCreate(&mut stream, 0), "cuStreamCreate", None)?;
+            Ok(Self {
+                inner: Arc::new(StreamInner { stream, device }),
+            })
+        }
+        #[cfg(not(any(feature = "hip", feature = "nvidia")))]
+        {
+            let _ = device;
+            Err(GpuError::NoRuntime)
+        }
+    }
+
+    /// Create a new stream on the given device.
+    ///
+    /// Panics if creating the stream fails, see [`Self::try_new_on`] for a non-panicking variant.
+    pub fn new_on(device: Device) -> Self {
+        Self::try_new_on(device).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a new stream on the given device.
+    pub fn try_new_on(device: Device) -> Result<Self, GpuError> {
+        device.with_current(Self::try_new)
+    }
+
+    /// The device this stream belongs to.
+    pub fn device(&self) -> Device {
+        self.inner.device
+    }
+
+    /// Block until all work queued on this stream finished.
+    pub fn synchronize(&self) -> Result<(), GpuError> {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let result = runtime().stream_synchronize(self.inner.stream);
+            check(result, "hipStreamSynchronize", None)?;
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let result = cuda::cuStreamSynchronize(self.inner.stream);
+            check(result, "cuStreamSynchronize", None)?;
+        }
+        Ok(())
+    }
+
+    /// Get the raw HIP stream.
+    ///
+    /// The stream stays valid as long as this `Stream` or a clone of it is alive.
+    #[cfg(feature = "hip")]
+    pub fn raw(&self) -> crate::hip::hipStream_t {
+        self.inner.stream
+    }
+
+    /// Get the raw CUDA stream.
+    #[cfg(feature = "nvidia")]
+    pub(crate) fn raw(&self) -> cuda::CUstream {
+        self.inner.stream
+    }
+
+    /// Get the thread-local stream of a device that is used when no stream is set in the
+    /// `LaunchConfig`.
+    ///
+    /// The stream is created on first use.
+    #[cfg(any(feature = "hip", feature = "nvidia"))]
+    pub(crate) fn thread_local(device: Device) -> Result<Self, GpuError> {
+        STREAMS.with_borrow_mut(|streams| {
+            let index = device.index() as usize;
+            if streams.len() <= index {
+                streams.resize(index + 1, None);
+            }
+            if let Some(s) = &streams[index] {
+                return Ok(s.clone());
+            }
+            Ok(streams[index].insert(Self::try_new_on(device)?).clone())
+        })
+    }
+}
+
+impl Default for Stream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for Stream {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl Eq for Stream {}
+
+impl Hash for Stream {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.inner).hash(state);
+    }
+}
+
+impl std::fmt::Debug for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut s = f.debug_struct("Stream");
+        #[cfg(any(feature = "hip", feature = "nvidia"))]
+        s.field("stream", &self.inner.stream);
+        s.field("device", &self.inner.device);
+        s.finish()
+    }
+}
+
+impl Drop for StreamInner {
+    fn drop(&mut self) {
+        #[cfg(feature = "hip")]
+        unsafe {
+            let result = runtime().stream_destroy(self.stream);
+            assert_eq!(result, crate::hip::hipError_t::hipSuccess);
+        }
+        #[cfg(feature = "nvidia")]
+        unsafe {
+            let result = cuda::cuStreamDestroy(self.stream);
+            assert_eq!(result, cuda::CUresult::SUCCESS);
+        }
+    }
+}
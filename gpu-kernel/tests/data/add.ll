@@ -0,0 +1,9 @@
+; A minimal code object for parsing tests, built with
+;   llc -mcpu=gfx90a -filetype=obj add.ll -o add.o && ld.lld -shared add.o -o add.co
+
+target triple = "amdgcn-amd-amdhsa"
+
+define amdgpu_kernel void @add(i32 addrspace(1)* %out, i32 %value) {
+  store i32 %value, i32 addrspace(1)* %out
+  ret void
+}